@@ -20,14 +20,18 @@ use crate::Entity;
 use crate::ModificationTime;
 use crate::Source;
 
+/// Source reading configs from the files of a directory on disk, reporting
+/// every path as changed on each poll.
 #[derive(Debug)]
-pub(crate) struct FileSource {
+pub struct FileSource {
     directory: PathBuf,
     extension: Option<String>,
 }
 
 impl FileSource {
-    pub(crate) fn new(directory: PathBuf, extension: impl Into<Option<String>>) -> Self {
+    /// Read the config at `path` from `directory/path`, with `extension`
+    /// appended if given.
+    pub fn new(directory: PathBuf, extension: impl Into<Option<String>>) -> Self {
         Self {
             directory,
             extension: extension.into(),
@@ -9,6 +9,7 @@
  */
 
 use std::sync::Arc;
+use std::sync::Weak;
 
 use anyhow::Context;
 use anyhow::Result;
@@ -17,12 +18,13 @@ use serde::de::DeserializeOwned;
 use serde_json::from_str;
 use tokio::sync::watch::Receiver;
 
+use crate::refreshable_entities::DerivedConfigEntity;
 use crate::refreshable_entities::RegisteredConfigEntity;
+use crate::refreshable_entities::UpstreamListener;
 
 /// A configuration handle, with self-refresh and wait-on-update if obtained
 /// from a `ConfigStore`. If your type `T` implements `Default`, then this
 /// will implement `Default` using a fixed config matching `T`'s default
-#[derive(Clone)]
 pub struct ConfigHandle<T> {
     inner: ConfigHandleImpl<T>,
 }
@@ -58,14 +60,27 @@ impl<T> ConfigUpdateWatcher<T> {
 
 // Enums have all their variants public, which needlessly exposes implementation
 // details of the ConfigHandle, that is why this enum is wrapped in a struct.
-#[derive(Clone)]
 enum ConfigHandleImpl<T> {
     /// Config is obtained from a `ConfigStore`, and kept up to date
     Registered(Arc<RegisteredConfigEntity<T>>),
+    /// Config is computed from other handles, and recomputed when they change
+    Derived(Arc<DerivedConfigEntity<T>>),
     /// Config is fixed. Obtained via `from_json`, `default` etc
     Fixed(Arc<T>),
 }
 
+// Implemented manually, as deriving would require `T: Clone`
+impl<T> Clone for ConfigHandle<T> {
+    fn clone(&self) -> Self {
+        let inner = match &self.inner {
+            ConfigHandleImpl::Registered(handle) => ConfigHandleImpl::Registered(handle.clone()),
+            ConfigHandleImpl::Derived(handle) => ConfigHandleImpl::Derived(handle.clone()),
+            ConfigHandleImpl::Fixed(contents) => ConfigHandleImpl::Fixed(contents.clone()),
+        };
+        Self { inner }
+    }
+}
+
 impl<T> ConfigHandle<T>
 where
     T: Send + Sync + 'static,
@@ -76,6 +91,7 @@ where
     pub fn get(&self) -> Arc<T> {
         match &self.inner {
             ConfigHandleImpl::Registered(handle) => handle.get(),
+            ConfigHandleImpl::Derived(handle) => handle.get(),
             ConfigHandleImpl::Fixed(contents) => contents.clone(),
         }
     }
@@ -91,12 +107,137 @@ where
             ConfigHandleImpl::Registered(handle) => {
                 Ok(ConfigUpdateWatcher::new(handle.update_receiver()))
             }
+            ConfigHandleImpl::Derived(handle) => {
+                Ok(ConfigUpdateWatcher::new(handle.update_receiver()))
+            }
             ConfigHandleImpl::Fixed(_) => {
                 bail!("Config update watchers are not supported for static configs")
             }
         }
     }
 
+    /// Derive a handle whose config is computed by `f` from the config of
+    /// this handle. `f` is only called again when this config changes, and
+    /// watchers of the derived handle are notified of the new value. This is
+    /// useful to cache values that are expensive to build from a config,
+    /// e.g. compiled regexes or lookup tables. If `f` panics on a new config
+    /// the derived handle keeps its last value.
+    pub fn map<U>(&self, f: impl Fn(&T) -> U + Send + Sync + 'static) -> ConfigHandle<U>
+    where
+        U: Send + Sync + 'static,
+    {
+        self.try_map(move |config| Ok(f(config)))
+            .expect("infallible map failed")
+    }
+
+    /// Like `map`, but `f` may fail. An error on the current config is
+    /// returned, while an error on a later config keeps the last value of
+    /// the derived handle until `f` succeeds on a new config.
+    pub fn try_map<U>(
+        &self,
+        f: impl Fn(&T) -> Result<U> + Send + Sync + 'static,
+    ) -> Result<ConfigHandle<U>>
+    where
+        U: Send + Sync + 'static,
+    {
+        let upstream = self.clone();
+        let mut last = upstream.get();
+        let initial = f(&last)?;
+        let recompute = move || {
+            let current = upstream.get();
+            if Arc::ptr_eq(&current, &last) {
+                return None;
+            }
+            last = current;
+            f(&last).ok()
+        };
+        let entity = Arc::new(DerivedConfigEntity::new(initial, Box::new(recompute)));
+        let subscribed = self.add_dependent(ConfigHandle::listener(&entity));
+        Ok(ConfigHandle::from_derived(entity, subscribed))
+    }
+
+    /// Like `map`, but derives the config from this handle and `other`,
+    /// recomputing it when either of them changes. Handles derived from more
+    /// configs can be built by chaining `map2` calls.
+    pub fn map2<U, V>(
+        &self,
+        other: &ConfigHandle<U>,
+        f: impl Fn(&T, &U) -> V + Send + Sync + 'static,
+    ) -> ConfigHandle<V>
+    where
+        U: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        self.try_map2(other, move |config, other_config| {
+            Ok(f(config, other_config))
+        })
+        .expect("infallible map2 failed")
+    }
+
+    /// Like `map2`, but `f` may fail, see `try_map`.
+    pub fn try_map2<U, V>(
+        &self,
+        other: &ConfigHandle<U>,
+        f: impl Fn(&T, &U) -> Result<V> + Send + Sync + 'static,
+    ) -> Result<ConfigHandle<V>>
+    where
+        U: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let upstreams = (self.clone(), other.clone());
+        let mut last = (upstreams.0.get(), upstreams.1.get());
+        let initial = f(&last.0, &last.1)?;
+        let recompute = move || {
+            let current = (upstreams.0.get(), upstreams.1.get());
+            if Arc::ptr_eq(&current.0, &last.0) && Arc::ptr_eq(&current.1, &last.1) {
+                return None;
+            }
+            last = current;
+            f(&last.0, &last.1).ok()
+        };
+        let entity = Arc::new(DerivedConfigEntity::new(initial, Box::new(recompute)));
+        let subscribed = self.add_dependent(ConfigHandle::listener(&entity));
+        let other_subscribed = other.add_dependent(ConfigHandle::listener(&entity));
+        Ok(ConfigHandle::from_derived(
+            entity,
+            subscribed || other_subscribed,
+        ))
+    }
+
+    fn listener(entity: &Arc<DerivedConfigEntity<T>>) -> Weak<dyn UpstreamListener + Send + Sync> {
+        Arc::downgrade(entity) as Weak<dyn UpstreamListener + Send + Sync>
+    }
+
+    /// Build a derived handle from its entity, which is only kept if it was
+    /// `subscribed` to an upstream handle that can change.
+    fn from_derived(entity: Arc<DerivedConfigEntity<T>>, subscribed: bool) -> Self {
+        if !subscribed {
+            return Self {
+                inner: ConfigHandleImpl::Fixed(entity.get()),
+            };
+        }
+        // Catch up with changes that happened before the subscription
+        entity.upstream_changed();
+        Self {
+            inner: ConfigHandleImpl::Derived(entity),
+        }
+    }
+
+    /// Returns false if this config can't change
+    fn add_dependent(&self, listener: Weak<dyn UpstreamListener + Send + Sync>) -> bool {
+        match &self.inner {
+            ConfigHandleImpl::Registered(handle) => {
+                handle.add_dependent(listener);
+                true
+            }
+            ConfigHandleImpl::Derived(handle) => {
+                handle.add_dependent(listener);
+                true
+            }
+            ConfigHandleImpl::Fixed(_) => false,
+        }
+    }
+
     pub(crate) fn from_registered(registered: Arc<RegisteredConfigEntity<T>>) -> Self {
         Self {
             inner: ConfigHandleImpl::Registered(registered),
@@ -0,0 +1,426 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+//! A `Source` reading configs from a directory on disk, like `FileSource`,
+//! but using inotify to learn which files have changed instead of reporting
+//! every path on each poll.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::thread;
+
+use anyhow::Context;
+use anyhow::Result;
+use bytes::Bytes;
+use inotify::EventMask;
+use inotify::Events;
+use inotify::Inotify;
+use inotify::WatchDescriptor;
+use inotify::WatchMask;
+use inotify::Watches;
+
+use crate::ChangeNotifier;
+use crate::Entity;
+use crate::ModificationTime;
+use crate::Source;
+
+/// Events on a watched directory that may change what one of its entries
+/// resolves to. Renames and creations cover both atomic rename-into-place
+/// writes and symlink swaps, close-after-write covers in-place edits.
+const WATCH_MASK: WatchMask = WatchMask::CLOSE_WRITE
+    .union(WatchMask::MOVED_TO)
+    .union(WatchMask::MOVED_FROM)
+    .union(WatchMask::CREATE)
+    .union(WatchMask::DELETE)
+    .union(WatchMask::ATTRIB);
+
+/// Source reading configs from the files of a directory on disk, like
+/// `FileSource`, that only reports the paths whose files changed, and wakes
+/// the `ConfigStore` up as soon as they change.
+pub struct InotifySource {
+    directory: PathBuf,
+    extension: Option<String>,
+    shared: Arc<Shared>,
+    /// Closed when the source is dropped, which wakes up the watcher thread
+    /// so that it exits and releases the inotify instance.
+    _wake: UnixStream,
+}
+
+struct Shared {
+    state: Mutex<WatchState>,
+    notifiers: Mutex<Vec<ChangeNotifier>>,
+}
+
+struct WatchState {
+    watches: Watches,
+    /// Directories observed by each watch. A single watch may be reached
+    /// through several paths, e.g. a symlinked directory and its target.
+    watched_dirs: HashMap<WatchDescriptor, HashSet<PathBuf>>,
+    /// For each config path that was read, the on-disk paths whose
+    /// replacement or modification affects its contents: the file itself,
+    /// each intermediate directory below the config directory and the
+    /// canonical location of the file if it is reached through symlinks.
+    dependencies: HashMap<String, HashSet<PathBuf>>,
+    /// Config paths that changed since they were last reported.
+    changed: HashSet<String>,
+    /// Set if reading events failed, after which every path is reported as
+    /// changed, as `FileSource` does.
+    failed: bool,
+}
+
+impl InotifySource {
+    /// Read the config at `path` from `directory/path`, with `extension`
+    /// appended if given, watching the files read with inotify.
+    pub fn new(directory: PathBuf, extension: impl Into<Option<String>>) -> Result<Self> {
+        let inotify = Inotify::init().context("failed to initialize inotify")?;
+        let (wake, wake_watcher) = UnixStream::pair().context("failed to create wake socket")?;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(WatchState {
+                watches: inotify.watches(),
+                watched_dirs: HashMap::new(),
+                dependencies: HashMap::new(),
+                changed: HashSet::new(),
+                failed: false,
+            }),
+            notifiers: Mutex::new(Vec::new()),
+        });
+
+        thread::Builder::new()
+            .name("rust-cfgr-inotify".into())
+            .spawn({
+                let shared = Arc::downgrade(&shared);
+                move || watcher_thread(inotify, wake_watcher, shared)
+            })
+            .context("Can't spawn cached_config inotify watcher")?;
+
+        Ok(Self {
+            directory,
+            extension: extension.into(),
+            shared,
+            _wake: wake,
+        })
+    }
+
+    fn file_path(&self, path: &str) -> PathBuf {
+        let mut path_with_extension = path.to_owned();
+        if let Some(extension) = &self.extension {
+            path_with_extension.push_str(extension);
+        }
+        self.directory.join(path_with_extension)
+    }
+
+    /// Make sure that any change to what `file` resolves to is reported as
+    /// a change of `path`. This is redone on every read, so that watches
+    /// follow symlinks to their new targets after a swap.
+    fn track(&self, path: &str, file: &Path) {
+        let mut dependencies = HashSet::new();
+        let mut dirs = HashSet::new();
+
+        let mut current = file;
+        while let Some(parent) = current.parent() {
+            dependencies.insert(current.to_path_buf());
+            dirs.insert(parent.to_path_buf());
+            if parent == self.directory || !parent.starts_with(&self.directory) {
+                break;
+            }
+            current = parent;
+        }
+
+        if let Ok(canonical) = fs::canonicalize(file) {
+            if let Some(parent) = canonical.parent() {
+                dirs.insert(parent.to_path_buf());
+            }
+            dependencies.insert(canonical);
+        }
+
+        let mut state = self.shared.state.lock().expect("lock poisoned");
+        for dir in dirs {
+            // Directories that don't exist yet are picked up through their
+            // parent once they are created.
+            if let Ok(wd) = state.watches.add(&dir, WATCH_MASK) {
+                state.watched_dirs.entry(wd).or_default().insert(dir);
+            }
+        }
+        state.dependencies.insert(path.to_owned(), dependencies);
+    }
+}
+
+impl Shared {
+    /// Record the config paths affected by `events`, returning whether any
+    /// were found.
+    fn process_events(&self, events: Events<'_>) -> bool {
+        let mut state = self.state.lock().expect("lock poisoned");
+        let mut changed_files = Vec::new();
+
+        for event in events {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                let all_paths: Vec<_> = state.dependencies.keys().cloned().collect();
+                state.changed.extend(all_paths);
+                return true;
+            }
+            if event.mask.contains(EventMask::IGNORED) {
+                state.watched_dirs.remove(&event.wd);
+                continue;
+            }
+            if let (Some(name), Some(dirs)) = (event.name, state.watched_dirs.get(&event.wd)) {
+                changed_files.extend(dirs.iter().map(|dir| dir.join(name)));
+            }
+        }
+
+        let changed_paths: Vec<_> = state
+            .dependencies
+            .iter()
+            .filter(|(_, dependencies)| changed_files.iter().any(|f| dependencies.contains(f)))
+            .map(|(path, _)| path.clone())
+            .collect();
+        let any_changed = !changed_paths.is_empty();
+        state.changed.extend(changed_paths);
+        any_changed
+    }
+
+    fn notify(&self) {
+        let notifiers = self.notifiers.lock().expect("lock poisoned").clone();
+        for notifier in notifiers {
+            notifier();
+        }
+    }
+}
+
+/// Wait until either `inotify` has events to read or `wake` is closed,
+/// returning whether there are events to read.
+fn wait_for_events(inotify: &Inotify, wake: &UnixStream) -> std::io::Result<bool> {
+    let mut fds = [
+        libc::pollfd {
+            fd: inotify.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: wake.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    // SAFETY: `fds` is a valid array of pollfds for the duration of the call.
+    if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(fds[1].revents == 0)
+}
+
+fn watcher_thread(mut inotify: Inotify, wake: UnixStream, shared: Weak<Shared>) {
+    let mut buffer = [0; 4096];
+    loop {
+        let result = match wait_for_events(&inotify, &wake) {
+            Ok(true) => inotify.read_events(&mut buffer),
+            Ok(false) => return,
+            Err(e) => Err(e),
+        };
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        match result {
+            Ok(events) => {
+                if shared.process_events(events) {
+                    shared.notify();
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock) => {}
+            Err(_) => {
+                shared.state.lock().expect("lock poisoned").failed = true;
+                shared.notify();
+                return;
+            }
+        }
+    }
+}
+
+impl Source for InotifySource {
+    fn config_for_path(&self, path: &str) -> Result<Entity> {
+        let file_path = self.file_path(path);
+        self.track(path, &file_path);
+
+        let mut file = File::open(&file_path)
+            .with_context(|| format!("failed to open {}", file_path.to_string_lossy()))?;
+        let metadata = file.metadata()?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .with_context(|| format!("failed to read {}", file_path.to_string_lossy()))?;
+
+        // Replacing the file gives it a new inode and editing it in place
+        // bumps its modification time, so there's no need to compare the
+        // whole contents.
+        let version = format!(
+            "{}:{}:{}.{}",
+            metadata.ino(),
+            metadata.size(),
+            metadata.mtime(),
+            metadata.mtime_nsec()
+        );
+
+        Ok(Entity {
+            contents: Some(Bytes::from(contents)),
+            mod_time: ModificationTime::UnixTimestamp(metadata.mtime().try_into().unwrap_or(0)),
+            version,
+        })
+    }
+
+    fn paths_to_refresh<'a>(&self, paths: &mut dyn Iterator<Item = &'a str>) -> Vec<&'a str> {
+        let mut state = self.shared.state.lock().expect("lock poisoned");
+        if state.failed {
+            return paths.collect();
+        }
+        // Changes to paths that aren't registered yet are kept, as a client
+        // may be registering right after having read the old contents.
+        paths.filter(|path| state.changed.remove(*path)).collect()
+    }
+
+    fn register_change_notifier(&self, notifier: ChangeNotifier) {
+        self.shared
+            .notifiers
+            .lock()
+            .expect("lock poisoned")
+            .push(notifier);
+    }
+}
+
+impl fmt::Debug for InotifySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InotifySource")
+            .field("directory", &self.directory)
+            .field("extension", &self.extension)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::ConfigStore;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn write_atomically(path: &Path, contents: &str) {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents).unwrap();
+        fs::rename(&tmp, path).unwrap();
+    }
+
+    fn wait_for_refresh<'a>(source: &InotifySource, paths: &[&'a str]) -> Vec<&'a str> {
+        let start = Instant::now();
+        loop {
+            let to_refresh = source.paths_to_refresh(&mut paths.iter().copied());
+            if !to_refresh.is_empty() || start.elapsed() > TIMEOUT {
+                return to_refresh;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_only_changed_paths_are_refreshed() -> Result<()> {
+        let dir = TempDir::new()?;
+        fs::write(dir.path().join("a.json"), "1")?;
+        fs::write(dir.path().join("b.json"), "2")?;
+
+        let source = InotifySource::new(dir.path().to_path_buf(), ".json".to_owned())?;
+        let a = source.config_for_path("a")?;
+        source.config_for_path("b")?;
+        assert!(
+            source
+                .paths_to_refresh(&mut ["a", "b"].into_iter())
+                .is_empty()
+        );
+
+        write_atomically(&dir.path().join("a.json"), "11");
+        assert_eq!(wait_for_refresh(&source, &["a", "b"]), vec!["a"]);
+        assert!(
+            source
+                .paths_to_refresh(&mut ["a", "b"].into_iter())
+                .is_empty()
+        );
+
+        let new_a = source.config_for_path("a")?;
+        assert_eq!(new_a.contents, Some(Bytes::from("11")));
+        assert_ne!(new_a.version, a.version);
+        Ok(())
+    }
+
+    #[test]
+    fn test_symlink_swap() -> Result<()> {
+        let dir = TempDir::new()?;
+        for (version, value) in [("v1", "1"), ("v2", "2")] {
+            fs::create_dir(dir.path().join(version))?;
+            fs::write(dir.path().join(version).join("c.json"), value)?;
+        }
+        symlink(dir.path().join("v1"), dir.path().join("current"))?;
+
+        let source = InotifySource::new(dir.path().to_path_buf(), ".json".to_owned())?;
+        assert_eq!(
+            source.config_for_path("current/c")?.contents,
+            Some(Bytes::from("1"))
+        );
+
+        symlink(dir.path().join("v2"), dir.path().join("next"))?;
+        fs::rename(dir.path().join("next"), dir.path().join("current"))?;
+        assert_eq!(wait_for_refresh(&source, &["current/c"]), vec!["current/c"]);
+        assert_eq!(
+            source.config_for_path("current/c")?.contents,
+            Some(Bytes::from("2"))
+        );
+
+        // The old target is no longer relevant, but the new one is.
+        fs::write(dir.path().join("v2").join("c.json"), "22")?;
+        assert_eq!(wait_for_refresh(&source, &["current/c"]), vec!["current/c"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_is_woken_up() -> Result<()> {
+        let dir = TempDir::new()?;
+        fs::write(dir.path().join("d"), "1")?;
+
+        let store = ConfigStore::inotify(
+            None,
+            dir.path().to_path_buf(),
+            None,
+            Duration::from_secs(3600),
+        )?;
+        let handle = store.get_raw_config_handle("d".to_owned())?;
+        assert_eq!(*handle.get(), "1");
+
+        write_atomically(&dir.path().join("d"), "2");
+        let start = Instant::now();
+        while *handle.get() != "2" && start.elapsed() < TIMEOUT {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*handle.get(), "2");
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+use std::collections::HashSet;
+use std::io;
+use std::sync::Arc;
+
+use anyhow::Error;
+use anyhow::Result;
+
+use crate::ChangeNotifier;
+use crate::ConfigNotFound;
+use crate::Entity;
+use crate::Source;
+
+/// Source combining an ordered list of named layers, e.g. local overrides on
+/// top of a deployment specific directory on top of checked-in defaults.
+///
+/// A config is served by the first layer that has it, so when a layer has no
+/// config for a path the next one is consulted. Any other error of a layer is
+/// returned as is, rather than serving the config from a layer with lower
+/// precedence. The name of the serving layer is part of the `Entity::version`,
+/// which makes handles switch to another layer as soon as a config appears or
+/// disappears in a layer with higher precedence.
+///
+/// Layers are usually directories, see `FileSource` and `InotifySource`.
+#[derive(Debug, Default)]
+pub struct LayeredSource {
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug)]
+struct Layer {
+    name: String,
+    source: Arc<dyn Source + Sync + Send>,
+}
+
+impl LayeredSource {
+    /// Create a `LayeredSource` without any layers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a layer with lower precedence than all the layers added before
+    pub fn with_layer(
+        mut self,
+        name: impl Into<String>,
+        source: Arc<dyn Source + Sync + Send>,
+    ) -> Self {
+        self.layers.push(Layer {
+            name: name.into(),
+            source,
+        });
+        self
+    }
+}
+
+impl Source for LayeredSource {
+    fn config_for_path(&self, path: &str) -> Result<Entity> {
+        let mut errors = Vec::new();
+        for layer in &self.layers {
+            match layer.source.config_for_path(path) {
+                Ok(entity) => {
+                    return Ok(Entity {
+                        version: format!("{}:{}", layer.name, entity.version),
+                        ..entity
+                    });
+                }
+                Err(e) if is_not_found(&e) => errors.push(format!("{}: {:#}", layer.name, e)),
+                Err(e) => return Err(e.context(format!("layer {}", layer.name))),
+            }
+        }
+        Err(Error::new(ConfigNotFound {
+            path: path.to_owned(),
+        })
+        .context(format!("Not present in any layer: [{}]", errors.join(", "))))
+    }
+
+    fn paths_to_refresh<'a>(&self, paths: &mut dyn Iterator<Item = &'a str>) -> Vec<&'a str> {
+        let paths: Vec<&'a str> = paths.collect();
+        // Every layer is asked, as sources may reset their state once they
+        // have reported a path.
+        let to_refresh: HashSet<&'a str> = self
+            .layers
+            .iter()
+            .flat_map(|layer| layer.source.paths_to_refresh(&mut paths.iter().copied()))
+            .collect();
+        paths
+            .into_iter()
+            .filter(|path| to_refresh.contains(path))
+            .collect()
+    }
+
+    fn register_change_notifier(&self, notifier: ChangeNotifier) {
+        for layer in &self.layers {
+            layer.source.register_change_notifier(notifier.clone());
+        }
+    }
+}
+
+fn is_not_found(e: &Error) -> bool {
+    e.chain().any(|cause| {
+        cause.is::<ConfigNotFound>()
+            || cause
+                .downcast_ref::<io::Error>()
+                .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ConfigStore;
+    use crate::FileSource;
+    use crate::ModificationTime;
+    use crate::TestSource;
+
+    #[test]
+    fn test_precedence() -> Result<()> {
+        let overrides = Arc::new(TestSource::new());
+        let defaults = Arc::new(TestSource::new());
+        defaults.insert_config("a", "default a", ModificationTime::UnixTimestamp(1));
+        defaults.insert_config("b", "default b", ModificationTime::UnixTimestamp(1));
+        overrides.insert_config("b", "override b", ModificationTime::UnixTimestamp(1));
+
+        let source = LayeredSource::new()
+            .with_layer("overrides", overrides)
+            .with_layer("defaults", defaults);
+
+        let a = source.config_for_path("a")?;
+        assert_eq!(a.contents.as_deref(), Some(b"default a".as_ref()));
+        assert_eq!(a.version, "defaults:");
+        let b = source.config_for_path("b")?;
+        assert_eq!(b.contents.as_deref(), Some(b"override b".as_ref()));
+        assert_eq!(b.version, "overrides:");
+        assert!(source.config_for_path("c").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors_are_not_skipped() -> Result<()> {
+        #[derive(Debug)]
+        struct BrokenSource;
+
+        impl Source for BrokenSource {
+            fn config_for_path(&self, _path: &str) -> Result<Entity> {
+                Err(io::Error::from(io::ErrorKind::PermissionDenied).into())
+            }
+
+            fn paths_to_refresh<'a>(
+                &self,
+                _paths: &mut dyn Iterator<Item = &'a str>,
+            ) -> Vec<&'a str> {
+                Vec::new()
+            }
+        }
+
+        let defaults = Arc::new(TestSource::new());
+        defaults.insert_config("a", "default a", ModificationTime::UnixTimestamp(1));
+
+        let source = LayeredSource::new()
+            .with_layer("overrides", Arc::new(TestSource::new()))
+            .with_layer("broken", Arc::new(BrokenSource))
+            .with_layer("defaults", defaults);
+        let err = source.config_for_path("a").unwrap_err();
+        assert!(!is_not_found(&err));
+        assert_eq!(format!("{:#}", err), "layer broken: permission denied");
+
+        let nested = LayeredSource::new().with_layer("empty", Arc::new(TestSource::new()));
+        let err = nested.config_for_path("a").unwrap_err();
+        assert!(is_not_found(&err));
+        Ok(())
+    }
+
+    #[test]
+    fn test_paths_to_refresh_are_merged() {
+        let first = Arc::new(TestSource::new());
+        let second = Arc::new(TestSource::new());
+        first.insert_to_refresh("b".to_owned());
+        second.insert_to_refresh("a".to_owned());
+        second.insert_to_refresh("b".to_owned());
+        second.insert_to_refresh("d".to_owned());
+
+        let source = LayeredSource::new()
+            .with_layer("first", first)
+            .with_layer("second", second);
+
+        assert_eq!(
+            source.paths_to_refresh(&mut ["a", "b", "c"].into_iter()),
+            vec!["a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_handle_switches_directory_layers() -> Result<()> {
+        let overrides = tempfile::tempdir()?;
+        let defaults = tempfile::tempdir()?;
+        std::fs::write(defaults.path().join("a.json"), "default")?;
+
+        let store = ConfigStore::new(
+            Arc::new(
+                LayeredSource::new()
+                    .with_layer(
+                        "overrides",
+                        Arc::new(FileSource::new(
+                            overrides.path().to_owned(),
+                            ".json".to_owned(),
+                        )),
+                    )
+                    .with_layer(
+                        "defaults",
+                        Arc::new(FileSource::new(
+                            defaults.path().to_owned(),
+                            ".json".to_owned(),
+                        )),
+                    ),
+            ),
+            None,
+            None,
+        );
+        let handle = store.get_raw_config_handle("a".to_owned())?;
+        assert_eq!(*handle.get(), "default");
+
+        std::fs::write(overrides.path().join("a.json"), "override")?;
+        store.force_update_configs();
+        assert_eq!(*handle.get(), "override");
+
+        std::fs::remove_file(overrides.path().join("a.json"))?;
+        store.force_update_configs();
+        assert_eq!(*handle.get(), "default");
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_switches_layers() -> Result<()> {
+        let overrides = Arc::new(TestSource::new());
+        let defaults = Arc::new(TestSource::new());
+        defaults.insert_config("a", "default", ModificationTime::UnixTimestamp(1));
+        for source in [&overrides, &defaults] {
+            source.insert_to_refresh("a".to_owned());
+        }
+
+        let store = ConfigStore::new(
+            Arc::new(
+                LayeredSource::new()
+                    .with_layer("overrides", overrides.clone())
+                    .with_layer("defaults", defaults),
+            ),
+            None,
+            None,
+        );
+        let handle = store.get_raw_config_handle("a".to_owned())?;
+        assert_eq!(*handle.get(), "default");
+
+        // Same modification time and version, only the layer differs.
+        overrides.insert_config("a", "override", ModificationTime::UnixTimestamp(1));
+        store.force_update_configs();
+        assert_eq!(*handle.get(), "override");
+
+        overrides.remove_config("a");
+        store.force_update_configs();
+        assert_eq!(*handle.get(), "default");
+        Ok(())
+    }
+}
@@ -18,6 +18,9 @@
 mod facebook;
 mod file_source;
 mod handle;
+#[cfg(target_os = "linux")]
+mod inotify_source;
+mod layered_source;
 #[cfg(not(fbcode_build))]
 mod oss;
 mod refreshable_entities;
@@ -27,26 +30,44 @@ pub mod test_source;
 #[cfg(test)]
 mod tests;
 
+use std::fmt;
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use anyhow::Result;
 use bytes::Bytes;
 use chrono::NaiveDateTime;
+pub use file_source::FileSource;
 pub use handle::ConfigHandle;
 pub use handle::ConfigUpdateWatcher;
+#[cfg(target_os = "linux")]
+pub use inotify_source::InotifySource;
+pub use layered_source::LayeredSource;
+pub use store::ConfigFormat;
+pub use store::ConfigStatus;
 pub use store::ConfigStore;
 pub use test_source::TestSource;
 
 /// Trait to be implemented by sources of configuration that the `ConfigStore`
 /// will use
 pub trait Source: Debug {
-    /// For a given path identifying the config return it's content
+    /// For a given path identifying the config return it's content. If there
+    /// is no config for the path the error should be a `ConfigNotFound` or an
+    /// `std::io::Error` of kind `NotFound`.
     fn config_for_path(&self, path: &str) -> Result<Entity>;
     /// Given a list of paths the client is interested in, return the ones that
     /// should be refreshed since the client last asked for them.
     fn paths_to_refresh<'a>(&self, paths: &mut dyn Iterator<Item = &'a str>) -> Vec<&'a str>;
+    /// Register a callback to be called when the source learns that some of
+    /// its configs might have changed, so that the `ConfigStore` refreshes
+    /// them without waiting for the next poll. Sources that can only be
+    /// polled don't need to implement this.
+    fn register_change_notifier(&self, _notifier: ChangeNotifier) {}
 }
 
+/// Callback used by a `Source` to wake up the `ConfigStore` updater.
+pub type ChangeNotifier = Arc<dyn Fn() + Send + Sync>;
+
 /// Represents a configuration Entity e.g. a JSON blob
 #[derive(Clone, Debug)]
 pub struct Entity {
@@ -59,6 +80,21 @@ pub struct Entity {
     pub version: String,
 }
 
+/// Error returned by a `Source` that has no config for the requested path.
+#[derive(Clone, Debug)]
+pub struct ConfigNotFound {
+    /// The requested path
+    pub path: String,
+}
+
+impl fmt::Display for ConfigNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Config not present for {:?}", self.path)
+    }
+}
+
+impl std::error::Error for ConfigNotFound {}
+
 /// Represents the last modification time of the given config.
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum ModificationTime {
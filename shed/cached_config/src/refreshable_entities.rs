@@ -8,9 +8,14 @@
  * above-listed licenses.
  */
 
+use std::panic;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::RwLock;
+use std::sync::Weak;
 
+use anyhow::Context;
 use anyhow::Result;
 use anyhow::bail;
 use bytes::Bytes;
@@ -28,13 +33,49 @@ pub(crate) trait Refreshable {
     fn refresh(&self, entity: Entity) -> Result<bool>;
 }
 
+/// Entities computed from other entities, which are told when the entities
+/// they depend on have a new value
+pub(crate) trait UpstreamListener {
+    fn upstream_changed(&self);
+}
+
+/// Listeners of an entity. They are held weakly, so that a derived entity
+/// stops being updated once all of its handles are gone.
+#[derive(Default)]
+pub(crate) struct Dependents(Mutex<Vec<Weak<dyn UpstreamListener + Send + Sync>>>);
+
+impl Dependents {
+    pub(crate) fn add(&self, listener: Weak<dyn UpstreamListener + Send + Sync>) {
+        self.0.lock().expect("lock poisoned").push(listener);
+    }
+
+    fn notify(&self) {
+        let listeners: Vec<_> = {
+            let mut listeners = self.0.lock().expect("lock poisoned");
+            listeners.retain(|listener| listener.strong_count() > 0);
+            listeners.iter().filter_map(Weak::upgrade).collect()
+        };
+        for listener in listeners {
+            listener.upstream_changed();
+        }
+    }
+}
+
+/// Check applied to every deserialized version of a config before it is made
+/// available to the `ConfigHandle`
+pub(crate) type Validator<T> = Box<dyn Fn(&T) -> Result<()> + Send + Sync>;
+
 /// The type contained in a `ConfigHandle` when it's obtained from a `ConfigStore`
 pub(crate) struct RegisteredConfigEntity<T> {
+    /// The last version that was accepted. A rejected version is processed
+    /// again on each refresh, until it is replaced by one that is accepted.
     contents: RwLock<CachedConfigEntity>,
     path: String,
     deserializer: fn(Bytes) -> Result<T>,
+    validator: Option<Validator<T>>,
     update_sender: RwLock<Sender<Arc<T>>>,
     update_receiver: RwLock<Receiver<Arc<T>>>,
+    dependents: Dependents,
 }
 
 struct CachedConfigEntity {
@@ -50,21 +91,30 @@ where
         path: String,
         entity: Entity,
         deserializer: fn(Bytes) -> Result<T>,
+        validator: Option<Validator<T>>,
     ) -> Result<Self> {
         let Entity {
             mod_time,
             version,
             contents,
         } = entity;
-        let contents = Arc::new(deserializer(contents.unwrap_or_else(Bytes::new))?);
+        let contents = deserializer(contents.unwrap_or_else(Bytes::new))
+            .with_context(|| format!("Failed to deserialize config at path {}", path))?;
+        if let Some(validator) = &validator {
+            validator(&contents)
+                .with_context(|| format!("Config at path {} failed validation", path))?;
+        }
+        let contents = Arc::new(contents);
         let (update_sender, update_receiver) = channel(contents);
 
         Ok(Self {
             contents: RwLock::new(CachedConfigEntity { mod_time, version }),
             path,
             deserializer,
+            validator,
             update_sender: RwLock::new(update_sender),
             update_receiver: RwLock::new(update_receiver),
+            dependents: Dependents::default(),
         })
     }
 
@@ -79,6 +129,10 @@ where
     pub(crate) fn update_receiver(&self) -> Receiver<Arc<T>> {
         self.update_receiver.read().expect("lock poisoned").clone()
     }
+
+    pub(crate) fn add_dependent(&self, listener: Weak<dyn UpstreamListener + Send + Sync>) {
+        self.dependents.add(listener)
+    }
 }
 
 impl<T> Refreshable for RegisteredConfigEntity<T>
@@ -96,13 +150,23 @@ where
         };
 
         if has_changed {
-            let contents = Arc::new((self.deserializer)(entity.contents.unwrap_or_default())?);
-            let update_sender = self.update_sender.write().expect("lock poisoned");
-            if update_sender.send(contents).is_err() {
-                bail!(
-                    "No subscriber for config updates at path {}",
-                    self.get_path()
-                )
+            let contents =
+                (self.deserializer)(entity.contents.unwrap_or_default()).with_context(|| {
+                    format!("Failed to deserialize config at path {}", self.get_path())
+                })?;
+            if let Some(validator) = &self.validator {
+                validator(&contents).with_context(|| {
+                    format!("Config at path {} failed validation", self.get_path())
+                })?;
+            }
+            {
+                let update_sender = self.update_sender.write().expect("lock poisoned");
+                if update_sender.send(Arc::new(contents)).is_err() {
+                    bail!(
+                        "No subscriber for config updates at path {}",
+                        self.get_path()
+                    )
+                }
             }
             {
                 let mut locked = self.contents.write().expect("lock poisoned");
@@ -110,10 +174,71 @@ where
                     mod_time: entity.mod_time,
                     version: entity.version,
                 };
-                Ok(true)
             }
+            self.dependents.notify();
+            Ok(true)
         } else {
             Ok(false)
         }
     }
 }
+
+/// The type contained in a `ConfigHandle` derived from other handles
+pub(crate) struct DerivedConfigEntity<T> {
+    /// Returns the new value if the values it's computed from have changed
+    /// and it could be computed
+    recompute: Mutex<Box<dyn FnMut() -> Option<T> + Send>>,
+    update_sender: Sender<Arc<T>>,
+    update_receiver: Receiver<Arc<T>>,
+    dependents: Dependents,
+}
+
+impl<T> DerivedConfigEntity<T>
+where
+    T: Send + Sync + 'static,
+{
+    pub(crate) fn new(initial: T, recompute: Box<dyn FnMut() -> Option<T> + Send>) -> Self {
+        let (update_sender, update_receiver) = channel(Arc::new(initial));
+        Self {
+            recompute: Mutex::new(recompute),
+            update_sender,
+            update_receiver,
+            dependents: Dependents::default(),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Arc<T> {
+        self.update_receiver.borrow().clone()
+    }
+
+    pub(crate) fn update_receiver(&self) -> Receiver<Arc<T>> {
+        self.update_receiver.clone()
+    }
+
+    pub(crate) fn add_dependent(&self, listener: Weak<dyn UpstreamListener + Send + Sync>) {
+        self.dependents.add(listener)
+    }
+}
+
+impl<T> UpstreamListener for DerivedConfigEntity<T>
+where
+    T: Send + Sync + 'static,
+{
+    fn upstream_changed(&self) {
+        let updated = {
+            let mut recompute = self.recompute.lock().expect("lock poisoned");
+            // This runs on the updater thread of the `ConfigStore`, so a
+            // panic keeps the last value rather than taking the thread down.
+            match panic::catch_unwind(AssertUnwindSafe(&mut *recompute)) {
+                Ok(Some(contents)) => {
+                    self.update_sender.send_replace(Arc::new(contents));
+                    true
+                }
+                Ok(None) | Err(_) => false,
+            }
+        };
+        if updated {
+            self.dependents.notify();
+        }
+    }
+}
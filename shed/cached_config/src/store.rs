@@ -11,6 +11,7 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::io::Cursor;
+use std::path::Path;
 use std::path::PathBuf;
 use std::str;
 use std::sync::Arc;
@@ -19,8 +20,10 @@ use std::sync::Mutex;
 use std::sync::Weak;
 use std::thread;
 use std::time::Duration;
+use std::time::SystemTime;
 
 use anyhow::Result;
+use anyhow::anyhow;
 use bytes::Bytes;
 use fbthrift::deserialize::Deserialize;
 use fbthrift::simplejson_protocol::SimpleJsonProtocolDeserializer;
@@ -29,11 +32,15 @@ use slog::Logger;
 use slog::info;
 use slog::warn;
 
+use crate::Entity;
 use crate::Source;
 use crate::file_source::FileSource;
 use crate::handle::ConfigHandle;
+#[cfg(target_os = "linux")]
+use crate::inotify_source::InotifySource;
 use crate::refreshable_entities::Refreshable;
 use crate::refreshable_entities::RegisteredConfigEntity;
+use crate::refreshable_entities::Validator;
 
 /// A wrapper around the configerator APIs to provide an easily mocked way of reading JSON configs
 /// into Serde-compatible structures.
@@ -42,17 +49,103 @@ pub struct ConfigStore {
     source: Arc<dyn Source + Sync + Send>,
     clients: Arc<Mutex<HashMap<String, ClientList>>>,
     kick: Arc<Condvar>,
+    wakeup: Arc<UpdaterWakeup>,
+    statuses: Arc<Mutex<HashMap<String, ConfigStatus>>>,
     logger: Option<Logger>,
 }
 
+/// Outcome of the refreshes of a config path registered in a `ConfigStore`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConfigStatus {
+    /// Error of the last version of the config that could not be fetched,
+    /// deserialized or validated. Cleared once a new version is accepted.
+    pub last_error: Option<String>,
+    /// Version of the config that was last accepted, as reported by the
+    /// `Source` in `Entity::version`
+    pub last_successful_version: Option<String>,
+    /// Time of the last attempt to refresh the config
+    pub last_attempt_time: Option<SystemTime>,
+}
+
 type ClientList = Vec<Weak<dyn Refreshable + Sync + Send>>;
 
+/// Serialization format of a config, for handles deserializing serde types
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// JSON, as in `get_config_handle_DEPRECATED`
+    Json,
+    /// TOML
+    Toml,
+    /// YAML
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Guess the format from the extension of `path`: `.json`, `.toml`,
+    /// `.yaml` or `.yml`
+    pub fn from_path(path: &str) -> Option<Self> {
+        match Path::new(path).extension()?.to_str()? {
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    fn deserializer<T: DeserializeOwned>(self) -> fn(Bytes) -> Result<T> {
+        fn deserialize_json<T: DeserializeOwned>(s: Bytes) -> Result<T> {
+            let v = serde_json::from_slice(&s)?;
+            Ok(v)
+        }
+        // TOML and YAML errors report the line and column of the failure
+        fn deserialize_toml<T: DeserializeOwned>(s: Bytes) -> Result<T> {
+            let s = str::from_utf8(&s)?;
+            let v = toml::from_str(s)?;
+            Ok(v)
+        }
+        fn deserialize_yaml<T: DeserializeOwned>(s: Bytes) -> Result<T> {
+            let v = serde_yaml_ng::from_slice(&s)?;
+            Ok(v)
+        }
+        match self {
+            Self::Json => deserialize_json,
+            Self::Toml => deserialize_toml,
+            Self::Yaml => deserialize_yaml,
+        }
+    }
+}
+
+/// Lets the source interrupt the sleep of the updating thread when it knows
+/// that some configs have changed.
+#[derive(Default)]
+struct UpdaterWakeup {
+    pending: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl UpdaterWakeup {
+    fn notify(&self) {
+        *self.pending.lock().expect("lock poisoned") = true;
+        self.condvar.notify_one();
+    }
+
+    fn wait(&self, timeout: Duration) {
+        let pending = self.pending.lock().expect("lock poisoned");
+        let (mut pending, _) = self
+            .condvar
+            .wait_timeout_while(pending, timeout, |pending| !*pending)
+            .expect("lock poisoned");
+        *pending = false;
+    }
+}
+
 impl ConfigStore {
     /// Create a new instance of the ConfigStore with its own updating thread
     /// which will be run every `poll_interval`. The configs will be retrieved
     /// from the provided `source`. If `logger` is given then the store will
     /// inform about status of refreshes. If `poll_interval` is None then no
-    /// updating thread will be spawned.
+    /// updating thread will be spawned. Sources that are able to tell when
+    /// their configs change may wake the updating thread up earlier.
     ///
     /// TODO: Each instance creates its own thread, make sure the thread is
     /// stopped once the ConfigStore and all relevant ConfigHandle are destroyed.
@@ -65,9 +158,16 @@ impl ConfigStore {
             source,
             clients: Arc::new(Mutex::new(HashMap::new())),
             kick: Arc::new(Condvar::new()),
+            wakeup: Arc::new(UpdaterWakeup::default()),
+            statuses: Arc::new(Mutex::new(HashMap::new())),
             logger: logger.into_option_logger(),
         };
 
+        this.source.register_change_notifier({
+            let wakeup = this.wakeup.clone();
+            Arc::new(move || wakeup.notify())
+        });
+
         if let Some(poll_interval) = poll_interval.into() {
             thread::Builder::new()
                 .name("rust-cfgr-updates".into())
@@ -99,6 +199,25 @@ impl ConfigStore {
         )
     }
 
+    /// Get configs from files on disk, like `file`, but use inotify to only
+    /// refresh the configs whose files changed, as soon as they change.
+    /// Atomic replacement of files and swaps of symlinks anywhere below
+    /// `directory` are detected as well.
+    /// `poll_interval` is the longest time between checks for config changes
+    #[cfg(target_os = "linux")]
+    pub fn inotify(
+        logger: impl crate::IntoOptionLogger,
+        directory: PathBuf,
+        extension: impl Into<Option<String>>,
+        poll_interval: impl Into<Option<Duration>>,
+    ) -> Result<Self> {
+        Ok(Self::new(
+            Arc::new(InotifySource::new(directory, extension)?),
+            poll_interval,
+            logger.into_option_logger(),
+        ))
+    }
+
     /// NOTE - this method uses json deserialization, but this is incorrect for configerator
     /// configs. For configerator configs thrift simple_json serialization should be used
     /// consider using `get_config_handle()` method below.
@@ -113,7 +232,26 @@ impl ConfigStore {
             let v = serde_json::from_slice(&s)?;
             Ok(v)
         }
-        self.get_config_handle_with_deserializer(path, deserialize_json)
+        self.get_config_handle_with_deserializer(path, deserialize_json, None)
+    }
+
+    /// Like `get_config_handle_DEPRECATED`, but new versions of the config
+    /// are only accepted if `validator` succeeds on them.
+    /// See `get_config_handle_with_validator` for details.
+    #[allow(non_snake_case)]
+    pub fn get_config_handle_DEPRECATED_with_validator<T>(
+        &self,
+        path: String,
+        validator: impl Fn(&T) -> Result<()> + Send + Sync + 'static,
+    ) -> Result<ConfigHandle<T>>
+    where
+        T: Send + Sync + DeserializeOwned + 'static,
+    {
+        fn deserialize_json<T: DeserializeOwned>(s: Bytes) -> Result<T> {
+            let v = serde_json::from_slice(&s)?;
+            Ok(v)
+        }
+        self.get_config_handle_with_deserializer(path, deserialize_json, Some(Box::new(validator)))
     }
 
     /// Fetch a self-updating config handle for the config at `path`.
@@ -130,7 +268,35 @@ impl ConfigStore {
             let v = fbthrift::simplejson_protocol::deserialize(s.as_ref())?;
             Ok(v)
         }
-        self.get_config_handle_with_deserializer(path, deserialize_thrift_simple_json)
+        self.get_config_handle_with_deserializer(path, deserialize_thrift_simple_json, None)
+    }
+
+    /// Fetch a self-updating config handle for the config at `path`, only
+    /// accepting versions of the config for which `validator` succeeds.
+    /// A version failing validation is logged and recorded in the
+    /// `config_status` of the path, and the handle keeps the last known good
+    /// config. Fails if the current version doesn't pass validation.
+    pub fn get_config_handle_with_validator<T>(
+        &self,
+        path: String,
+        validator: impl Fn(&T) -> Result<()> + Send + Sync + 'static,
+    ) -> Result<ConfigHandle<T>>
+    where
+        for<'a> T:
+            Send + Sync + Deserialize<SimpleJsonProtocolDeserializer<Cursor<&'a [u8]>>> + 'static,
+    {
+        fn deserialize_thrift_simple_json<T>(s: Bytes) -> Result<T>
+        where
+            for<'a> T: Deserialize<SimpleJsonProtocolDeserializer<Cursor<&'a [u8]>>>,
+        {
+            let v = fbthrift::simplejson_protocol::deserialize(s.as_ref())?;
+            Ok(v)
+        }
+        self.get_config_handle_with_deserializer(
+            path,
+            deserialize_thrift_simple_json,
+            Some(Box::new(validator)),
+        )
     }
 
     /// Fetch a self-updating config handle for the config at `path`, as a raw, non-deserialized
@@ -143,7 +309,78 @@ impl ConfigStore {
             let s = str::from_utf8(&s)?;
             Ok(s.to_owned())
         }
-        self.get_config_handle_with_deserializer(path, deserialize_raw)
+        self.get_config_handle_with_deserializer(path, deserialize_raw, None)
+    }
+
+    /// Like `get_raw_config_handle`, but new versions of the config are only
+    /// accepted if `validator` succeeds on them.
+    /// See `get_config_handle_with_validator` for details.
+    pub fn get_raw_config_handle_with_validator(
+        &self,
+        path: String,
+        validator: impl Fn(&String) -> Result<()> + Send + Sync + 'static,
+    ) -> Result<ConfigHandle<String>> {
+        fn deserialize_raw(s: Bytes) -> Result<String> {
+            let s = str::from_utf8(&s)?;
+            Ok(s.to_owned())
+        }
+        self.get_config_handle_with_deserializer(path, deserialize_raw, Some(Box::new(validator)))
+    }
+
+    /// Fetch a self-updating config handle for the TOML config at `path`.
+    /// See `ConfigHandle` for uses of this handle.
+    pub fn get_toml_config_handle<T>(&self, path: String) -> Result<ConfigHandle<T>>
+    where
+        T: Send + Sync + DeserializeOwned + 'static,
+    {
+        self.get_serde_config_handle_with_format(path, ConfigFormat::Toml)
+    }
+
+    /// Fetch a self-updating config handle for the YAML config at `path`.
+    /// See `ConfigHandle` for uses of this handle.
+    pub fn get_yaml_config_handle<T>(&self, path: String) -> Result<ConfigHandle<T>>
+    where
+        T: Send + Sync + DeserializeOwned + 'static,
+    {
+        self.get_serde_config_handle_with_format(path, ConfigFormat::Yaml)
+    }
+
+    /// Fetch a self-updating config handle for the config at `path`,
+    /// deserialized according to the extension of `path`, see
+    /// `ConfigFormat::from_path`. Note that the extension configured for
+    /// `ConfigStore::file` is not part of `path`, in which case use
+    /// `get_serde_config_handle_with_format` instead.
+    pub fn get_serde_config_handle<T>(&self, path: String) -> Result<ConfigHandle<T>>
+    where
+        T: Send + Sync + DeserializeOwned + 'static,
+    {
+        let format = ConfigFormat::from_path(&path)
+            .ok_or_else(|| anyhow!("Unknown config format for path {}", path))?;
+        self.get_serde_config_handle_with_format(path, format)
+    }
+
+    /// Fetch a self-updating config handle for the config at `path`,
+    /// deserialized from the given `format`.
+    /// See `ConfigHandle` for uses of this handle.
+    pub fn get_serde_config_handle_with_format<T>(
+        &self,
+        path: String,
+        format: ConfigFormat,
+    ) -> Result<ConfigHandle<T>>
+    where
+        T: Send + Sync + DeserializeOwned + 'static,
+    {
+        self.get_config_handle_with_deserializer(path, format.deserializer(), None)
+    }
+
+    /// Return the outcome of the refreshes of the config at `path`, or `None`
+    /// if there is no live handle for this path.
+    pub fn config_status(&self, path: &str) -> Option<ConfigStatus> {
+        self.statuses
+            .lock()
+            .expect("lock poisoned")
+            .get(path)
+            .cloned()
     }
 
     /// By default configs are updated once in `poll_interval`. Call this to force update them.
@@ -156,21 +393,35 @@ impl ConfigStore {
         &self,
         path: String,
         deserializer: fn(Bytes) -> Result<T>,
+        validator: Option<Validator<T>>,
     ) -> Result<ConfigHandle<T>>
     where
         T: Send + Sync + 'static,
     {
-        let entity = {
+        let (entity, version) = {
             let entity = self.source.config_for_path(&path)?;
-            Arc::new(RegisteredConfigEntity::new(
+            let version = entity.version.clone();
+            let entity = Arc::new(RegisteredConfigEntity::new(
                 path.clone(),
                 entity,
                 deserializer,
-            )?)
+                validator,
+            )?);
+            (entity, version)
         };
 
         let mut clients = self.clients.lock().expect("lock poisoned");
 
+        self.statuses
+            .lock()
+            .expect("lock poisoned")
+            .entry(path.clone())
+            .or_insert_with(|| ConfigStatus {
+                last_error: None,
+                last_successful_version: Some(version),
+                last_attempt_time: Some(SystemTime::now()),
+            });
+
         let client_handle = clients.entry(path).or_default();
         client_handle.push(Arc::downgrade(&entity) as Weak<dyn Refreshable + Send + Sync>);
 
@@ -179,13 +430,14 @@ impl ConfigStore {
         Ok(ConfigHandle::from_registered(entity))
     }
 
-    fn refresh_client(&self, client: Arc<dyn Refreshable + Sync + Send>) {
-        let res = self
-            .source
-            .config_for_path(client.get_path())
-            .and_then(|entity| client.refresh(entity));
+    fn refresh_client(
+        &self,
+        client: Arc<dyn Refreshable + Sync + Send>,
+        entity: Entity,
+    ) -> Result<bool> {
+        let res = client.refresh(entity);
         if let Some(ref logger) = self.logger {
-            match res {
+            match &res {
                 Ok(false) => {}
                 Ok(true) => info!(logger, "Updated path {}", client.get_path()),
                 Err(e) => warn!(
@@ -196,20 +448,58 @@ impl ConfigStore {
                 ),
             }
         }
+        res
+    }
+
+    fn refresh_client_list(&self, path: &str, client_list: &[Weak<dyn Refreshable + Sync + Send>]) {
+        let clients: Vec<_> = client_list.iter().filter_map(Weak::upgrade).collect();
+        if clients.is_empty() {
+            return;
+        }
+
+        let entity = match self.source.config_for_path(path) {
+            Ok(entity) => entity,
+            Err(e) => {
+                if let Some(ref logger) = self.logger {
+                    warn!(logger, "Failed to update path {} due to {:#?}", path, e);
+                }
+                self.record_attempt(path, Err(e));
+                return;
+            }
+        };
+
+        let version = entity.version.clone();
+        let mut res = Ok(false);
+        for client in clients {
+            match (self.refresh_client(client, entity.clone()), &mut res) {
+                (Ok(updated), Ok(any_updated)) => *any_updated |= updated,
+                (Err(e), Ok(_)) => res = Err(e),
+                (_, Err(_)) => {}
+            }
+        }
+        self.record_attempt(path, res.map(|updated| updated.then_some(version)));
     }
 
-    fn refresh_client_list(&self, client_list: &[Weak<dyn Refreshable + Sync + Send>]) {
-        for client in client_list {
-            if let Some(client) = client.upgrade() {
-                self.refresh_client(client);
+    /// Record the outcome of a refresh of `path` in its status, where
+    /// `Ok(Some(version))` means that a new version was accepted.
+    fn record_attempt(&self, path: &str, res: Result<Option<String>>) {
+        let mut statuses = self.statuses.lock().expect("lock poisoned");
+        let status = statuses.entry(path.to_owned()).or_default();
+        status.last_attempt_time = Some(SystemTime::now());
+        match res {
+            Ok(None) => {}
+            Ok(Some(version)) => {
+                status.last_error = None;
+                status.last_successful_version = Some(version);
             }
+            Err(e) => status.last_error = Some(format!("{:#}", e)),
         }
     }
 
     fn updater_thread(&self, poll_interval: Duration) {
         loop {
             self.updater_thread_iteration();
-            thread::sleep(poll_interval);
+            self.wakeup.wait(poll_interval);
         }
     }
 
@@ -228,7 +518,7 @@ impl ConfigStore {
             .paths_to_refresh(&mut clients.keys().map(|x| -> &str { x }))
         {
             if let Some(client_list) = clients.get(path) {
-                self.refresh_client_list(client_list);
+                self.refresh_client_list(path, client_list);
             }
         }
 
@@ -238,6 +528,10 @@ impl ConfigStore {
             client_list.retain(|client| client.upgrade().is_some());
             !client_list.is_empty()
         });
+        self.statuses
+            .lock()
+            .expect("lock poisoned")
+            .retain(|path, _| clients.contains_key(path));
     }
 }
 
@@ -14,9 +14,9 @@ use std::sync::Arc;
 use std::sync::Mutex;
 
 use anyhow::Result;
-use anyhow::anyhow;
 use bytes::Bytes;
 
+use crate::ConfigNotFound;
 use crate::Entity;
 use crate::ModificationTime;
 use crate::Source;
@@ -35,7 +35,12 @@ impl Source for TestSource {
             .expect("poisoned lock")
             .get(path)
             .cloned()
-            .ok_or_else(|| anyhow!("Config not present for {:?}", path))
+            .ok_or_else(|| {
+                ConfigNotFound {
+                    path: path.to_owned(),
+                }
+                .into()
+            })
     }
 
     fn paths_to_refresh<'a>(&self, paths: &mut dyn Iterator<Item = &'a str>) -> Vec<&'a str> {
@@ -55,17 +60,35 @@ impl TestSource {
 
     /// Insert config value into the `TestSource`, overwriting existing one
     pub fn insert_config(&self, key: &str, contents: &str, mod_time: ModificationTime) {
+        self.insert_config_with_version(key, contents, mod_time, "")
+    }
+
+    /// Insert config value with the given version into the `TestSource`,
+    /// overwriting existing one
+    pub fn insert_config_with_version(
+        &self,
+        key: &str,
+        contents: &str,
+        mod_time: ModificationTime,
+        version: &str,
+    ) {
         let mut map = self.path_to_config.lock().expect("poisoned lock");
         map.insert(
             key.to_owned(),
             Entity {
                 contents: Some(Bytes::copy_from_slice(contents.as_bytes())),
                 mod_time,
-                version: String::new(),
+                version: version.to_owned(),
             },
         );
     }
 
+    /// Remove config value from the `TestSource`
+    pub fn remove_config(&self, key: &str) {
+        let mut map = self.path_to_config.lock().expect("poisoned lock");
+        map.remove(key);
+    }
+
     /// Insert a new config path into a `to_refresh` set of `TestSource`
     pub fn insert_to_refresh(&self, path: String) {
         let mut to_refresh = self.to_refresh.lock().expect("poisoned lock");
@@ -340,3 +340,286 @@ fn test_config_update_watcher_for_static_configs() {
     // is not supported and results in an error.
     assert!(result.watcher().is_err());
 }
+
+#[test]
+fn test_config_validation() {
+    let test_source = Arc::new(TestSource::new());
+    test_source.insert_config_with_version(
+        "some",
+        r#"{ "value": 1 }"#,
+        ModificationTime::UnixTimestamp(1),
+        "v1",
+    );
+    test_source.insert_to_refresh("some".to_owned());
+
+    let store = ConfigStore::new(test_source.clone(), None, None);
+    let validate = |config: &TestConfig| {
+        if config.value < 0 {
+            anyhow::bail!("value must not be negative");
+        }
+        Ok(())
+    };
+    let handle = store
+        .get_config_handle_DEPRECATED_with_validator("some".to_owned(), validate)
+        .expect("Failed to get handle");
+    assert_eq!(*handle.get(), TestConfig { value: 1 });
+
+    let status = store.config_status("some").expect("No status for path");
+    assert_eq!(status.last_error, None);
+    assert!(status.last_attempt_time.is_some());
+
+    // A config that is rejected keeps the last known good config
+    test_source.insert_config_with_version(
+        "some",
+        r#"{ "value": -2 }"#,
+        ModificationTime::UnixTimestamp(2),
+        "v2",
+    );
+    store.force_update_configs();
+    assert_eq!(*handle.get(), TestConfig { value: 1 });
+    let status = store.config_status("some").expect("No status for path");
+    assert!(
+        status
+            .last_error
+            .as_deref()
+            .is_some_and(|e| e.contains("value must not be negative"))
+    );
+    assert_eq!(status.last_successful_version.as_deref(), Some("v1"));
+
+    // The rejected version is still reported on the next refresh
+    store.force_update_configs();
+    let status = store.config_status("some").expect("No status for path");
+    assert!(status.last_error.is_some());
+    assert!(
+        get_test_handle(&store, "some").is_ok(),
+        "handles without validator accept the config"
+    );
+    assert!(
+        store
+            .get_config_handle_DEPRECATED_with_validator("some".to_owned(), validate)
+            .is_err(),
+        "the current config is invalid"
+    );
+
+    test_source.insert_config_with_version(
+        "some",
+        r#"{ "value": 3 }"#,
+        ModificationTime::UnixTimestamp(3),
+        "v3",
+    );
+    store.force_update_configs();
+    assert_eq!(*handle.get(), TestConfig { value: 3 });
+    let status = store.config_status("some").expect("No status for path");
+    assert_eq!(status.last_error, None);
+    assert_eq!(status.last_successful_version.as_deref(), Some("v3"));
+
+    assert_eq!(store.config_status("other"), None);
+}
+
+#[test]
+fn test_toml_configs() {
+    let test_source = Arc::new(TestSource::new());
+    test_source.insert_config(
+        "some.toml",
+        "value = 1\n",
+        ModificationTime::UnixTimestamp(1),
+    );
+    test_source.insert_config(
+        "bad.toml",
+        "\nvalue = \"1\"\n",
+        ModificationTime::UnixTimestamp(1),
+    );
+
+    let store = ConfigStore::new(test_source, None, None);
+
+    let toml: ConfigHandle<TestConfig> = store
+        .get_toml_config_handle("some.toml".to_owned())
+        .expect("Failed to get TOML handle");
+    assert_eq!(*toml.get(), TestConfig { value: 1 });
+    let by_extension: ConfigHandle<TestConfig> = store
+        .get_serde_config_handle("some.toml".to_owned())
+        .expect("Failed to get handle by extension");
+    assert_eq!(*by_extension.get(), TestConfig { value: 1 });
+    assert!(
+        store
+            .get_serde_config_handle::<TestConfig>("some.ini".to_owned())
+            .is_err()
+    );
+
+    let toml_err = format!(
+        "{:#}",
+        store
+            .get_serde_config_handle::<TestConfig>("bad.toml".to_owned())
+            .err()
+            .expect("bad.toml should fail")
+    );
+    assert!(toml_err.contains("bad.toml"), "{}", toml_err);
+    assert!(toml_err.contains("line 2, column 9"), "{}", toml_err);
+}
+
+#[test]
+fn test_yaml_configs() {
+    let test_source = Arc::new(TestSource::new());
+    test_source.insert_config(
+        "some.yaml",
+        "value: 2\n",
+        ModificationTime::UnixTimestamp(1),
+    );
+    test_source.insert_config("some.yml", "value: 3\n", ModificationTime::UnixTimestamp(1));
+    test_source.insert_config(
+        "bad.yaml",
+        "\nvalue: [1]\n",
+        ModificationTime::UnixTimestamp(1),
+    );
+
+    let store = ConfigStore::new(test_source, None, None);
+
+    let yaml: ConfigHandle<TestConfig> = store
+        .get_yaml_config_handle("some.yaml".to_owned())
+        .expect("Failed to get YAML handle");
+    assert_eq!(*yaml.get(), TestConfig { value: 2 });
+    let by_extension: ConfigHandle<TestConfig> = store
+        .get_serde_config_handle("some.yml".to_owned())
+        .expect("Failed to get handle by extension");
+    assert_eq!(*by_extension.get(), TestConfig { value: 3 });
+
+    let yaml_err = format!(
+        "{:#}",
+        store
+            .get_serde_config_handle::<TestConfig>("bad.yaml".to_owned())
+            .err()
+            .expect("bad.yaml should fail")
+    );
+    assert!(yaml_err.contains("bad.yaml"), "{}", yaml_err);
+    assert!(yaml_err.contains("line 2 column 8"), "{}", yaml_err);
+}
+
+#[tokio::test]
+async fn test_derived_config_handles() {
+    let test_source = Arc::new(TestSource::new());
+    test_source.insert_config(
+        "some1",
+        r#"{ "value": 1 }"#,
+        ModificationTime::UnixTimestamp(1),
+    );
+    test_source.insert_config(
+        "some2",
+        r#"{ "value": 2 }"#,
+        ModificationTime::UnixTimestamp(1),
+    );
+    let store = ConfigStore::new(test_source.clone(), None, None);
+    let handle1 = get_test_handle(&store, "some1").expect("Failed to get handle1");
+    let handle2 = get_test_handle(&store, "some2").expect("Failed to get handle2");
+
+    let computations = Arc::new(AtomicI64::new(0));
+    let doubled = handle1.map({
+        let computations = computations.clone();
+        move |config| {
+            computations.fetch_add(1, Ordering::Relaxed);
+            config.value * 2
+        }
+    });
+    let sum = handle1.map2(&handle2, |config1, config2| config1.value + config2.value);
+    let sum_doubled = sum.map(|sum| sum * 2);
+    let mut watcher = sum_doubled.watcher().expect("Failed to get watcher");
+
+    assert_eq!(*doubled.get(), 2);
+    assert_eq!(*doubled.get(), 2);
+    assert_eq!(*sum.get(), 3);
+    assert_eq!(*sum_doubled.get(), 6);
+    assert_eq!(computations.load(Ordering::Relaxed), 1);
+
+    // Refreshing without changes doesn't recompute anything
+    test_source.insert_to_refresh("some1".to_owned());
+    test_source.insert_to_refresh("some2".to_owned());
+    store.force_update_configs();
+    assert_eq!(computations.load(Ordering::Relaxed), 1);
+
+    test_source.insert_config(
+        "some2",
+        r#"{ "value": 20 }"#,
+        ModificationTime::UnixTimestamp(2),
+    );
+    store.force_update_configs();
+    assert_eq!(*doubled.get(), 2);
+    assert_eq!(computations.load(Ordering::Relaxed), 1);
+    assert_eq!(*sum.get(), 21);
+    let updated = timeout(
+        Duration::from_millis(SLEEP_TIME_MS),
+        watcher.wait_for_next(),
+    )
+    .await
+    .expect("Derived handle watcher was not notified")
+    .expect("Failed to wait for next config");
+    assert_eq!(*updated, 42);
+
+    test_source.insert_config(
+        "some1",
+        r#"{ "value": 10 }"#,
+        ModificationTime::UnixTimestamp(2),
+    );
+    store.force_update_configs();
+    assert_eq!(*doubled.get(), 20);
+    assert_eq!(computations.load(Ordering::Relaxed), 2);
+    assert_eq!(*sum_doubled.get(), 60);
+
+    // Handles derived from static configs are static
+    let fixed = ConfigHandle::from(1).map(|value| value + 1);
+    assert_eq!(*fixed.get(), 2);
+    assert!(fixed.watcher().is_err());
+}
+
+#[test]
+fn test_failing_derived_config_handles() {
+    let test_source = Arc::new(TestSource::new());
+    test_source.insert_config(
+        "some",
+        r#"{ "value": 1 }"#,
+        ModificationTime::UnixTimestamp(1),
+    );
+    test_source.insert_to_refresh("some".to_owned());
+    let store = ConfigStore::new(test_source.clone(), None, None);
+    let handle = get_test_handle(&store, "some").expect("Failed to get handle");
+
+    let checked = handle
+        .try_map(|config| {
+            if config.value < 0 {
+                anyhow::bail!("value must not be negative");
+            }
+            Ok(config.value * 2)
+        })
+        .expect("Failed to derive handle");
+    let panicking = handle.map(|config| {
+        assert!(config.value >= 0, "value must not be negative");
+        config.value * 3
+    });
+    assert_eq!(*checked.get(), 2);
+    assert_eq!(*panicking.get(), 3);
+    assert!(
+        handle
+            .try_map(|_| -> Result<i64> { anyhow::bail!("always fails") })
+            .is_err()
+    );
+
+    // Derived handles keep their last value when they fail on a new config,
+    // and the store keeps working
+    test_source.insert_config(
+        "some",
+        r#"{ "value": -1 }"#,
+        ModificationTime::UnixTimestamp(2),
+    );
+    store.force_update_configs();
+    assert_eq!(handle.get().value, -1);
+    assert_eq!(*checked.get(), 2);
+    assert_eq!(*panicking.get(), 3);
+    assert!(get_test_handle(&store, "some").is_ok());
+
+    test_source.insert_config(
+        "some",
+        r#"{ "value": 5 }"#,
+        ModificationTime::UnixTimestamp(3),
+    );
+    store.force_update_configs();
+    assert_eq!(*checked.get(), 10);
+    assert_eq!(*panicking.get(), 15);
+}
@@ -11,6 +11,7 @@
 use std::time::Duration;
 
 /// A fixed interval backoff strategy that always returns the same duration.
+#[derive(Clone)]
 pub struct FixedInterval {
     interval: Duration,
 }
@@ -31,6 +32,7 @@ impl Iterator for FixedInterval {
 }
 
 /// An exponential backoff strategy that multiplies the previous duration by a base value.
+#[derive(Clone)]
 pub struct ExponentialBackoff {
     base: f64,
     current: Duration,
@@ -62,6 +64,7 @@ impl Iterator for ExponentialBackoff {
 }
 
 /// A Fibonacci backoff strategy that follows the Fibonacci sequence pattern.
+#[derive(Clone)]
 pub struct FibonacciBackoff {
     current: Duration,
     next: Duration,
@@ -90,6 +93,7 @@ impl Iterator for FibonacciBackoff {
 }
 
 /// A wrapper that adds random jitter to another backoff strategy.
+#[derive(Clone)]
 pub struct Jitter<B> {
     inner: B,
     jitter: Duration,
@@ -0,0 +1,108 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// A retry budget shared by all the `Retry`s it is attached to, limiting
+/// their retries to a fraction of their recent successful attempts.
+///
+/// The budget is a token bucket: each successful attempt deposits `ratio`
+/// tokens, up to `max_tokens`, and each retry withdraws one token.  Retries
+/// are refused while the bucket holds less than one token.  The bucket starts
+/// full, so that retries are allowed before any attempt has succeeded.
+#[derive(Clone)]
+pub struct RetryBudget {
+    tokens: Arc<Mutex<f64>>,
+    ratio: f64,
+    max_tokens: f64,
+    on_exhausted: Option<Arc<dyn Fn() + Send + Sync>>,
+}
+
+impl RetryBudget {
+    /// Create a new retry budget allowing `ratio` retries per successful
+    /// attempt, with up to `max_tokens` retries saved up.
+    pub fn new(ratio: f64, max_tokens: f64) -> Self {
+        Self {
+            tokens: Arc::new(Mutex::new(max_tokens)),
+            ratio,
+            max_tokens,
+            on_exhausted: None,
+        }
+    }
+
+    /// Call `on_exhausted` each time a retry is refused, e.g. to count it in
+    /// stats.
+    pub fn on_exhausted(mut self, on_exhausted: impl Fn() + Send + Sync + 'static) -> Self {
+        self.on_exhausted = Some(Arc::new(on_exhausted));
+        self
+    }
+
+    /// Record a successful attempt.
+    pub fn deposit(&self) {
+        let mut tokens = self.tokens.lock().expect("poisoned lock");
+        *tokens = (*tokens + self.ratio).min(self.max_tokens);
+    }
+
+    /// Take a token for a retry, returning whether the retry is allowed.
+    pub fn try_withdraw(&self) -> bool {
+        let allowed = {
+            let mut tokens = self.tokens.lock().expect("poisoned lock");
+            if *tokens >= 1.0 {
+                *tokens -= 1.0;
+                true
+            } else {
+                false
+            }
+        };
+        if !allowed && let Some(on_exhausted) = &self.on_exhausted {
+            on_exhausted();
+        }
+        allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    #[test]
+    fn test_retry_budget() {
+        let exhausted = Arc::new(AtomicUsize::new(0));
+        let exhausted_clone = exhausted.clone();
+        let budget = RetryBudget::new(0.5, 2.0).on_exhausted(move || {
+            exhausted_clone.fetch_add(1, Ordering::SeqCst);
+        });
+        let shared = budget.clone();
+
+        assert!(budget.try_withdraw());
+        assert!(shared.try_withdraw());
+        assert!(!budget.try_withdraw());
+        assert_eq!(exhausted.load(Ordering::SeqCst), 1);
+
+        // Two successes pay for one retry.
+        shared.deposit();
+        assert!(!budget.try_withdraw());
+        shared.deposit();
+        assert!(budget.try_withdraw());
+        assert_eq!(exhausted.load(Ordering::SeqCst), 2);
+
+        // Tokens are capped.
+        for _ in 0..10 {
+            budget.deposit();
+        }
+        assert!(budget.try_withdraw());
+        assert!(budget.try_withdraw());
+        assert!(!budget.try_withdraw());
+    }
+}
@@ -0,0 +1,216 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// The state of a `CircuitBreaker`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Attempts are allowed.
+    Closed,
+    /// Attempts are refused until the cooldown has passed.
+    Open,
+    /// A single probe attempt is allowed per cooldown, to find out whether
+    /// the circuit can be closed again.
+    HalfOpen,
+}
+
+/// Error of a `Retry` whose first attempt was refused by its circuit breaker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitOpen;
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "circuit breaker is open")
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+enum State {
+    Closed { consecutive_failures: usize },
+    Open { until: Instant },
+    HalfOpen { next_probe: Instant },
+}
+
+impl State {
+    fn circuit_state(&self) -> CircuitState {
+        match self {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+}
+
+/// A circuit breaker shared by all the `Retry`s it is attached to.
+///
+/// The circuit opens after `failure_threshold` consecutive failed attempts,
+/// and then refuses attempts for `cooldown`.  After that it is half-open,
+/// allowing one probe attempt per `cooldown`: a successful attempt closes it,
+/// and a failed one opens it again.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    state: Arc<Mutex<State>>,
+    failure_threshold: usize,
+    cooldown: Duration,
+    on_state_change: Option<Arc<dyn Fn(CircuitState, CircuitState) + Send + Sync>>,
+}
+
+impl CircuitBreaker {
+    /// Create a new, closed, circuit breaker.
+    pub fn new(failure_threshold: usize, cooldown: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::Closed {
+                consecutive_failures: 0,
+            })),
+            failure_threshold,
+            cooldown,
+            on_state_change: None,
+        }
+    }
+
+    /// Call `on_state_change` with the old and new states each time the
+    /// state of the circuit changes, e.g. to report it in stats.
+    pub fn on_state_change(
+        mut self,
+        on_state_change: impl Fn(CircuitState, CircuitState) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_state_change = Some(Arc::new(on_state_change));
+        self
+    }
+
+    /// The current state of the circuit.
+    pub fn state(&self) -> CircuitState {
+        self.state.lock().expect("poisoned lock").circuit_state()
+    }
+
+    /// Whether an attempt is allowed now.  When the circuit is half-open this
+    /// uses up the probe of the current cooldown.
+    pub fn allow(&self) -> bool {
+        self.transition(|state, now| match *state {
+            State::Closed { .. } => true,
+            State::Open { until } | State::HalfOpen { next_probe: until } => {
+                if now >= until {
+                    *state = State::HalfOpen {
+                        next_probe: now + self.cooldown,
+                    };
+                    true
+                } else {
+                    false
+                }
+            }
+        })
+    }
+
+    /// Record a successful attempt.
+    pub fn record_success(&self) {
+        self.transition(|state, _now| {
+            *state = State::Closed {
+                consecutive_failures: 0,
+            };
+        })
+    }
+
+    /// Record a failed attempt.
+    pub fn record_failure(&self) {
+        self.transition(|state, now| match state {
+            State::Closed {
+                consecutive_failures,
+            } => {
+                *consecutive_failures += 1;
+                if *consecutive_failures >= self.failure_threshold {
+                    *state = State::Open {
+                        until: now + self.cooldown,
+                    };
+                }
+            }
+            State::HalfOpen { .. } => {
+                *state = State::Open {
+                    until: now + self.cooldown,
+                };
+            }
+            State::Open { .. } => {}
+        })
+    }
+
+    fn transition<T>(&self, f: impl FnOnce(&mut State, Instant) -> T) -> T {
+        let (res, old, new) = {
+            let mut state = self.state.lock().expect("poisoned lock");
+            let old = state.circuit_state();
+            let res = f(&mut state, Instant::now());
+            (res, old, state.circuit_state())
+        };
+        if old != new
+            && let Some(on_state_change) = &self.on_state_change
+        {
+            on_state_change(old, new);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker() {
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let transitions_clone = transitions.clone();
+        let breaker =
+            CircuitBreaker::new(2, Duration::from_secs(1)).on_state_change(move |old, new| {
+                transitions_clone
+                    .lock()
+                    .expect("poisoned lock")
+                    .push((old, new));
+            });
+
+        assert!(breaker.allow());
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow());
+
+        // After the cooldown a single probe is allowed.
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(breaker.allow());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(!breaker.allow());
+
+        // A failed probe opens the circuit again.
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // A successful probe closes it.
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(breaker.allow());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allow());
+
+        assert_eq!(
+            *transitions.lock().expect("poisoned lock"),
+            vec![
+                (CircuitState::Closed, CircuitState::Open),
+                (CircuitState::Open, CircuitState::HalfOpen),
+                (CircuitState::HalfOpen, CircuitState::Open),
+                (CircuitState::Open, CircuitState::HalfOpen),
+                (CircuitState::HalfOpen, CircuitState::Closed),
+            ]
+        );
+    }
+}
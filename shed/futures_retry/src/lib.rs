@@ -12,8 +12,10 @@
 //!
 //! This provides a generic method for retrying fallible futures (i.e.
 //! TryFutures).  It supports various kinds of backoff, customizable with the
-//! builder pattern.
+//! builder pattern.  Fallible streams (i.e. TryStreams) that can be resumed
+//! from a position can be retried with `retry_stream`.
 
+use std::fmt;
 use std::future::Future;
 use std::pin::Pin;
 use std::task::Poll;
@@ -25,8 +27,17 @@ use crate::backoff::ExponentialBackoff;
 use crate::backoff::FibonacciBackoff;
 use crate::backoff::FixedInterval;
 use crate::backoff::Jitter;
+use crate::budget::RetryBudget;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::circuit_breaker::CircuitOpen;
 
 pub mod backoff;
+pub mod budget;
+pub mod circuit_breaker;
+pub mod stream;
+
+pub use crate::stream::RetryStream;
+pub use crate::stream::retry_stream;
 
 pub trait InspectErr<E>: Send {
     fn inspect_err(&mut self, attempt: usize, err: &E);
@@ -47,6 +58,16 @@ where
 
 pub trait RetryIf<E>: Send {
     fn retry_if(&mut self, attempt: usize, err: &E) -> bool;
+
+    /// Decide how to proceed after a failed attempt.  By default the attempt
+    /// is retried with the normal backoff if `retry_if` returns true.
+    fn retry_decision(&mut self, attempt: usize, err: &E) -> RetryDecision {
+        if self.retry_if(attempt, err) {
+            RetryDecision::Retry
+        } else {
+            RetryDecision::GiveUp
+        }
+    }
 }
 
 impl<E> RetryIf<E> for () {
@@ -64,6 +85,55 @@ where
     }
 }
 
+/// What to do after a failed attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Retry after the next interval of the backoff.
+    Retry,
+    /// Retry after the next interval of the backoff, or the given duration
+    /// if it is longer, e.g. as requested by an overloaded server.
+    RetryAfter(Duration),
+    /// Return the error instead of retrying.
+    GiveUp,
+}
+
+/// Adapts a closure returning a `RetryDecision` to `RetryIf`, see
+/// `Retry::retry_decision`.
+pub struct DecideRetry<D>(D);
+
+impl<E, D> RetryIf<E> for DecideRetry<D>
+where
+    D: for<'err> FnMut(usize, &'err E) -> RetryDecision + Send,
+{
+    fn retry_if(&mut self, attempt: usize, err: &E) -> bool {
+        self.retry_decision(attempt, err) != RetryDecision::GiveUp
+    }
+
+    fn retry_decision(&mut self, attempt: usize, err: &E) -> RetryDecision {
+        (self.0)(attempt, err)
+    }
+}
+
+/// Error of an attempt, or of the whole retry, that ran out of time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timeout {
+    /// An attempt took longer than its timeout.
+    Attempt(Duration),
+    /// The attempts went on past the deadline.
+    Deadline(Duration),
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timeout::Attempt(timeout) => write!(f, "attempt timed out after {:?}", timeout),
+            Timeout::Deadline(deadline) => write!(f, "retry deadline of {:?} exceeded", deadline),
+        }
+    }
+}
+
+impl std::error::Error for Timeout {}
+
 #[pin_project]
 pub struct Retry<F, Fut, V, E, B, I, R>
 where
@@ -83,6 +153,16 @@ where
     backoff: B,
     max_attempts: Option<usize>,
     max_interval: Option<Duration>,
+    #[pin]
+    attempt_sleep: Option<tokio::time::Sleep>,
+    attempt_timeout: Option<Duration>,
+    #[pin]
+    deadline_sleep: Option<tokio::time::Sleep>,
+    deadline: Option<Duration>,
+    timeout_err: Option<fn(Timeout) -> E>,
+    retry_budget: Option<RetryBudget>,
+    circuit_breaker: Option<CircuitBreaker>,
+    circuit_open_err: Option<fn(CircuitOpen) -> E>,
     inspect_err: I,
     retry_if: R,
 }
@@ -110,6 +190,14 @@ where
         backoff: FixedInterval::new(interval),
         max_attempts: None,
         max_interval: None,
+        attempt_sleep: None,
+        attempt_timeout: None,
+        deadline_sleep: None,
+        deadline: None,
+        timeout_err: None,
+        retry_budget: None,
+        circuit_breaker: None,
+        circuit_open_err: None,
         inspect_err: (),
         retry_if: (),
     }
@@ -138,6 +226,53 @@ where
         self
     }
 
+    /// Limit the duration of each attempt.  An attempt that takes longer is
+    /// abandoned and fails with `Timeout::Attempt`, which is passed to
+    /// `inspect_err` and `retry_if` like any other error.
+    pub fn attempt_timeout(mut self, attempt_timeout: Duration) -> Self
+    where
+        E: From<Timeout>,
+    {
+        self.attempt_timeout = Some(attempt_timeout);
+        self.timeout_err = Some(E::from);
+        self
+    }
+
+    /// Limit the total duration of all attempts, starting from the first
+    /// poll.  An attempt still running at the deadline is abandoned and
+    /// `Timeout::Deadline` is returned.  When the next attempt would start at
+    /// or after the deadline, the error of the last attempt is returned
+    /// instead of retrying.
+    pub fn deadline(mut self, deadline: Duration) -> Self
+    where
+        E: From<Timeout>,
+    {
+        self.deadline = Some(deadline);
+        self.timeout_err = Some(E::from);
+        self
+    }
+
+    /// Draw each retry from a budget, which can be shared with other
+    /// `Retry`s.  When the budget is exhausted the error of the last attempt
+    /// is returned instead of retrying.
+    pub fn retry_budget(mut self, retry_budget: RetryBudget) -> Self {
+        self.retry_budget = Some(retry_budget);
+        self
+    }
+
+    /// Guard attempts with a circuit breaker, which can be shared with other
+    /// `Retry`s.  The outcome of each attempt is recorded in the breaker.
+    /// While the circuit is open the error of the last attempt is returned
+    /// instead of retrying, or `CircuitOpen` if there was no attempt yet.
+    pub fn circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self
+    where
+        E: From<CircuitOpen>,
+    {
+        self.circuit_breaker = Some(circuit_breaker);
+        self.circuit_open_err = Some(E::from);
+        self
+    }
+
     /// Perform binary exponential backoff.  The second and
     /// subsequent retry intervals will be multiplied by
     /// 2, 4, 8, etc.
@@ -154,6 +289,14 @@ where
             backoff: ExponentialBackoff::binary(initial_interval),
             max_attempts: self.max_attempts,
             max_interval: self.max_interval,
+            attempt_sleep: self.attempt_sleep,
+            attempt_timeout: self.attempt_timeout,
+            deadline_sleep: self.deadline_sleep,
+            deadline: self.deadline,
+            timeout_err: self.timeout_err,
+            retry_budget: self.retry_budget,
+            circuit_breaker: self.circuit_breaker,
+            circuit_open_err: self.circuit_open_err,
             inspect_err: self.inspect_err,
             retry_if: self.retry_if,
         }
@@ -178,6 +321,14 @@ where
             backoff: ExponentialBackoff::new(initial_interval, base.into()),
             max_attempts: self.max_attempts,
             max_interval: self.max_interval,
+            attempt_sleep: self.attempt_sleep,
+            attempt_timeout: self.attempt_timeout,
+            deadline_sleep: self.deadline_sleep,
+            deadline: self.deadline,
+            timeout_err: self.timeout_err,
+            retry_budget: self.retry_budget,
+            circuit_breaker: self.circuit_breaker,
+            circuit_open_err: self.circuit_open_err,
             inspect_err: self.inspect_err,
             retry_if: self.retry_if,
         }
@@ -198,6 +349,14 @@ where
             backoff: FibonacciBackoff::new(initial_interval),
             max_attempts: self.max_attempts,
             max_interval: self.max_interval,
+            attempt_sleep: self.attempt_sleep,
+            attempt_timeout: self.attempt_timeout,
+            deadline_sleep: self.deadline_sleep,
+            deadline: self.deadline,
+            timeout_err: self.timeout_err,
+            retry_budget: self.retry_budget,
+            circuit_breaker: self.circuit_breaker,
+            circuit_open_err: self.circuit_open_err,
             inspect_err: self.inspect_err,
             retry_if: self.retry_if,
         }
@@ -217,6 +376,14 @@ where
             backoff: Jitter::new(self.backoff, jitter),
             max_attempts: self.max_attempts,
             max_interval: self.max_interval,
+            attempt_sleep: self.attempt_sleep,
+            attempt_timeout: self.attempt_timeout,
+            deadline_sleep: self.deadline_sleep,
+            deadline: self.deadline,
+            timeout_err: self.timeout_err,
+            retry_budget: self.retry_budget,
+            circuit_breaker: self.circuit_breaker,
+            circuit_open_err: self.circuit_open_err,
             inspect_err: self.inspect_err,
             retry_if: self.retry_if,
         }
@@ -236,6 +403,14 @@ where
             backoff: self.backoff,
             max_attempts: self.max_attempts,
             max_interval: self.max_interval,
+            attempt_sleep: self.attempt_sleep,
+            attempt_timeout: self.attempt_timeout,
+            deadline_sleep: self.deadline_sleep,
+            deadline: self.deadline,
+            timeout_err: self.timeout_err,
+            retry_budget: self.retry_budget,
+            circuit_breaker: self.circuit_breaker,
+            circuit_open_err: self.circuit_open_err,
             inspect_err,
             retry_if: self.retry_if,
         }
@@ -255,10 +430,73 @@ where
             backoff: self.backoff,
             max_attempts: self.max_attempts,
             max_interval: self.max_interval,
+            attempt_sleep: self.attempt_sleep,
+            attempt_timeout: self.attempt_timeout,
+            deadline_sleep: self.deadline_sleep,
+            deadline: self.deadline,
+            timeout_err: self.timeout_err,
+            retry_budget: self.retry_budget,
+            circuit_breaker: self.circuit_breaker,
+            circuit_open_err: self.circuit_open_err,
             inspect_err: self.inspect_err,
             retry_if,
         }
     }
+
+    /// Decide how to proceed after each failed attempt, like `retry_if`
+    /// but also allowing the closure to ask for a longer interval before the
+    /// next attempt.  The backoff advances on each retry either way, and the
+    /// interval is still limited by `max_interval`.
+    pub fn retry_decision<D>(self, retry_decision: D) -> Retry<F, Fut, V, E, B, I, DecideRetry<D>>
+    where
+        D: FnMut(usize, &E) -> RetryDecision + Send,
+    {
+        Retry {
+            func: self.func,
+            fut: self.fut,
+            sleep: self.sleep,
+            attempt: self.attempt,
+            backoff: self.backoff,
+            max_attempts: self.max_attempts,
+            max_interval: self.max_interval,
+            attempt_sleep: self.attempt_sleep,
+            attempt_timeout: self.attempt_timeout,
+            deadline_sleep: self.deadline_sleep,
+            deadline: self.deadline,
+            timeout_err: self.timeout_err,
+            retry_budget: self.retry_budget,
+            circuit_breaker: self.circuit_breaker,
+            circuit_open_err: self.circuit_open_err,
+            inspect_err: self.inspect_err,
+            retry_if: DecideRetry(retry_decision),
+        }
+    }
+}
+
+/// The interval to wait before retrying after a failed attempt, or `None` if
+/// the error should be returned instead.
+fn retry_interval<E>(
+    retry_if: &mut impl RetryIf<E>,
+    backoff: &mut impl Iterator<Item = Duration>,
+    attempt: usize,
+    max_attempts: Option<usize>,
+    max_interval: Option<Duration>,
+    err: &E,
+) -> Option<Duration> {
+    if max_attempts.is_some_and(|max| attempt >= max) {
+        return None;
+    }
+    let interval = match retry_if.retry_decision(attempt, err) {
+        RetryDecision::Retry => backoff.next()?,
+        RetryDecision::RetryAfter(min_interval) => backoff
+            .next()
+            .map_or(min_interval, |interval| interval.max(min_interval)),
+        RetryDecision::GiveUp => return None,
+    };
+    Some(match max_interval {
+        Some(max_interval) => interval.clamp(Duration::ZERO, max_interval),
+        None => interval,
+    })
 }
 
 impl<F, Fut, V, E, B, I, R> Future for Retry<F, Fut, V, E, B, I, R>
@@ -275,23 +513,82 @@ where
 
     fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
         let mut this = self.project();
+        if *this.attempt == 0
+            && this.deadline_sleep.is_none()
+            && let Some(deadline) = *this.deadline
+        {
+            this.deadline_sleep.set(Some(tokio::time::sleep(deadline)));
+        }
         loop {
             if let Some(fut) = this.fut.as_mut().as_pin_mut() {
-                match fut.poll(cx) {
-                    Poll::Pending => return Poll::Pending,
-                    Poll::Ready(Ok(v)) => {
+                let result = match fut.poll(cx) {
+                    Poll::Ready(result) => result,
+                    Poll::Pending => {
+                        if let Some(deadline_sleep) = this.deadline_sleep.as_mut().as_pin_mut()
+                            && deadline_sleep.poll(cx).is_ready()
+                            && let (Some(deadline), Some(timeout_err)) =
+                                (*this.deadline, *this.timeout_err)
+                        {
+                            this.fut.set(None);
+                            this.attempt_sleep.set(None);
+                            return Poll::Ready(Err(timeout_err(Timeout::Deadline(deadline))));
+                        }
+                        if let Some(attempt_sleep) = this.attempt_sleep.as_mut().as_pin_mut()
+                            && attempt_sleep.poll(cx).is_ready()
+                            && let (Some(attempt_timeout), Some(timeout_err)) =
+                                (*this.attempt_timeout, *this.timeout_err)
+                        {
+                            Err(timeout_err(Timeout::Attempt(attempt_timeout)))
+                        } else {
+                            return Poll::Pending;
+                        }
+                    }
+                };
+                this.fut.set(None);
+                this.attempt_sleep.set(None);
+                match result {
+                    Ok(v) => {
+                        if let Some(retry_budget) = this.retry_budget {
+                            retry_budget.deposit();
+                        }
+                        if let Some(circuit_breaker) = this.circuit_breaker {
+                            circuit_breaker.record_success();
+                        }
                         return Poll::Ready(Ok((v, *this.attempt)));
                     }
-                    Poll::Ready(Err(e)) => {
-                        this.fut.set(None);
+                    Err(e) => {
+                        if let Some(circuit_breaker) = this.circuit_breaker {
+                            circuit_breaker.record_failure();
+                        }
                         this.inspect_err.inspect_err(*this.attempt, &e);
-                        if this.max_attempts.is_none_or(|max| *this.attempt < max)
-                            && this.retry_if.retry_if(*this.attempt, &e)
-                            && let Some(mut interval) = this.backoff.next()
+                        // The deadline is checked first, so that neither a
+                        // retry token nor a half-open probe is spent on an
+                        // attempt that would start too late, and the circuit
+                        // breaker before the budget, so that a token is only
+                        // withdrawn for a retry that actually starts.
+                        if let Some(interval) = retry_interval(
+                            this.retry_if,
+                            this.backoff,
+                            *this.attempt,
+                            *this.max_attempts,
+                            *this.max_interval,
+                            &e,
+                        ) && this.deadline_sleep.as_ref().as_pin_ref().is_none_or(
+                            |deadline_sleep| {
+                                interval
+                                    < deadline_sleep
+                                        .deadline()
+                                        .saturating_duration_since(tokio::time::Instant::now())
+                            },
+                        ) && this
+                            .circuit_breaker
+                            .as_ref()
+                            .is_none_or(|circuit_breaker| circuit_breaker.allow())
+                            && this
+                                .retry_budget
+                                .as_ref()
+                                .is_none_or(|retry_budget| retry_budget.try_withdraw())
                         {
-                            if let Some(max_interval) = this.max_interval {
-                                interval = interval.clamp(Duration::ZERO, *max_interval);
-                            }
                             this.sleep.set(Some(tokio::time::sleep(interval)));
                         } else {
                             return Poll::Ready(Err(e));
@@ -307,8 +604,19 @@ where
                     }
                 }
             }
+            if *this.attempt == 0
+                && let (Some(circuit_breaker), Some(circuit_open_err)) =
+                    (this.circuit_breaker.as_ref(), *this.circuit_open_err)
+                && !circuit_breaker.allow()
+            {
+                return Poll::Ready(Err(circuit_open_err(CircuitOpen)));
+            }
             *this.attempt += 1;
             this.fut.set(Some((this.func)(*this.attempt)));
+            if let Some(attempt_timeout) = *this.attempt_timeout {
+                this.attempt_sleep
+                    .set(Some(tokio::time::sleep(attempt_timeout)));
+            }
         }
     }
 }
@@ -326,6 +634,20 @@ mod tests {
     enum TestError {
         NotYet,
         AlwaysFails,
+        TimedOut(Timeout),
+        CircuitOpen,
+    }
+
+    impl From<CircuitOpen> for TestError {
+        fn from(_: CircuitOpen) -> Self {
+            TestError::CircuitOpen
+        }
+    }
+
+    impl From<Timeout> for TestError {
+        fn from(timeout: Timeout) -> Self {
+            TestError::TimedOut(timeout)
+        }
     }
 
     #[tokio::test]
@@ -655,4 +977,279 @@ mod tests {
         assert_eq!(value, "success on attempt 3");
         assert_eq!(attempts, 3);
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_with_attempt_timeout() {
+        let timeouts = Arc::new(AtomicUsize::new(0));
+        let timeouts_clone = timeouts.clone();
+
+        let result = retry(
+            |attempt| async move {
+                if attempt < 2 {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+                Ok::<_, TestError>(format!("success on attempt {}", attempt))
+            },
+            Duration::from_millis(10),
+        )
+        .attempt_timeout(Duration::from_millis(100))
+        .inspect_err(move |_attempt, err: &TestError| {
+            assert_eq!(
+                *err,
+                TestError::TimedOut(Timeout::Attempt(Duration::from_millis(100)))
+            );
+            timeouts_clone.fetch_add(1, Ordering::SeqCst);
+        })
+        .await;
+
+        assert_eq!(timeouts.load(Ordering::SeqCst), 1);
+        let (value, attempts) = result.unwrap();
+        assert_eq!(value, "success on attempt 2");
+        assert_eq!(attempts, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_with_deadline_abandons_attempt() {
+        let start_time = tokio::time::Instant::now();
+
+        let result = retry(
+            |_attempt| async move {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                Err::<String, _>(TestError::AlwaysFails)
+            },
+            Duration::from_millis(50),
+        )
+        .deadline(Duration::from_millis(100))
+        .await;
+
+        // The second attempt starts after 80ms, and is abandoned at 100ms.
+        assert_eq!(start_time.elapsed(), Duration::from_millis(100));
+        assert_eq!(
+            result.unwrap_err(),
+            TestError::TimedOut(Timeout::Deadline(Duration::from_millis(100)))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_with_deadline_skips_late_attempt() {
+        let start_time = tokio::time::Instant::now();
+        let counter = Arc::new(AtomicUsize::new(0));
+        let counter_clone = counter.clone();
+        let exhausted = Arc::new(AtomicUsize::new(0));
+        let exhausted_clone = exhausted.clone();
+        let budget = RetryBudget::new(1.0, 3.0).on_exhausted(move || {
+            exhausted_clone.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let result = retry(
+            move |_attempt| {
+                let c = counter_clone.clone();
+                async move {
+                    c.fetch_add(1, Ordering::SeqCst);
+                    Err::<String, _>(TestError::AlwaysFails)
+                }
+            },
+            Duration::from_millis(10),
+        )
+        .deadline(Duration::from_millis(35))
+        .retry_budget(budget.clone())
+        .await;
+
+        // Attempts at 0ms, 10ms, 20ms and 30ms. The next one would start at
+        // 40ms, past the deadline, so the last error is returned at once and
+        // the budget, which held 3 retries, isn't drawn from.
+        assert_eq!(start_time.elapsed(), Duration::from_millis(30));
+        assert_eq!(counter.load(Ordering::SeqCst), 4);
+        assert_eq!(exhausted.load(Ordering::SeqCst), 0);
+        assert_eq!(result.unwrap_err(), TestError::AlwaysFails);
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_retry_budget() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let exhausted = Arc::new(AtomicUsize::new(0));
+        let exhausted_clone = exhausted.clone();
+        let budget = RetryBudget::new(1.0, 1.0).on_exhausted(move || {
+            exhausted_clone.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let result = retry(
+            |_attempt| {
+                let c = counter.clone();
+                async move {
+                    c.fetch_add(1, Ordering::SeqCst);
+                    Err::<String, _>(TestError::AlwaysFails)
+                }
+            },
+            Duration::from_millis(10),
+        )
+        .max_attempts(5)
+        .retry_budget(budget.clone())
+        .await;
+
+        // The budget only holds a single retry.
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+        assert_eq!(exhausted.load(Ordering::SeqCst), 1);
+        assert_eq!(result.unwrap_err(), TestError::AlwaysFails);
+
+        // Without a token left, a failed attempt isn't retried.
+        let fails_once = |attempt| async move {
+            if attempt < 2 {
+                Err(TestError::NotYet)
+            } else {
+                Ok(attempt)
+            }
+        };
+        let result = retry(fails_once, Duration::from_millis(10))
+            .retry_budget(budget.clone())
+            .await;
+        assert_eq!(result, Err(TestError::NotYet));
+        assert_eq!(exhausted.load(Ordering::SeqCst), 2);
+
+        // A success deposits a token, which pays for the next retry.
+        let result = retry(
+            |attempt| async move { Ok::<_, TestError>(attempt) },
+            Duration::from_millis(10),
+        )
+        .retry_budget(budget.clone())
+        .await;
+        assert_eq!(result, Ok((1, 1)));
+        let result = retry(fails_once, Duration::from_millis(10))
+            .retry_budget(budget.clone())
+            .await;
+        assert_eq!(result, Ok((2, 2)));
+        assert_eq!(exhausted.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_open_circuit_keeps_budget() {
+        let exhausted = Arc::new(AtomicUsize::new(0));
+        let exhausted_clone = exhausted.clone();
+        let budget = RetryBudget::new(1.0, 2.0).on_exhausted(move || {
+            exhausted_clone.fetch_add(1, Ordering::SeqCst);
+        });
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+
+        let result = retry(
+            |_attempt| async move { Err::<String, _>(TestError::AlwaysFails) },
+            Duration::from_millis(10),
+        )
+        .max_attempts(5)
+        .retry_budget(budget.clone())
+        .circuit_breaker(breaker.clone())
+        .await;
+
+        // The first failure opens the circuit, which refuses the retry
+        // without drawing from the budget, whose 2 tokens are left.
+        assert_eq!(result.unwrap_err(), TestError::AlwaysFails);
+        assert_eq!(breaker.state(), circuit_breaker::CircuitState::Open);
+        assert_eq!(exhausted.load(Ordering::SeqCst), 0);
+        assert!(budget.try_withdraw());
+        assert!(budget.try_withdraw());
+        assert!(!budget.try_withdraw());
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_circuit_breaker() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+
+        let result = retry(
+            |_attempt| {
+                let c = counter.clone();
+                async move {
+                    c.fetch_add(1, Ordering::SeqCst);
+                    Err::<String, _>(TestError::AlwaysFails)
+                }
+            },
+            Duration::from_millis(10),
+        )
+        .max_attempts(5)
+        .circuit_breaker(breaker.clone())
+        .await;
+
+        // The circuit opens after the second failure.
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+        assert_eq!(result.unwrap_err(), TestError::AlwaysFails);
+        assert_eq!(breaker.state(), circuit_breaker::CircuitState::Open);
+
+        let result = retry(
+            |_attempt| {
+                let c = counter.clone();
+                async move {
+                    c.fetch_add(1, Ordering::SeqCst);
+                    Ok::<_, TestError>(())
+                }
+            },
+            Duration::from_millis(10),
+        )
+        .circuit_breaker(breaker)
+        .await;
+
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+        assert_eq!(result.unwrap_err(), TestError::CircuitOpen);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_with_retry_decision() {
+        let start_time = tokio::time::Instant::now();
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        let result = retry(
+            |attempt| {
+                let c = counter.clone();
+                async move {
+                    c.fetch_add(1, Ordering::SeqCst);
+                    match attempt {
+                        1 | 2 => Err::<(), _>(TestError::NotYet),
+                        _ => Err(TestError::AlwaysFails),
+                    }
+                }
+            },
+            Duration::from_millis(10),
+        )
+        .binary_exponential_backoff()
+        .max_interval(Duration::from_millis(150))
+        .retry_decision(|attempt, err| match (attempt, err) {
+            (1, _) => RetryDecision::RetryAfter(Duration::from_millis(100)),
+            (_, TestError::NotYet) => RetryDecision::RetryAfter(Duration::from_secs(10)),
+            _ => RetryDecision::GiveUp,
+        })
+        .await;
+
+        // The first retry waits for the hint of 100ms rather than 10ms, the
+        // second retry's hint of 10s is limited to 150ms.
+        assert_eq!(start_time.elapsed(), Duration::from_millis(250));
+        assert_eq!(counter.load(Ordering::SeqCst), 3);
+        assert_eq!(result.unwrap_err(), TestError::AlwaysFails);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_decision_advances_backoff() {
+        let start_time = tokio::time::Instant::now();
+
+        let result = retry(
+            |attempt| async move {
+                if attempt < 4 {
+                    Err(TestError::NotYet)
+                } else {
+                    Ok(attempt)
+                }
+            },
+            Duration::from_millis(10),
+        )
+        .binary_exponential_backoff()
+        .retry_decision(|attempt, _err: &TestError| {
+            if attempt == 1 {
+                RetryDecision::RetryAfter(Duration::from_millis(1))
+            } else {
+                RetryDecision::Retry
+            }
+        })
+        .await;
+
+        // Intervals of 10ms (longer than the hint), 20ms and 40ms.
+        assert_eq!(start_time.elapsed(), Duration::from_millis(70));
+        assert_eq!(result, Ok((4, 4)));
+    }
 }
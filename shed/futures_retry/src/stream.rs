@@ -0,0 +1,447 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+//! Retrying of fallible streams that can be resumed from a position.
+
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+
+use futures::Stream;
+use pin_project::pin_project;
+
+use crate::DecideRetry;
+use crate::InspectErr;
+use crate::RetryDecision;
+use crate::RetryIf;
+use crate::backoff::ExponentialBackoff;
+use crate::backoff::FibonacciBackoff;
+use crate::backoff::FixedInterval;
+use crate::backoff::Jitter;
+use crate::retry_interval;
+
+#[pin_project]
+pub struct RetryStream<F, S, P, T, V, E, B, I, R>
+where
+    F: FnMut(Option<T>, usize) -> S + Send,
+    S: Stream<Item = Result<V, E>>,
+    P: FnMut(&V) -> T + Send,
+    T: Clone,
+    B: Iterator<Item = Duration> + Clone + Send,
+    I: InspectErr<E>,
+    R: RetryIf<E>,
+{
+    func: F,
+    position: P,
+    #[pin]
+    stream: Option<S>,
+    #[pin]
+    sleep: Option<tokio::time::Sleep>,
+    resume_from: Option<T>,
+    attempt: usize,
+    done: bool,
+    initial_backoff: B,
+    backoff: B,
+    max_attempts: Option<usize>,
+    max_interval: Option<Duration>,
+    inspect_err: I,
+    retry_if: R,
+}
+
+/// Retry a fallible stream, resuming it where it failed.
+///
+/// `func` is called with the position of the last item the stream yielded,
+/// or `None` to start from the beginning, and the attempt number.  It must
+/// return a stream of the items after that position.  The position of each
+/// item is given by `position`.
+///
+/// When the stream yields an error it is re-created after the given interval,
+/// until it ends.  Attempts and backoff are counted from the last item that
+/// was yielded, so that a long stream isn't limited in how many errors it can
+/// recover from in total.  An error that isn't retried is yielded, and ends
+/// the stream.
+///
+/// You can customize the behaviour with the methods on `RetryStream`.
+pub fn retry_stream<F, S, P, T, V, E>(
+    func: F,
+    position: P,
+    interval: Duration,
+) -> RetryStream<F, S, P, T, V, E, FixedInterval, (), ()>
+where
+    F: FnMut(Option<T>, usize) -> S + Send,
+    S: Stream<Item = Result<V, E>>,
+    P: FnMut(&V) -> T + Send,
+    T: Clone,
+{
+    RetryStream {
+        func,
+        position,
+        stream: None,
+        sleep: None,
+        resume_from: None,
+        attempt: 0,
+        done: false,
+        initial_backoff: FixedInterval::new(interval),
+        backoff: FixedInterval::new(interval),
+        max_attempts: None,
+        max_interval: None,
+        inspect_err: (),
+        retry_if: (),
+    }
+}
+
+impl<F, S, P, T, V, E, B, I, R> RetryStream<F, S, P, T, V, E, B, I, R>
+where
+    F: FnMut(Option<T>, usize) -> S + Send,
+    S: Stream<Item = Result<V, E>>,
+    P: FnMut(&V) -> T + Send,
+    T: Clone,
+    B: Iterator<Item = Duration> + Clone + Send,
+    I: InspectErr<E>,
+    R: RetryIf<E>,
+{
+    /// Limit the number of consecutive attempts that yield no items.  If the
+    /// last attempt fails then its error is yielded.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Limit the interval to a maximum value.
+    pub fn max_interval(mut self, max_interval: Duration) -> Self {
+        self.max_interval = Some(max_interval);
+        self
+    }
+
+    /// Perform binary exponential backoff.  The second and
+    /// subsequent retry intervals will be multiplied by
+    /// 2, 4, 8, etc.
+    pub fn binary_exponential_backoff(
+        mut self,
+    ) -> RetryStream<F, S, P, T, V, E, ExponentialBackoff, I, R> {
+        let initial_interval = self
+            .initial_backoff
+            .next()
+            .unwrap_or(Duration::from_millis(10));
+        self.with_backoff(ExponentialBackoff::binary(initial_interval))
+    }
+
+    /// Perform exponential backoff with the given base.
+    /// The second and subsequent retry intervals will be
+    /// multiplied by base, base^2^, base^3^, etc.
+    pub fn exponential_backoff(
+        mut self,
+        base: impl Into<f64>,
+    ) -> RetryStream<F, S, P, T, V, E, ExponentialBackoff, I, R> {
+        let initial_interval = self
+            .initial_backoff
+            .next()
+            .unwrap_or(Duration::from_millis(10));
+        self.with_backoff(ExponentialBackoff::new(initial_interval, base.into()))
+    }
+
+    /// Perform fibonacci backoff.  Each retry interval will
+    /// be the sum of the previous two intervals.
+    pub fn fibonacci_backoff(mut self) -> RetryStream<F, S, P, T, V, E, FibonacciBackoff, I, R> {
+        let initial_interval = self
+            .initial_backoff
+            .next()
+            .unwrap_or(Duration::from_millis(10));
+        self.with_backoff(FibonacciBackoff::new(initial_interval))
+    }
+
+    /// Add jitter to the retry intervals.  The additional delay is
+    /// uniformly random between zero and the jitter duration.
+    pub fn jitter(self, jitter: Duration) -> RetryStream<F, S, P, T, V, E, Jitter<B>, I, R> {
+        let backoff = Jitter::new(self.initial_backoff.clone(), jitter);
+        self.with_backoff(backoff)
+    }
+
+    /// Inspect each error that occurs.  The closure is called after each
+    /// attempt that fails, allowing you to log the error.
+    pub fn inspect_err<I2>(self, inspect_err: I2) -> RetryStream<F, S, P, T, V, E, B, I2, R>
+    where
+        I2: FnMut(usize, &E) + Send,
+    {
+        RetryStream {
+            func: self.func,
+            position: self.position,
+            stream: self.stream,
+            sleep: self.sleep,
+            resume_from: self.resume_from,
+            attempt: self.attempt,
+            done: self.done,
+            initial_backoff: self.initial_backoff,
+            backoff: self.backoff,
+            max_attempts: self.max_attempts,
+            max_interval: self.max_interval,
+            inspect_err,
+            retry_if: self.retry_if,
+        }
+    }
+
+    /// Add a condition to retrying.  If the closure returns false then the
+    /// error is yielded instead of retrying.
+    pub fn retry_if<R2>(self, retry_if: R2) -> RetryStream<F, S, P, T, V, E, B, I, R2>
+    where
+        R2: FnMut(usize, &E) -> bool + Send,
+    {
+        RetryStream {
+            func: self.func,
+            position: self.position,
+            stream: self.stream,
+            sleep: self.sleep,
+            resume_from: self.resume_from,
+            attempt: self.attempt,
+            done: self.done,
+            initial_backoff: self.initial_backoff,
+            backoff: self.backoff,
+            max_attempts: self.max_attempts,
+            max_interval: self.max_interval,
+            inspect_err: self.inspect_err,
+            retry_if,
+        }
+    }
+
+    /// Decide how to proceed after each failed attempt, like `retry_if`
+    /// but also allowing the closure to ask for a longer interval before the
+    /// next attempt.
+    pub fn retry_decision<D>(
+        self,
+        retry_decision: D,
+    ) -> RetryStream<F, S, P, T, V, E, B, I, DecideRetry<D>>
+    where
+        D: FnMut(usize, &E) -> RetryDecision + Send,
+    {
+        RetryStream {
+            func: self.func,
+            position: self.position,
+            stream: self.stream,
+            sleep: self.sleep,
+            resume_from: self.resume_from,
+            attempt: self.attempt,
+            done: self.done,
+            initial_backoff: self.initial_backoff,
+            backoff: self.backoff,
+            max_attempts: self.max_attempts,
+            max_interval: self.max_interval,
+            inspect_err: self.inspect_err,
+            retry_if: DecideRetry(retry_decision),
+        }
+    }
+
+    fn with_backoff<B2>(self, backoff: B2) -> RetryStream<F, S, P, T, V, E, B2, I, R>
+    where
+        B2: Iterator<Item = Duration> + Clone + Send,
+    {
+        RetryStream {
+            func: self.func,
+            position: self.position,
+            stream: self.stream,
+            sleep: self.sleep,
+            resume_from: self.resume_from,
+            attempt: self.attempt,
+            done: self.done,
+            initial_backoff: backoff.clone(),
+            backoff,
+            max_attempts: self.max_attempts,
+            max_interval: self.max_interval,
+            inspect_err: self.inspect_err,
+            retry_if: self.retry_if,
+        }
+    }
+}
+
+impl<F, S, P, T, V, E, B, I, R> Stream for RetryStream<F, S, P, T, V, E, B, I, R>
+where
+    F: FnMut(Option<T>, usize) -> S + Send,
+    S: Stream<Item = Result<V, E>>,
+    P: FnMut(&V) -> T + Send,
+    T: Clone,
+    B: Iterator<Item = Duration> + Clone + Send,
+    I: InspectErr<E>,
+    R: RetryIf<E>,
+{
+    type Item = Result<V, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if *this.done {
+                return Poll::Ready(None);
+            }
+            if let Some(stream) = this.stream.as_mut().as_pin_mut() {
+                match ready!(stream.poll_next(cx)) {
+                    Some(Ok(v)) => {
+                        *this.resume_from = Some((this.position)(&v));
+                        // Later failures are retried from this item on.
+                        *this.attempt = 1;
+                        *this.backoff = this.initial_backoff.clone();
+                        return Poll::Ready(Some(Ok(v)));
+                    }
+                    Some(Err(e)) => {
+                        this.stream.set(None);
+                        this.inspect_err.inspect_err(*this.attempt, &e);
+                        match retry_interval(
+                            this.retry_if,
+                            this.backoff,
+                            *this.attempt,
+                            *this.max_attempts,
+                            *this.max_interval,
+                            &e,
+                        ) {
+                            Some(interval) => {
+                                this.sleep.set(Some(tokio::time::sleep(interval)));
+                            }
+                            None => {
+                                *this.done = true;
+                                return Poll::Ready(Some(Err(e)));
+                            }
+                        }
+                    }
+                    None => {
+                        this.stream.set(None);
+                        *this.done = true;
+                        return Poll::Ready(None);
+                    }
+                }
+            }
+            if let Some(sleep) = this.sleep.as_mut().as_pin_mut() {
+                ready!(sleep.poll(cx));
+                this.sleep.set(None);
+            }
+            *this.attempt += 1;
+            this.stream
+                .set(Some((this.func)(this.resume_from.clone(), *this.attempt)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use futures::StreamExt;
+    use futures::TryStreamExt;
+    use futures::stream;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum TestError {
+        Transient,
+        Permanent,
+    }
+
+    /// A listing of the numbers 0 to 9, failing once with `error` just before
+    /// each position in `failures`.
+    fn listing(
+        failures: Vec<usize>,
+        error: fn() -> TestError,
+    ) -> impl FnMut(Option<usize>, usize) -> stream::BoxStream<'static, Result<usize, TestError>>
+    {
+        let failures = Arc::new(Mutex::new(failures));
+        move |resume_from, _attempt| {
+            let start = resume_from.map_or(0, |position| position + 1);
+            let failures = failures.clone();
+            stream::iter(start..10)
+                .map(move |i| {
+                    let mut failures = failures.lock().expect("poisoned lock");
+                    match failures.iter().position(|failure| *failure == i) {
+                        Some(index) => {
+                            failures.remove(index);
+                            Err(error())
+                        }
+                        None => Ok(i),
+                    }
+                })
+                .boxed()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_stream_resumes() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let attempts_clone = attempts.clone();
+        let mut listing = listing(vec![0, 4, 4, 7], || TestError::Transient);
+
+        let items: Vec<usize> = retry_stream(
+            move |resume_from, attempt| {
+                attempts_clone
+                    .lock()
+                    .expect("poisoned lock")
+                    .push((resume_from, attempt));
+                listing(resume_from, attempt)
+            },
+            |i: &usize| *i,
+            Duration::from_millis(10),
+        )
+        .max_attempts(3)
+        .try_collect()
+        .await
+        .unwrap();
+
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+        assert_eq!(
+            *attempts.lock().expect("poisoned lock"),
+            vec![
+                (None, 1),
+                (None, 2),
+                (Some(3), 2),
+                (Some(3), 3),
+                (Some(6), 2),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_stream_max_attempts() {
+        let items: Vec<Result<usize, TestError>> = retry_stream(
+            listing(vec![4, 4, 4], || TestError::Transient),
+            |i: &usize| *i,
+            Duration::from_millis(10),
+        )
+        .binary_exponential_backoff()
+        .max_attempts(3)
+        .collect()
+        .await;
+
+        let mut expected: Vec<_> = (0..4).map(Ok).collect();
+        expected.push(Err(TestError::Transient));
+        assert_eq!(items, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_stream_retry_if() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let errors_clone = errors.clone();
+
+        let items: Vec<Result<usize, TestError>> = retry_stream(
+            listing(vec![2], || TestError::Permanent),
+            |i: &usize| *i,
+            Duration::from_millis(10),
+        )
+        .inspect_err(move |attempt, err: &TestError| {
+            errors_clone
+                .lock()
+                .expect("poisoned lock")
+                .push((attempt, *err == TestError::Transient));
+        })
+        .retry_if(|_attempt, err| *err == TestError::Transient)
+        .collect()
+        .await;
+
+        assert_eq!(items, vec![Ok(0), Ok(1), Err(TestError::Permanent)]);
+        assert_eq!(*errors.lock().expect("poisoned lock"), vec![(1, false)]);
+    }
+}
@@ -24,6 +24,7 @@ use futures_ext::future::CancelData;
 
 use super::FutureStats;
 use super::StreamStats;
+use crate::PollHistograms;
 use crate::TryStreamStats;
 
 /// A Future that gathers some basic statistics for inner Future.
@@ -34,6 +35,8 @@ pub struct TimedFuture<F> {
     poll_count: u64,
     poll_time: Duration,
     max_poll_time: Duration,
+    poll_histograms: Option<PollHistograms>,
+    last_poll_end: Option<Instant>,
 }
 
 impl<F> TimedFuture<F> {
@@ -44,8 +47,47 @@ impl<F> TimedFuture<F> {
             poll_count: 0,
             poll_time: Duration::from_secs(0),
             max_poll_time: Duration::from_secs(0),
+            poll_histograms: None,
+            last_poll_end: None,
         }
     }
+
+    /// Also collect the distribution of the time spent in each poll, and
+    /// between polls, into [FutureStats::poll_histograms].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures_stats::TimedFutureExt;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (stats, _) = async { 123u32 }.timed().with_poll_histograms().await;
+    /// let poll_histograms = stats.poll_histograms.unwrap();
+    /// assert_eq!(poll_histograms.poll_time.count(), stats.poll_count);
+    /// # });
+    /// ```
+    pub fn with_poll_histograms(mut self) -> Self {
+        self.poll_histograms = Some(PollHistograms::default());
+        self
+    }
+}
+
+/// Record a poll that started at `poll_start` in the histograms, if enabled.
+fn record_poll(
+    poll_histograms: &mut Option<PollHistograms>,
+    last_poll_end: &mut Option<Instant>,
+    poll_start: Instant,
+    poll_elapsed: Duration,
+) {
+    if let Some(poll_histograms) = poll_histograms {
+        poll_histograms.poll_time.add(poll_elapsed);
+        if let Some(last_poll_end) = last_poll_end {
+            poll_histograms
+                .scheduling_delay
+                .add(poll_start.saturating_duration_since(*last_poll_end));
+        }
+        *last_poll_end = Some(poll_start + poll_elapsed);
+    }
 }
 
 impl<F: Future> Future for TimedFuture<F> {
@@ -59,8 +101,15 @@ impl<F: Future> Future for TimedFuture<F> {
         let poll_start = Instant::now();
 
         let poll = unsafe { Pin::new_unchecked(&mut this.inner).poll(cx) };
-        this.poll_time += poll_start.elapsed();
-        this.max_poll_time = poll_start.elapsed().max(this.max_poll_time);
+        let poll_elapsed = poll_start.elapsed();
+        this.poll_time += poll_elapsed;
+        this.max_poll_time = poll_elapsed.max(this.max_poll_time);
+        record_poll(
+            &mut this.poll_histograms,
+            &mut this.last_poll_end,
+            poll_start,
+            poll_elapsed,
+        );
 
         let out = match poll {
             Poll::Pending => return Poll::Pending,
@@ -72,6 +121,7 @@ impl<F: Future> Future for TimedFuture<F> {
             poll_time: this.poll_time,
             max_poll_time: this.max_poll_time,
             poll_count: this.poll_count,
+            poll_histograms: this.poll_histograms.take(),
         };
 
         Poll::Ready((stats, out))
@@ -89,6 +139,7 @@ impl<F> CancelData for TimedFuture<F> {
             poll_time: self.poll_time,
             poll_count: self.poll_count,
             max_poll_time: self.max_poll_time,
+            poll_histograms: self.poll_histograms.clone(),
         }
     }
 }
@@ -105,6 +156,14 @@ impl<F> TimedTryFuture<F> {
             inner: TimedFuture::new(future),
         }
     }
+
+    /// Also collect the distribution of the time spent in each poll, and
+    /// between polls, into [FutureStats::poll_histograms].
+    pub fn with_poll_histograms(self) -> Self {
+        Self {
+            inner: self.inner.with_poll_histograms(),
+        }
+    }
 }
 
 impl<I, E, F: Future<Output = Result<I, E>>> Future for TimedTryFuture<F> {
@@ -146,6 +205,8 @@ where
     max_poll_time: Duration,
     first_item_time: Option<Duration>,
     completed: bool,
+    poll_histograms: Option<PollHistograms>,
+    last_poll_end: Option<Instant>,
 }
 
 impl<S, C> TimedStream<S, C>
@@ -164,9 +225,18 @@ where
             max_poll_time: Duration::from_secs(0),
             first_item_time: None,
             completed: false,
+            poll_histograms: None,
+            last_poll_end: None,
         }
     }
 
+    /// Also collect the distribution of the time spent in each poll, and
+    /// between polls, into [StreamStats::poll_histograms].
+    pub fn with_poll_histograms(mut self) -> Self {
+        self.poll_histograms = Some(PollHistograms::default());
+        self
+    }
+
     fn gen_stats(&self) -> StreamStats {
         StreamStats {
             completion_time: self.start.as_ref().map(Instant::elapsed),
@@ -176,6 +246,7 @@ where
             count: self.count,
             first_item_time: self.first_item_time,
             completed: self.completed,
+            poll_histograms: self.poll_histograms.clone(),
         }
     }
 
@@ -207,8 +278,15 @@ where
 
         let poll_start = Instant::now();
         let poll = unsafe { Pin::new_unchecked(&mut this.inner).poll_next(cx) };
-        this.poll_time += poll_start.elapsed();
-        this.max_poll_time = poll_start.elapsed().max(this.max_poll_time);
+        let poll_elapsed = poll_start.elapsed();
+        this.poll_time += poll_elapsed;
+        this.max_poll_time = poll_elapsed.max(this.max_poll_time);
+        record_poll(
+            &mut this.poll_histograms,
+            &mut this.last_poll_end,
+            poll_start,
+            poll_elapsed,
+        );
         match poll {
             Poll::Pending => Poll::Pending,
             Poll::Ready(Some(item)) => {
@@ -263,6 +341,13 @@ where
         }
     }
 
+    /// Also collect the distribution of the time spent in each poll, and
+    /// between polls, into [StreamStats::poll_histograms].
+    pub fn with_poll_histograms(mut self) -> Self {
+        self.inner.poll_histograms = Some(PollHistograms::default());
+        self
+    }
+
     fn gen_stats(&self) -> TryStreamStats {
         TryStreamStats {
             stream_stats: self.inner.gen_stats(),
@@ -584,4 +669,72 @@ mod tests {
         assert!(out.is_err());
         assert!(callback_called.load(Ordering::SeqCst));
     }
+
+    #[tokio::test]
+    async fn test_timed_future_poll_histograms() {
+        let (stats, _) = async {
+            tokio::task::yield_now().await;
+            thread::sleep(Duration::from_millis(10));
+            tokio::task::yield_now().await;
+        }
+        .timed()
+        .with_poll_histograms()
+        .await;
+        let poll_histograms = stats.poll_histograms.unwrap();
+        assert_eq!(stats.poll_count, 3);
+        assert_eq!(poll_histograms.poll_time.count(), 3);
+        assert_eq!(poll_histograms.scheduling_delay.count(), 2);
+        assert!(poll_histograms.poll_time.quantile(1.0).unwrap() > Duration::from_millis(10));
+
+        let (stats, _) = async {}.timed().await;
+        assert!(stats.poll_histograms.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_timed_try_future_poll_histograms() {
+        let (stats, _) = async { Result::<_, ()>::Ok(123u32) }
+            .try_timed()
+            .with_poll_histograms()
+            .await
+            .unwrap();
+        assert_eq!(stats.poll_histograms.unwrap().poll_time.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_timed_stream_poll_histograms() {
+        let callback_called = Arc::new(AtomicBool::new(false));
+        stream::iter([0u32; 3])
+            .timed({
+                let callback_called = callback_called.clone();
+                move |stats| {
+                    let poll_histograms = stats.poll_histograms.unwrap();
+                    assert_eq!(poll_histograms.poll_time.count(), 4);
+                    assert_eq!(poll_histograms.scheduling_delay.count(), 3);
+                    callback_called.store(true, Ordering::SeqCst);
+                }
+            })
+            .with_poll_histograms()
+            .collect::<Vec<u32>>()
+            .await;
+        assert!(callback_called.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_try_timed_stream_poll_histograms() {
+        let callback_called = Arc::new(AtomicBool::new(false));
+        let out = stream::iter([Ok(0), Err("Rounding error".to_owned())])
+            .try_timed({
+                let callback_called = callback_called.clone();
+                move |stats: TryStreamStats| {
+                    let poll_histograms = stats.stream_stats.poll_histograms.unwrap();
+                    assert_eq!(poll_histograms.poll_time.count(), 2);
+                    callback_called.store(true, Ordering::SeqCst);
+                }
+            })
+            .with_poll_histograms()
+            .try_collect::<Vec<u32>>()
+            .await;
+        assert!(out.is_err());
+        assert!(callback_called.load(Ordering::SeqCst));
+    }
 }
@@ -0,0 +1,154 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+//! Compact histograms of the durations of individual polls.
+
+use std::time::Duration;
+
+use stats_traits::stat_types::Histogram;
+
+const BUCKET_COUNT: usize = 32;
+
+/// A histogram of durations with fixed, power of two, buckets: the first
+/// bucket counts durations under 1µs, and bucket `i` counts durations from
+/// 2^(i-1)µs up to 2^iµs.  Durations too long for the last bucket, of over
+/// half an hour, are counted in it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PollHistogram {
+    buckets: [u64; BUCKET_COUNT],
+}
+
+impl PollHistogram {
+    /// Count a duration in the histogram.
+    pub fn add(&mut self, duration: Duration) {
+        let micros = duration.as_micros();
+        let bucket = (u128::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKET_COUNT - 1)] += 1;
+    }
+
+    /// Number of durations in the histogram.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// The upper bound and count of each bucket, in increasing order.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(bucket, count)| (Self::upper_bound(bucket), *count))
+    }
+
+    /// The upper bound of the bucket holding the given `quantile` of the
+    /// durations, from 0.0 to 1.0, e.g. 0.99 for the 99th percentile.  None
+    /// if the histogram is empty.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let rank = (quantile.clamp(0.0, 1.0) * self.count() as f64).ceil() as u64;
+        let mut seen = 0;
+        self.buckets().find_map(|(upper_bound, count)| {
+            seen += count;
+            (count > 0 && seen >= rank).then_some(upper_bound)
+        })
+    }
+
+    /// Add the durations to a `stats` histogram, in microseconds.  Each
+    /// duration is added as the upper bound of its bucket.
+    pub fn add_to<H: Histogram + ?Sized>(&self, histogram: &H) {
+        for (upper_bound, count) in self.buckets() {
+            if count > 0 {
+                let value = upper_bound.as_micros().try_into().unwrap_or(i64::MAX);
+                histogram.add_repeated_value(value, count.try_into().unwrap_or(u32::MAX));
+            }
+        }
+    }
+
+    fn upper_bound(bucket: usize) -> Duration {
+        Duration::from_micros(1 << bucket)
+    }
+}
+
+/// Histograms of the polls of a Future or Stream.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PollHistograms {
+    /// Time spent in each call to `poll()`.
+    pub poll_time: PollHistogram,
+
+    /// Time between the end of each call to `poll()` and the start of the
+    /// next one, i.e. how long it took for the Future or Stream to be woken
+    /// and scheduled again.
+    pub scheduling_delay: PollHistogram,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn test_poll_histogram() {
+        let mut histogram = PollHistogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+
+        histogram.add(Duration::from_nanos(500));
+        histogram.add(Duration::from_micros(1));
+        histogram.add(Duration::from_micros(3));
+        histogram.add(Duration::from_micros(3));
+        histogram.add(Duration::from_secs(1_000_000));
+        assert_eq!(histogram.count(), 5);
+
+        let buckets: Vec<_> = histogram
+            .buckets()
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        assert_eq!(
+            buckets,
+            vec![
+                (Duration::from_micros(1), 1),
+                (Duration::from_micros(2), 1),
+                (Duration::from_micros(4), 2),
+                (Duration::from_micros(1 << 31), 1),
+            ]
+        );
+
+        assert_eq!(histogram.quantile(0.0), Some(Duration::from_micros(1)));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_micros(4)));
+        assert_eq!(histogram.quantile(0.8), Some(Duration::from_micros(4)));
+        assert_eq!(
+            histogram.quantile(1.0),
+            Some(Duration::from_micros(1 << 31))
+        );
+    }
+
+    #[test]
+    fn test_poll_histogram_add_to() {
+        #[derive(Default)]
+        struct TestHistogram(Mutex<Vec<(i64, u32)>>);
+
+        impl Histogram for TestHistogram {
+            fn add_value(&self, value: i64) {
+                self.add_repeated_value(value, 1);
+            }
+
+            fn add_repeated_value(&self, value: i64, nsamples: u32) {
+                self.0.lock().unwrap().push((value, nsamples));
+            }
+        }
+
+        let mut histogram = PollHistogram::default();
+        histogram.add(Duration::from_micros(3));
+        histogram.add(Duration::from_micros(3));
+        histogram.add(Duration::from_millis(1));
+
+        let stats = TestHistogram::default();
+        histogram.add_to(&stats);
+        assert_eq!(*stats.0.lock().unwrap(), vec![(4, 2), (1024, 1)]);
+    }
+}
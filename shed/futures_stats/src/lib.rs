@@ -18,12 +18,15 @@
 use std::time::Duration;
 
 pub mod futures03;
+pub mod histogram;
 
 // Export new Futures 0.3 API, which has different names.
 pub use futures03::TimedFutureExt;
 pub use futures03::TimedStreamExt;
 pub use futures03::TimedTryFutureExt;
 pub use futures03::TimedTryStreamExt;
+pub use histogram::PollHistogram;
+pub use histogram::PollHistograms;
 
 /// A structure that holds some basic statistics for Future.
 #[derive(Clone, Debug)]
@@ -43,6 +46,10 @@ pub struct FutureStats {
 
     /// Number of times that the Future was polled.
     pub poll_count: u64,
+
+    /// Distribution of the time spent in each `poll()` and between them.  None unless enabled with
+    /// `with_poll_histograms()`.
+    pub poll_histograms: Option<PollHistograms>,
 }
 
 /// A structure that holds some basic statistics for Stream.
@@ -69,6 +76,10 @@ pub struct StreamStats {
     /// Number of times that the Stream was polled.
     pub poll_count: u64,
 
+    /// Distribution of the time spent in each `poll()` and between them.  None unless enabled with
+    /// `with_poll_histograms()`.
+    pub poll_histograms: Option<PollHistograms>,
+
     /// Number of items in the stream.
     pub count: usize,
 
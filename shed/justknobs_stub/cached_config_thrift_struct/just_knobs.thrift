@@ -14,9 +14,32 @@ include "thrift/annotation/rust.thrift"
 struct JustKnobs {
   1: JustKnobInts ints;
   2: JustKnobBools bools;
+  // Bool knobs that are only true for a percentage of hash values.
+  3: JustKnobRollouts rollouts;
+  4: JustKnobStrings strings;
+  5: JustKnobFloats floats;
+  6: JustKnobLists lists;
+}
+
+@rust.Exhaustive
+struct JustKnobRollout {
+  // Percentage of hash values, between 0 and 100, for which the knob is true.
+  1: double percentage;
+  // Percentages overriding `percentage` for the given switch values.
+  2: JustKnobSwitchValues switch_values;
 }
 
 @rust.Type{name = "HashMap"}
 typedef map<string, bool> JustKnobBools
 @rust.Type{name = "HashMap"}
 typedef map<string, i64> JustKnobInts
+@rust.Type{name = "HashMap"}
+typedef map<string, string> JustKnobStrings
+@rust.Type{name = "HashMap"}
+typedef map<string, double> JustKnobFloats
+@rust.Type{name = "HashMap"}
+typedef map<string, list<string>> JustKnobLists
+@rust.Type{name = "HashMap"}
+typedef map<string, JustKnobRollout> JustKnobRollouts
+@rust.Type{name = "HashMap"}
+typedef map<string, double> JustKnobSwitchValues
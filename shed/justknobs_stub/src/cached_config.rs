@@ -25,9 +25,13 @@ use tokio::runtime::Handle;
 use tracing_slog_compat::Logger;
 use tracing_slog_compat::debug;
 use tracing_slog_compat::error;
+use tracing_slog_compat::info;
 use tracing_slog_compat::warn;
 
 use crate::JustKnobs;
+use crate::changes;
+use crate::changes::KnobChange;
+use crate::rollout::KnobRollout;
 
 static JUST_KNOBS: OnceLock<ArcSwap<JustKnobsInMemory>> = OnceLock::new();
 static JUST_KNOBS_WORKER_STATE: OnceLock<JustKnobsWorkerState> = OnceLock::new();
@@ -35,23 +39,83 @@ static JUST_KNOBS_WORKER_STATE: OnceLock<JustKnobsWorkerState> = OnceLock::new()
 #[derive(Serialize, Deserialize)]
 pub struct JustKnobsInMemory(HashMap<String, KnobVal>);
 
+impl From<&JustKnobsStruct> for JustKnobsInMemory {
+    fn from(jk: &JustKnobsStruct) -> Self {
+        Self(knob_vals(jk))
+    }
+}
+
 impl From<Arc<JustKnobsStruct>> for JustKnobsInMemory {
     fn from(jk: Arc<JustKnobsStruct>) -> Self {
-        Self(
-            jk.bools
-                .iter()
-                .map(|(k, v)| (k.clone(), KnobVal::Bool(*v)))
-                .chain(jk.ints.iter().map(|(k, v)| (k.clone(), KnobVal::Int(*v))))
-                .collect(),
-        )
+        Self::from(jk.as_ref())
     }
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone)]
+/// Value of a knob, shared by the cached_config and thread local in memory
+/// implementations.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(untagged)]
-enum KnobVal {
+pub enum KnobVal {
     Bool(bool),
     Int(i64),
+    Float(f64),
+    String(String),
+    List(Vec<String>),
+    /// Bool knob that is true for a percentage of hash values
+    Rollout(KnobRollout),
+}
+
+impl KnobVal {
+    fn type_name(&self) -> &'static str {
+        match self {
+            KnobVal::Bool(_) | KnobVal::Rollout(_) => "bool",
+            KnobVal::Int(_) => "int",
+            KnobVal::Float(_) => "float",
+            KnobVal::String(_) => "string",
+            KnobVal::List(_) => "list",
+        }
+    }
+
+    /// Error for knob `name` holding this value while `expected_type` was
+    /// requested.
+    pub(crate) fn type_mismatch(&self, name: &str, expected_type: &str) -> anyhow::Error {
+        anyhow!(
+            "JustKnobs knob {} has type {} while expected {}",
+            name,
+            self.type_name(),
+            expected_type,
+        )
+    }
+}
+
+/// Values of all the knobs of `jk` by name, which both `JustKnobsInMemory`
+/// implementations wrap.
+pub(crate) fn knob_vals(jk: &JustKnobsStruct) -> HashMap<String, KnobVal> {
+    jk.bools
+        .iter()
+        .map(|(k, v)| (k.clone(), KnobVal::Bool(*v)))
+        .chain(jk.ints.iter().map(|(k, v)| (k.clone(), KnobVal::Int(*v))))
+        .chain(
+            jk.rollouts
+                .iter()
+                .map(|(k, v)| (k.clone(), KnobVal::Rollout(v.into()))),
+        )
+        .chain(
+            jk.strings
+                .iter()
+                .map(|(k, v)| (k.clone(), KnobVal::String(v.clone()))),
+        )
+        .chain(
+            jk.floats
+                .iter()
+                .map(|(k, v)| (k.clone(), KnobVal::Float(*v))),
+        )
+        .chain(
+            jk.lists
+                .iter()
+                .map(|(k, v)| (k.clone(), KnobVal::List(v.clone()))),
+        )
+        .collect()
 }
 
 pub fn in_use() -> bool {
@@ -62,46 +126,117 @@ pub fn just_knobs() -> &'static ArcSwap<JustKnobsInMemory> {
     JUST_KNOBS.get_or_init(|| ArcSwap::from(Arc::new(JustKnobsInMemory(HashMap::new()))))
 }
 
-pub struct CachedConfigJustKnobs;
-impl JustKnobs for CachedConfigJustKnobs {
-    fn eval(name: &str, _hash_val: Option<&str>, _switch_val: Option<&str>) -> Result<bool> {
-        let value = *(just_knobs()
-            .load()
+impl JustKnobsInMemory {
+    /// Knobs that differ between `old` and `self`, sorted by name.
+    fn changes_since(&self, old: &JustKnobsInMemory) -> Vec<KnobChange> {
+        let mut changes: Vec<KnobChange> = self
             .0
+            .iter()
+            .filter(|(name, new)| old.0.get(*name) != Some(*new))
+            .map(|(name, new)| KnobChange {
+                name: name.clone(),
+                old: old.0.get(name).cloned(),
+                new: Some(new.clone()),
+            })
+            .chain(
+                old.0
+                    .iter()
+                    .filter(|(name, _)| !self.0.contains_key(*name))
+                    .map(|(name, old)| KnobChange {
+                        name: name.clone(),
+                        old: Some(old.clone()),
+                        new: None,
+                    }),
+            )
+            .collect();
+        changes.sort_by(|a, b| a.name.cmp(&b.name));
+        changes
+    }
+
+    fn knob(&self, name: &str, expected_type: &str) -> Result<&KnobVal> {
+        self.0
             .get(name)
-            .ok_or_else(|| anyhow!("Missing just knobs bool: {}", name))?);
-
-        match value {
-            KnobVal::Int(_v) => Err(anyhow!(
-                "JustKnobs knob {} has type int while expected bool",
-                name,
-            )),
-            KnobVal::Bool(b) => Ok(b),
+            .ok_or_else(|| anyhow!("Missing just knobs {}: {}", expected_type, name))
+    }
+
+    pub(crate) fn eval(
+        &self,
+        name: &str,
+        hash_val: Option<&str>,
+        switch_val: Option<&str>,
+    ) -> Result<bool> {
+        match self.knob(name, "bool")? {
+            KnobVal::Bool(b) => Ok(*b),
+            KnobVal::Rollout(rollout) => Ok(rollout.eval(name, hash_val, switch_val)),
+            value => Err(value.type_mismatch(name, "bool")),
         }
     }
 
-    fn get(name: &str, _switch_val: Option<&str>) -> Result<i64> {
-        let value = *(just_knobs()
-            .load()
-            .0
-            .get(name)
-            .ok_or_else(|| anyhow!("Missing just knobs int: {}", name))?);
-
-        match value {
-            KnobVal::Bool(_v) => Err(anyhow!(
-                "JustKnobs knob {} has type bool while expected int",
-                name,
-            )),
-            KnobVal::Int(b) => Ok(b),
+    pub(crate) fn get(&self, name: &str, _switch_val: Option<&str>) -> Result<i64> {
+        match self.knob(name, "int")? {
+            KnobVal::Int(v) => Ok(*v),
+            value => Err(value.type_mismatch(name, "int")),
+        }
+    }
+
+    pub(crate) fn get_float(&self, name: &str, _switch_val: Option<&str>) -> Result<f64> {
+        match self.knob(name, "float")? {
+            KnobVal::Float(v) => Ok(*v),
+            value => Err(value.type_mismatch(name, "float")),
+        }
+    }
+
+    pub(crate) fn get_string(&self, name: &str, _switch_val: Option<&str>) -> Result<String> {
+        match self.knob(name, "string")? {
+            KnobVal::String(v) => Ok(v.clone()),
+            value => Err(value.type_mismatch(name, "string")),
+        }
+    }
+
+    pub(crate) fn get_list(&self, name: &str, _switch_val: Option<&str>) -> Result<Vec<String>> {
+        match self.knob(name, "list")? {
+            KnobVal::List(v) => Ok(v.clone()),
+            value => Err(value.type_mismatch(name, "list")),
         }
     }
 }
 
+pub struct CachedConfigJustKnobs;
+impl JustKnobs for CachedConfigJustKnobs {
+    fn eval(name: &str, hash_val: Option<&str>, switch_val: Option<&str>) -> Result<bool> {
+        just_knobs().load().eval(name, hash_val, switch_val)
+    }
+
+    fn get(name: &str, switch_val: Option<&str>) -> Result<i64> {
+        just_knobs().load().get(name, switch_val)
+    }
+
+    fn get_float(name: &str, switch_val: Option<&str>) -> Result<f64> {
+        just_knobs().load().get_float(name, switch_val)
+    }
+
+    fn get_string(name: &str, switch_val: Option<&str>) -> Result<String> {
+        just_knobs().load().get_string(name, switch_val)
+    }
+
+    fn get_list(name: &str, switch_val: Option<&str>) -> Result<Vec<String>> {
+        just_knobs().load().get_list(name, switch_val)
+    }
+}
+
 fn log_just_knobs(just_knobs: &JustKnobsStruct) -> String {
     serde_json::to_string(just_knobs)
         .unwrap_or_else(|e| format!("failed to serialize JustKnobs: {}", e))
 }
 
+fn log_knob_value(value: Option<&KnobVal>) -> String {
+    match value {
+        Some(value) => serde_json::to_string(value)
+            .unwrap_or_else(|e| format!("failed to serialize knob value: {}", e)),
+        None => "<unset>".to_owned(),
+    }
+}
+
 pub fn init_just_knobs_worker(
     logger: impl IntoLogger,
     config_handle: ConfigHandle<JustKnobsStruct>,
@@ -128,13 +263,13 @@ pub fn init_just_knobs(
     config_handle: &ConfigHandle<JustKnobsStruct>,
 ) -> Result<()> {
     let just_knobs = config_handle.get();
-    let logger = logger.clone();
+    let logger = logger.clone().into_logger();
     debug!(
-        logger.into_logger(),
+        logger,
         "Initializing JustKnobs: {}",
         log_just_knobs(&just_knobs)
     );
-    update_just_knobs(just_knobs)
+    update_just_knobs(just_knobs, &logger)
 }
 
 struct JustKnobsWorkerState {
@@ -173,14 +308,27 @@ fn wait_and_update_iteration(new_just_knobs: Arc<JustKnobsStruct>, logger: &Logg
         "Updating JustKnobs to new: {}",
         log_just_knobs(&new_just_knobs),
     );
-    if let Err(e) = update_just_knobs(new_just_knobs) {
+    if let Err(e) = update_just_knobs(new_just_knobs, logger) {
         warn!(logger, "Failed to refresh just knobs: {}", e);
     }
 }
 
-fn update_just_knobs(new_just_knobs: Arc<JustKnobsStruct>) -> Result<()> {
+fn update_just_knobs(new_just_knobs: Arc<JustKnobsStruct>, logger: &Logger) -> Result<()> {
     let just_knobs = just_knobs();
-    just_knobs.swap(Arc::new(new_just_knobs.into()));
+    let new_just_knobs: Arc<JustKnobsInMemory> = Arc::new(new_just_knobs.into());
+    let old_just_knobs = just_knobs.swap(new_just_knobs.clone());
+
+    let changes = new_just_knobs.changes_since(&old_just_knobs);
+    for change in &changes {
+        info!(
+            logger,
+            "JustKnob {} changed", change.name;
+            "knob" => %change.name,
+            "old_value" => %log_knob_value(change.old.as_ref()),
+            "new_value" => %log_knob_value(change.new.as_ref()),
+        );
+    }
+    changes::notify(&changes);
     Ok(())
 }
 
@@ -209,6 +357,9 @@ mod test {
     use cached_config::ConfigStore;
     use cached_config::ModificationTime;
     use cached_config::test_source::TestSource;
+    use futures::Stream;
+    use futures::StreamExt;
+    use futures::future;
     use slog_glog_fmt::logger_that_can_work_in_tests;
     use tokio::runtime::Handle;
 
@@ -216,31 +367,59 @@ mod test {
     use crate as justknobs;
     const SLEEP_TIME_MS: u64 = 50;
 
+    /// Changes of the knobs whose name starts with `prefix`, so that tests
+    /// updating other knobs concurrently don't interfere.
+    fn knob_changes(prefix: &'static str) -> impl Stream<Item = Vec<KnobChange>> + Unpin {
+        changes::changes().filter_map(move |changes| {
+            let changes: Vec<_> = changes
+                .into_iter()
+                .filter(|change| change.name.starts_with(prefix))
+                .collect();
+            future::ready((!changes.is_empty()).then_some(changes))
+        })
+    }
+
     #[tokio::test(start_paused = true)]
     async fn test_jk_loading() -> Result<()> {
         let test_source = {
             let test_source = TestSource::new();
             test_source.insert_config(
                 "justknobs.json",
-                r#"{ "bools": {"my/config:knob1": true } }"#,
+                r#"{ "bools": {"jk_loading/config:knob1": true } }"#,
                 ModificationTime::UnixTimestamp(1),
             );
             Arc::new(test_source)
         };
         let logger = logger_that_can_work_in_tests().unwrap();
         let store = ConfigStore::new(test_source.clone(), Duration::from_millis(2), None);
+        let mut knob_changes = knob_changes("jk_loading/");
         init_just_knobs_worker(
             logger.clone(),
             store.get_config_handle("justknobs.json".to_owned())?,
             Handle::current(),
         )?;
-        assert!(CachedConfigJustKnobs::eval("my/config:knob1", None, None).unwrap());
+        assert!(CachedConfigJustKnobs::eval("jk_loading/config:knob1", None, None).unwrap());
+        assert_eq!(
+            knob_changes.next().await.unwrap(),
+            vec![KnobChange {
+                name: "jk_loading/config:knob1".to_owned(),
+                old: None,
+                new: Some(KnobVal::Bool(true)),
+            }]
+        );
 
         test_source.insert_config(
             "justknobs.json",
             r#"{
-                "bools": {"my/config:knob1": false },
-                "ints": {"my/config:knob2": 10 }
+                "bools": {"jk_loading/config:knob1": false },
+                "ints": {"jk_loading/config:knob2": 10 },
+                "rollouts": {"jk_loading/config:knob4": {
+                    "percentage": 0.0,
+                    "switch_values": {"region1": 100.0 }
+                }},
+                "strings": {"jk_loading/config:knob5": "value" },
+                "floats": {"jk_loading/config:knob6": 0.25 },
+                "lists": {"jk_loading/config:knob7": ["a", "b"] }
              }"#,
             ModificationTime::UnixTimestamp(10),
         );
@@ -252,12 +431,71 @@ mod test {
         // mode so the time is auto-advanced if the runtime has nothing to do.
         tokio::time::sleep(Duration::from_millis(SLEEP_TIME_MS)).await;
 
-        assert!(!CachedConfigJustKnobs::eval("my/config:knob1", None, None).unwrap());
+        assert!(!CachedConfigJustKnobs::eval("jk_loading/config:knob1", None, None).unwrap());
         assert_eq!(
-            CachedConfigJustKnobs::get("my/config:knob2", None).unwrap(),
+            CachedConfigJustKnobs::get("jk_loading/config:knob2", None).unwrap(),
             10
         );
-        assert!(CachedConfigJustKnobs::eval("my/config:knob3", None, None).is_err());
+        assert!(CachedConfigJustKnobs::eval("jk_loading/config:knob3", None, None).is_err());
+        assert!(
+            !CachedConfigJustKnobs::eval("jk_loading/config:knob4", Some("repo"), None).unwrap()
+        );
+        assert!(
+            CachedConfigJustKnobs::eval("jk_loading/config:knob4", Some("repo"), Some("region1"))
+                .unwrap()
+        );
+        assert_eq!(
+            CachedConfigJustKnobs::get_string("jk_loading/config:knob5", None).unwrap(),
+            "value"
+        );
+        assert_eq!(
+            CachedConfigJustKnobs::get_float("jk_loading/config:knob6", None).unwrap(),
+            0.25
+        );
+        assert_eq!(
+            CachedConfigJustKnobs::get_list("jk_loading/config:knob7", None).unwrap(),
+            vec!["a".to_owned(), "b".to_owned()]
+        );
+        assert_eq!(
+            CachedConfigJustKnobs::get("jk_loading/config:knob5", None)
+                .unwrap_err()
+                .to_string(),
+            "JustKnobs knob jk_loading/config:knob5 has type string while expected int"
+        );
+
+        let changes = knob_changes.next().await.unwrap();
+        let changed: Vec<&str> = changes.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            changed,
+            vec![
+                "jk_loading/config:knob1",
+                "jk_loading/config:knob2",
+                "jk_loading/config:knob4",
+                "jk_loading/config:knob5",
+                "jk_loading/config:knob6",
+                "jk_loading/config:knob7",
+            ]
+        );
+        assert_eq!(
+            changes[0],
+            KnobChange {
+                name: "jk_loading/config:knob1".to_owned(),
+                old: Some(KnobVal::Bool(true)),
+                new: Some(KnobVal::Bool(false)),
+            }
+        );
+
+        // Removed knobs are reported, unchanged ones aren't
+        test_source.insert_config(
+            "justknobs.json",
+            r#"{ "bools": {"jk_loading/config:knob1": false } }"#,
+            ModificationTime::UnixTimestamp(20),
+        );
+        test_source.insert_to_refresh("justknobs.json".to_owned());
+        let changes = knob_changes.next().await.unwrap();
+        assert_eq!(changes.len(), 5);
+        assert!(changes.iter().all(|c| c.old.is_some() && c.new.is_none()));
+        assert!(changes.iter().all(|c| c.name != "jk_loading/config:knob1"));
         Ok(())
     }
 
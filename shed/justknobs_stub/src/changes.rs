@@ -0,0 +1,113 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+/// Subscriptions to the changes of the knobs served by the cached_config
+/// implementation, notified every time it swaps in a new set of knobs.
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+
+use futures::Stream;
+use futures::channel::mpsc;
+
+use crate::cached_config::KnobVal;
+
+type Callback = Arc<dyn Fn(&[KnobChange]) + Send + Sync>;
+
+static SUBSCRIBERS: Mutex<Vec<(u64, Callback)>> = Mutex::new(Vec::new());
+static NEXT_SUBSCRIBER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Change of a single knob between two consecutive updates of the knobs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KnobChange {
+    /// Name of the knob
+    pub name: String,
+    /// Value before the update, `None` if the knob was added by it
+    pub old: Option<KnobVal>,
+    /// Value after the update, `None` if the knob was removed by it
+    pub new: Option<KnobVal>,
+}
+
+/// Keeps a callback registered with `subscribe` until it is dropped.
+#[must_use = "the callback is unsubscribed when the subscription is dropped"]
+pub struct KnobChangeSubscription {
+    id: u64,
+}
+
+impl Drop for KnobChangeSubscription {
+    fn drop(&mut self) {
+        SUBSCRIBERS
+            .lock()
+            .expect("poisoned lock")
+            .retain(|(id, _)| *id != self.id);
+    }
+}
+
+/// Call `callback` with the knobs that changed every time the knobs are
+/// updated. Updates that don't change any knob are not reported. The callback
+/// runs on the thread performing the update, so it should not block.
+pub fn subscribe(
+    callback: impl Fn(&[KnobChange]) + Send + Sync + 'static,
+) -> KnobChangeSubscription {
+    let id = NEXT_SUBSCRIBER_ID.fetch_add(1, Ordering::Relaxed);
+    SUBSCRIBERS
+        .lock()
+        .expect("poisoned lock")
+        .push((id, Arc::new(callback)));
+    KnobChangeSubscription { id }
+}
+
+/// Stream of the knobs changed by each update, see `subscribe`.
+pub fn changes() -> KnobChangeStream {
+    let (sender, receiver) = mpsc::unbounded();
+    let subscription = subscribe(move |changes| {
+        // The receiver is only gone once the stream, and so this
+        // subscription, is dropped.
+        let _ = sender.unbounded_send(changes.to_vec());
+    });
+    KnobChangeStream {
+        receiver,
+        _subscription: subscription,
+    }
+}
+
+/// Stream returned by `changes`, which stays subscribed until it is dropped.
+pub struct KnobChangeStream {
+    receiver: mpsc::UnboundedReceiver<Vec<KnobChange>>,
+    _subscription: KnobChangeSubscription,
+}
+
+impl Stream for KnobChangeStream {
+    type Item = Vec<KnobChange>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+pub(crate) fn notify(changes: &[KnobChange]) {
+    if changes.is_empty() {
+        return;
+    }
+    // Called without holding the lock, so that callbacks can (un)subscribe.
+    let callbacks: Vec<Callback> = SUBSCRIBERS
+        .lock()
+        .expect("poisoned lock")
+        .iter()
+        .map(|(_, callback)| callback.clone())
+        .collect();
+    for callback in callbacks {
+        callback(changes);
+    }
+}
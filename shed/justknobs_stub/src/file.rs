@@ -0,0 +1,194 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+/// JustKnobs implementation that reads the knobs from a JSON file, in the
+/// format accepted by `JustKnobsInMemory::from_json`, and reloads it when it
+/// changes. Unlike the cached_config implementation, it does not need a Tokio
+/// runtime and it can be initialized again to switch to another file.
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Weak;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Result;
+use anyhow::anyhow;
+use arc_swap::ArcSwapOption;
+use cached_config::ConfigHandle;
+use cached_config::ConfigStore;
+use just_knobs_struct::JustKnobs as JustKnobsStruct;
+use tracing_slog_compat::debug;
+
+use crate::JustKnobs;
+use crate::cached_config::IntoLogger;
+use crate::cached_config::JustKnobsInMemory;
+
+static FILE_JUST_KNOBS: ArcSwapOption<FileJustKnobsState> = ArcSwapOption::const_empty();
+
+struct FileJustKnobsState {
+    // Refreshed by the reload thread, which exits once this state is dropped.
+    store: ConfigStore,
+    just_knobs: ConfigHandle<JustKnobsInMemory>,
+}
+
+pub fn in_use() -> bool {
+    FILE_JUST_KNOBS.load().is_some()
+}
+
+pub struct FileJustKnobs;
+impl JustKnobs for FileJustKnobs {
+    fn eval(name: &str, hash_val: Option<&str>, switch_val: Option<&str>) -> Result<bool> {
+        loaded_just_knobs()?.eval(name, hash_val, switch_val)
+    }
+
+    fn get(name: &str, switch_val: Option<&str>) -> Result<i64> {
+        loaded_just_knobs()?.get(name, switch_val)
+    }
+
+    fn get_float(name: &str, switch_val: Option<&str>) -> Result<f64> {
+        loaded_just_knobs()?.get_float(name, switch_val)
+    }
+
+    fn get_string(name: &str, switch_val: Option<&str>) -> Result<String> {
+        loaded_just_knobs()?.get_string(name, switch_val)
+    }
+
+    fn get_list(name: &str, switch_val: Option<&str>) -> Result<Vec<String>> {
+        loaded_just_knobs()?.get_list(name, switch_val)
+    }
+}
+
+fn loaded_just_knobs() -> Result<Arc<JustKnobsInMemory>> {
+    FILE_JUST_KNOBS
+        .load()
+        .as_ref()
+        .map(|state| state.just_knobs.get())
+        .ok_or_else(|| anyhow!("File-backed JustKnobs are not initialized"))
+}
+
+/// Use the knobs from the JSON file at `path`, checking every
+/// `reload_interval` whether it has changed. If the file can't be read or
+/// parsed later on, the last loaded knobs are kept and a warning is logged.
+/// Calling this again replaces the previously used file.
+pub fn init_just_knobs(
+    logger: impl IntoLogger,
+    path: &Path,
+    reload_interval: Duration,
+) -> Result<()> {
+    let logger = logger.into_logger();
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = path
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        .ok_or_else(|| anyhow!("Invalid JustKnobs file path {}", path.display()))?;
+
+    // No updating thread is spawned by the store, as it would outlive it.
+    let store = ConfigStore::file(logger.clone(), directory, None, None);
+    let just_knobs = store
+        .get_config_handle_DEPRECATED::<JustKnobsStruct>(file_name.to_owned())
+        .with_context(|| format!("Failed to load JustKnobs from {}", path.display()))?
+        .map(|just_knobs| JustKnobsInMemory::from(just_knobs));
+    debug!(logger, "Initializing JustKnobs from {}", path.display());
+
+    let state = Arc::new(FileJustKnobsState { store, just_knobs });
+    thread::Builder::new()
+        .name("rust-jk-file-reload".into())
+        .spawn({
+            let state = Arc::downgrade(&state);
+            move || reload_thread(state, reload_interval)
+        })
+        .context("Can't spawn JustKnobs file reload thread")?;
+    FILE_JUST_KNOBS.store(Some(state));
+
+    Ok(())
+}
+
+/// Stop using the knobs from the file given to `init_just_knobs`
+pub fn reset_just_knobs() {
+    FILE_JUST_KNOBS.store(None);
+}
+
+fn reload_thread(state: Weak<FileJustKnobsState>, reload_interval: Duration) {
+    loop {
+        thread::sleep(reload_interval);
+        match state.upgrade() {
+            Some(state) => state.store.force_update_configs(),
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::time::Instant;
+
+    use slog_glog_fmt::logger_that_can_work_in_tests;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const RELOAD_INTERVAL: Duration = Duration::from_millis(10);
+
+    fn wait_for(mut condition: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Timed out waiting for JustKnobs reload"
+            );
+            thread::sleep(RELOAD_INTERVAL);
+        }
+    }
+
+    #[test]
+    fn test_file_just_knobs() -> Result<()> {
+        let logger = logger_that_can_work_in_tests().unwrap();
+        let dir = TempDir::new()?;
+        let path = dir.path().join("justknobs.json");
+        fs::write(
+            &path,
+            r#"{ "bools": {"my/config:knob1": true }, "ints": {"my/config:knob2": 2 } }"#,
+        )?;
+
+        assert!(FileJustKnobs::eval("my/config:knob1", None, None).is_err());
+        init_just_knobs(logger.clone(), &path, RELOAD_INTERVAL)?;
+        assert!(in_use());
+        assert!(FileJustKnobs::eval("my/config:knob1", None, None)?);
+        assert_eq!(FileJustKnobs::get("my/config:knob2", None)?, 2);
+
+        fs::write(&path, r#"{ "bools": {"my/config:knob1": false } }"#)?;
+        wait_for(|| !FileJustKnobs::eval("my/config:knob1", None, None).unwrap());
+        assert!(FileJustKnobs::get("my/config:knob2", None).is_err());
+
+        // Invalid contents are ignored
+        fs::write(&path, "{")?;
+        thread::sleep(RELOAD_INTERVAL * 5);
+        assert!(!FileJustKnobs::eval("my/config:knob1", None, None)?);
+
+        // Switch to another file
+        let other_path = dir.path().join("other.json");
+        fs::write(&other_path, r#"{ "ints": {"my/config:knob2": 20 } }"#)?;
+        init_just_knobs(logger, &other_path, RELOAD_INTERVAL)?;
+        assert_eq!(FileJustKnobs::get("my/config:knob2", None)?, 20);
+        fs::write(&other_path, r#"{ "ints": {"my/config:knob2": 21 } }"#)?;
+        wait_for(|| FileJustKnobs::get("my/config:knob2", None).unwrap() == 21);
+
+        reset_just_knobs();
+        assert!(!in_use());
+        assert!(FileJustKnobs::get("my/config:knob2", None).is_err());
+        Ok(())
+    }
+}
@@ -15,7 +15,12 @@
 //!  * cached-config, which will work with any config source that cached_config crate can work with.
 //!    Can be used for integration tests where the config can be read from on-disk JSON file and
 //!    fully isolated from prod setup.  Used after being initialized with
-//!    init_cached_config_just_knobs/init_cached_config_just_knobs_worker.
+//!    init_cached_config_just_knobs/init_cached_config_just_knobs_worker.  Changes of its knobs
+//!    are logged and can be observed with subscribe_to_cached_config_just_knobs_changes or
+//!    cached_config_just_knobs_changes.
+//!  * file, which reads knobs from a JSON file and reloads it when it changes, without requiring
+//!    a Tokio runtime.  Used after being initialized with init_file_just_knobs, until
+//!    reset_file_just_knobs is called.
 //!  * thread-local-in-memory, which is useful for testing. It allows to override justknobs within a
 //!    test without affecting other tests. Used always when cfg(test) is true.
 
@@ -25,17 +30,26 @@ use anyhow::Result;
 use cached_config::CachedConfigJustKnobs;
 #[cfg(fbcode_build)]
 use fb_justknobs as prod_implementation;
+use file::FileJustKnobs;
 
 pub mod cached_config;
+pub mod changes;
+pub mod file;
+mod rollout;
 mod thread_local_in_memory;
 pub use cached_config::init_just_knobs as init_cached_config_just_knobs;
 pub use cached_config::init_just_knobs_worker as init_cached_config_just_knobs_worker;
+pub use changes::changes as cached_config_just_knobs_changes;
+pub use changes::subscribe as subscribe_to_cached_config_just_knobs_changes;
+pub use file::init_just_knobs as init_file_just_knobs;
+pub use file::reset_just_knobs as reset_file_just_knobs;
 use thread_local_in_memory::ThreadLocalInMemoryJustKnobsImpl;
 
 /// Those should be only used in tests.
 pub mod test_helpers {
+    pub use crate::cached_config::KnobVal;
+    pub use crate::rollout::KnobRollout;
     pub use crate::thread_local_in_memory::JustKnobsInMemory;
-    pub use crate::thread_local_in_memory::KnobVal;
     pub use crate::thread_local_in_memory::override_just_knobs;
     pub use crate::thread_local_in_memory::with_just_knobs;
     pub use crate::thread_local_in_memory::with_just_knobs_async;
@@ -55,6 +69,12 @@ trait JustKnobs {
     {
         Ok(get(name, switch_val)?.try_into()?)
     }
+
+    fn get_float(name: &str, switch_val: Option<&str>) -> Result<f64>;
+
+    fn get_string(name: &str, switch_val: Option<&str>) -> Result<String>;
+
+    fn get_list(name: &str, switch_val: Option<&str>) -> Result<Vec<String>>;
 }
 
 /// For open-source for now we're using a stub implementation that always returns default.
@@ -70,6 +90,18 @@ impl JustKnobs for JustKnobsStub {
     fn get(_name: &str, _switch_val: Option<&str>) -> Result<i64> {
         Ok(0)
     }
+
+    fn get_float(_name: &str, _switch_val: Option<&str>) -> Result<f64> {
+        Ok(0.0)
+    }
+
+    fn get_string(_name: &str, _switch_val: Option<&str>) -> Result<String> {
+        Ok(String::new())
+    }
+
+    fn get_list(_name: &str, _switch_val: Option<&str>) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
 }
 
 pub struct JustKnobsCombinedImpl;
@@ -80,6 +112,8 @@ impl JustKnobs for JustKnobsCombinedImpl {
             ThreadLocalInMemoryJustKnobsImpl::eval(name, hash_val, switch_val)
         } else if cached_config::in_use() {
             CachedConfigJustKnobs::eval(name, hash_val, switch_val)
+        } else if file::in_use() {
+            FileJustKnobs::eval(name, hash_val, switch_val)
         } else {
             prod_implementation::eval(name, hash_val, switch_val)
         }
@@ -90,14 +124,55 @@ impl JustKnobs for JustKnobsCombinedImpl {
             ThreadLocalInMemoryJustKnobsImpl::get(name, switch_val)
         } else if cached_config::in_use() {
             CachedConfigJustKnobs::get(name, switch_val)
+        } else if file::in_use() {
+            FileJustKnobs::get(name, switch_val)
         } else {
             prod_implementation::get(name, switch_val)
         }
     }
+
+    fn get_float(name: &str, switch_val: Option<&str>) -> Result<f64> {
+        if thread_local_in_memory::in_use() {
+            ThreadLocalInMemoryJustKnobsImpl::get_float(name, switch_val)
+        } else if cached_config::in_use() {
+            CachedConfigJustKnobs::get_float(name, switch_val)
+        } else if file::in_use() {
+            FileJustKnobs::get_float(name, switch_val)
+        } else {
+            prod_implementation::get_float(name, switch_val)
+        }
+    }
+
+    fn get_string(name: &str, switch_val: Option<&str>) -> Result<String> {
+        if thread_local_in_memory::in_use() {
+            ThreadLocalInMemoryJustKnobsImpl::get_string(name, switch_val)
+        } else if cached_config::in_use() {
+            CachedConfigJustKnobs::get_string(name, switch_val)
+        } else if file::in_use() {
+            FileJustKnobs::get_string(name, switch_val)
+        } else {
+            prod_implementation::get_string(name, switch_val)
+        }
+    }
+
+    fn get_list(name: &str, switch_val: Option<&str>) -> Result<Vec<String>> {
+        if thread_local_in_memory::in_use() {
+            ThreadLocalInMemoryJustKnobsImpl::get_list(name, switch_val)
+        } else if cached_config::in_use() {
+            CachedConfigJustKnobs::get_list(name, switch_val)
+        } else if file::in_use() {
+            FileJustKnobs::get_list(name, switch_val)
+        } else {
+            prod_implementation::get_list(name, switch_val)
+        }
+    }
 }
 
 /// Evaluate a Boolean knob.
 ///
+/// Knobs rolled out to a percentage are consistently true or false for a
+/// given `hash_val`, and the percentage may be overridden per `switch_val`.
+///
 /// Do not use `unwrap_or` or otherwise ignore errors on the `Result` that is
 /// returned.  If this function returns an error then that may indicate a real
 /// problem (e.g. the JK has not been created or has been misspelled).
@@ -132,3 +207,33 @@ where
 {
     JustKnobsCombinedImpl::get_as(name, switch_val)
 }
+
+/// Evaluate a float knob.
+///
+/// Do not use `unwrap_or` or otherwise ignore errors on the `Result` that is
+/// returned.  If this function returns an error then that may indicate a real
+/// problem (e.g. the JK has not been created, has been misspelled or has
+/// another type).
+pub fn get_float(name: &str, switch_val: Option<&str>) -> Result<f64> {
+    JustKnobsCombinedImpl::get_float(name, switch_val)
+}
+
+/// Evaluate a string knob.
+///
+/// Do not use `unwrap_or` or otherwise ignore errors on the `Result` that is
+/// returned.  If this function returns an error then that may indicate a real
+/// problem (e.g. the JK has not been created, has been misspelled or has
+/// another type).
+pub fn get_string(name: &str, switch_val: Option<&str>) -> Result<String> {
+    JustKnobsCombinedImpl::get_string(name, switch_val)
+}
+
+/// Evaluate a knob holding a list of strings.
+///
+/// Do not use `unwrap_or` or otherwise ignore errors on the `Result` that is
+/// returned.  If this function returns an error then that may indicate a real
+/// problem (e.g. the JK has not been created, has been misspelled or has
+/// another type).
+pub fn get_list(name: &str, switch_val: Option<&str>) -> Result<Vec<String>> {
+    JustKnobsCombinedImpl::get_list(name, switch_val)
+}
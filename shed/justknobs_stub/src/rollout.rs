@@ -0,0 +1,161 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+/// Bool knobs rolled out to a percentage of hash values, shared by the
+/// cached_config and thread-local in-memory implementations.
+use std::collections::HashMap;
+
+use just_knobs_struct::JustKnobRollout;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Number of buckets hash values are spread over, allowing percentages with
+/// a precision of 0.0001%.
+const BUCKETS: u64 = 1_000_000;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct KnobRollout {
+    /// Percentage of hash values, between 0 and 100, for which the knob is
+    /// true.
+    pub percentage: f64,
+    /// Percentages overriding `percentage` for the given switch values.
+    #[serde(default)]
+    pub switch_values: HashMap<String, f64>,
+}
+
+impl KnobRollout {
+    pub fn new(percentage: f64) -> Self {
+        Self {
+            percentage,
+            switch_values: HashMap::new(),
+        }
+    }
+
+    /// Use `percentage` instead of the default one when evaluated for
+    /// `switch_val`.
+    pub fn with_switch_value(mut self, switch_val: impl Into<String>, percentage: f64) -> Self {
+        self.switch_values.insert(switch_val.into(), percentage);
+        self
+    }
+
+    /// The result is always the same for a given `name` and `hash_val`, and
+    /// rolling out to a higher percentage keeps the knob true for all the hash
+    /// values it was true for before. The knob name is part of the hash, so
+    /// that knobs at the same percentage aren't enabled for the same hash
+    /// values. Without `hash_val` the result is random.
+    pub fn eval(&self, name: &str, hash_val: Option<&str>, switch_val: Option<&str>) -> bool {
+        let percentage = switch_val
+            .and_then(|switch_val| self.switch_values.get(switch_val))
+            .copied()
+            .unwrap_or(self.percentage);
+        if percentage <= 0.0 {
+            return false;
+        }
+        if percentage >= 100.0 {
+            return true;
+        }
+
+        let bucket = match hash_val {
+            Some(hash_val) => stable_hash(name, hash_val) % BUCKETS,
+            None => rand::random::<u64>() % BUCKETS,
+        };
+        (bucket as f64) < percentage / 100.0 * BUCKETS as f64
+    }
+}
+
+impl From<&JustKnobRollout> for KnobRollout {
+    fn from(rollout: &JustKnobRollout) -> Self {
+        Self {
+            percentage: rollout.percentage,
+            switch_values: rollout.switch_values.clone(),
+        }
+    }
+}
+
+/// 64-bit FNV-1a, which unlike the std hashers is guaranteed to give the same
+/// result across processes and releases.
+fn stable_hash(name: &str, hash_val: &str) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    name.as_bytes()
+        .iter()
+        .chain(&[0])
+        .chain(hash_val.as_bytes())
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn enabled_count(rollout: &KnobRollout, name: &str, switch_val: Option<&str>) -> usize {
+        (0..10000)
+            .filter(|i| rollout.eval(name, Some(&i.to_string()), switch_val))
+            .count()
+    }
+
+    #[test]
+    fn test_rollout_percentage() {
+        assert_eq!(
+            enabled_count(&KnobRollout::new(0.0), "my/config:knob", None),
+            0
+        );
+        assert_eq!(
+            enabled_count(&KnobRollout::new(100.0), "my/config:knob", None),
+            10000
+        );
+
+        let count = enabled_count(&KnobRollout::new(25.0), "my/config:knob", None);
+        assert!((2300..2700).contains(&count), "{}", count);
+    }
+
+    #[test]
+    fn test_rollout_is_deterministic() {
+        let rollout = KnobRollout::new(50.0);
+        for i in 0..100 {
+            let hash_val = i.to_string();
+            let first = rollout.eval("my/config:knob", Some(&hash_val), None);
+            assert_eq!(rollout.eval("my/config:knob", Some(&hash_val), None), first);
+            // Increasing the percentage never disables the knob
+            if first {
+                assert!(KnobRollout::new(60.0).eval("my/config:knob", Some(&hash_val), None));
+            }
+        }
+
+        // Different knobs are enabled for different hash values
+        let enabled = |name: &str| -> Vec<bool> {
+            (0..100)
+                .map(|i| rollout.eval(name, Some(&i.to_string()), None))
+                .collect()
+        };
+        assert_ne!(enabled("my/config:knob1"), enabled("my/config:knob2"));
+    }
+
+    #[test]
+    fn test_rollout_switch_values() {
+        let rollout = KnobRollout::new(50.0)
+            .with_switch_value("region1", 0.0)
+            .with_switch_value("region2", 100.0);
+
+        assert_eq!(
+            enabled_count(&rollout, "my/config:knob", Some("region1")),
+            0
+        );
+        assert_eq!(
+            enabled_count(&rollout, "my/config:knob", Some("region2")),
+            10000
+        );
+        let count = enabled_count(&rollout, "my/config:knob", Some("region3"));
+        assert!((4700..5300).contains(&count), "{}", count);
+    }
+}
@@ -24,6 +24,8 @@ use futures::future::poll_fn;
 use just_knobs_struct::JustKnobs as JustKnobsStruct;
 
 use crate::JustKnobs;
+use crate::cached_config::KnobVal;
+use crate::cached_config::knob_vals;
 
 thread_local! {
     static JUST_KNOBS: RefCell<Option<Arc<JustKnobsInMemory>>> = Default::default()
@@ -47,59 +49,56 @@ impl JustKnobsInMemory {
 
 impl From<&JustKnobsStruct> for JustKnobsInMemory {
     fn from(jk: &JustKnobsStruct) -> Self {
-        Self(
-            jk.bools
-                .iter()
-                .map(|(k, v)| (k.clone(), KnobVal::Bool(*v)))
-                .chain(jk.ints.iter().map(|(k, v)| (k.clone(), KnobVal::Int(*v))))
-                .collect(),
-        )
+        Self(knob_vals(jk))
     }
 }
 
-#[derive(Copy, Clone)]
-pub enum KnobVal {
-    Bool(bool),
-    Int(i64),
+fn get_knob(name: &str, expected_type: &str) -> Result<KnobVal> {
+    JUST_KNOBS.with(|jk| match jk.borrow().deref() {
+        Some(jk) => {
+            jk.0.get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("Missing just knobs {}: {}", expected_type, name))
+        }
+        None => bail!("Thread local JUST_KNOBS is not set"),
+    })
 }
 
 pub(crate) struct ThreadLocalInMemoryJustKnobsImpl;
 impl JustKnobs for ThreadLocalInMemoryJustKnobsImpl {
-    fn eval(name: &str, _hash_val: Option<&str>, _switch_val: Option<&str>) -> Result<bool> {
-        let value = JUST_KNOBS.with(|jk| match jk.borrow().deref() {
-            Some(jk) => {
-                jk.0.get(name)
-                    .copied()
-                    .ok_or_else(|| anyhow!("Missing just knobs bool: {}", name))
-            }
-            None => bail!("Thread local JUST_KNOBS is not set"),
-        })?;
-
-        match value {
-            KnobVal::Int(_v) => Err(anyhow!(
-                "JustKnobs knob {} has type int while expected bool",
-                name,
-            )),
+    fn eval(name: &str, hash_val: Option<&str>, switch_val: Option<&str>) -> Result<bool> {
+        match get_knob(name, "bool")? {
             KnobVal::Bool(b) => Ok(b),
+            KnobVal::Rollout(rollout) => Ok(rollout.eval(name, hash_val, switch_val)),
+            value => Err(value.type_mismatch(name, "bool")),
         }
     }
 
     fn get(name: &str, _switch_val: Option<&str>) -> Result<i64> {
-        let value = JUST_KNOBS.with(|jk| match jk.borrow().deref() {
-            Some(jk) => {
-                jk.0.get(name)
-                    .copied()
-                    .ok_or_else(|| anyhow!("Missing just knobs int: {}", name))
-            }
-            None => bail!("Thread local JUST_KNOBS is not set"),
-        })?;
-
-        match value {
-            KnobVal::Bool(_b) => Err(anyhow!(
-                "JustKnobs knob {} has type bool while expected int",
-                name,
-            )),
+        match get_knob(name, "int")? {
             KnobVal::Int(v) => Ok(v),
+            value => Err(value.type_mismatch(name, "int")),
+        }
+    }
+
+    fn get_float(name: &str, _switch_val: Option<&str>) -> Result<f64> {
+        match get_knob(name, "float")? {
+            KnobVal::Float(v) => Ok(v),
+            value => Err(value.type_mismatch(name, "float")),
+        }
+    }
+
+    fn get_string(name: &str, _switch_val: Option<&str>) -> Result<String> {
+        match get_knob(name, "string")? {
+            KnobVal::String(v) => Ok(v),
+            value => Err(value.type_mismatch(name, "string")),
+        }
+    }
+
+    fn get_list(name: &str, _switch_val: Option<&str>) -> Result<Vec<String>> {
+        match get_knob(name, "list")? {
+            KnobVal::List(v) => Ok(v),
+            value => Err(value.type_mismatch(name, "list")),
         }
     }
 }
@@ -168,6 +167,7 @@ mod test {
     use maplit::hashmap;
 
     use super::*;
+    use crate::rollout::KnobRollout;
 
     #[test]
     fn test_with_just_knobs() {
@@ -331,6 +331,113 @@ mod test {
         .await;
     }
 
+    #[test]
+    fn test_rollout_just_knobs() {
+        let just_knobs = JustKnobsInMemory::from_json(
+            r#"{
+                "bools": {"my/config:knob1": true},
+                "rollouts": {
+                    "my/config:knob2": {
+                        "percentage": 0.0,
+                        "switch_values": {"region1": 100.0}
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        with_just_knobs(just_knobs, || {
+            assert!(
+                ThreadLocalInMemoryJustKnobsImpl::eval("my/config:knob1", Some("repo"), None)
+                    .unwrap()
+            );
+            assert!(
+                !ThreadLocalInMemoryJustKnobsImpl::eval("my/config:knob2", Some("repo"), None)
+                    .unwrap()
+            );
+            assert!(
+                ThreadLocalInMemoryJustKnobsImpl::eval(
+                    "my/config:knob2",
+                    Some("repo"),
+                    Some("region1")
+                )
+                .unwrap()
+            );
+            assert!(ThreadLocalInMemoryJustKnobsImpl::get("my/config:knob2", None).is_err());
+        });
+
+        with_just_knobs(
+            JustKnobsInMemory::new(hashmap! {
+                "my/config:knob3".to_string() => KnobVal::Rollout(KnobRollout::new(50.0)),
+            }),
+            || {
+                let enabled = (0..1000)
+                    .filter(|i| {
+                        ThreadLocalInMemoryJustKnobsImpl::eval(
+                            "my/config:knob3",
+                            Some(&i.to_string()),
+                            None,
+                        )
+                        .unwrap()
+                    })
+                    .count();
+                assert!((400..600).contains(&enabled), "{}", enabled);
+            },
+        );
+    }
+
+    #[test]
+    fn test_typed_just_knobs() {
+        let just_knobs = JustKnobsInMemory::from_json(
+            r#"{
+                "strings": {"my/config:knob1": "^foo.*$"},
+                "floats": {"my/config:knob2": 0.5},
+                "lists": {"my/config:knob3": ["a", "b"]}
+            }"#,
+        )
+        .unwrap();
+
+        with_just_knobs(just_knobs, || {
+            assert_eq!(
+                ThreadLocalInMemoryJustKnobsImpl::get_string("my/config:knob1", None).unwrap(),
+                "^foo.*$"
+            );
+            assert_eq!(
+                ThreadLocalInMemoryJustKnobsImpl::get_float("my/config:knob2", None).unwrap(),
+                0.5
+            );
+            assert_eq!(
+                ThreadLocalInMemoryJustKnobsImpl::get_list("my/config:knob3", None).unwrap(),
+                vec!["a".to_string(), "b".to_string()]
+            );
+
+            assert_eq!(
+                ThreadLocalInMemoryJustKnobsImpl::get("my/config:knob1", None)
+                    .unwrap_err()
+                    .to_string(),
+                "JustKnobs knob my/config:knob1 has type string while expected int"
+            );
+            assert_eq!(
+                ThreadLocalInMemoryJustKnobsImpl::get_string("my/config:knob2", None)
+                    .unwrap_err()
+                    .to_string(),
+                "JustKnobs knob my/config:knob2 has type float while expected string"
+            );
+            assert_eq!(
+                ThreadLocalInMemoryJustKnobsImpl::eval("my/config:knob3", None, None)
+                    .unwrap_err()
+                    .to_string(),
+                "JustKnobs knob my/config:knob3 has type list while expected bool"
+            );
+            assert_eq!(
+                ThreadLocalInMemoryJustKnobsImpl::get_list("my/config:knob4", None)
+                    .unwrap_err()
+                    .to_string(),
+                "Missing just knobs list: my/config:knob4"
+            );
+        });
+    }
+
     #[test]
     fn test_override_just_knobs() {
         override_just_knobs(JustKnobsInMemory::new(hashmap! {
@@ -14,14 +14,18 @@
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
 
 pub mod mysql;
+pub mod postgres;
+pub mod read_policy;
 pub mod sqlite;
 pub mod transaction;
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Debug;
 
+use mysql_async::FromValueError;
 use mysql_async::Value;
 use mysql_async::prelude::FromValue;
+use thiserror::Error;
 use vec1::Vec1;
 
 /// Struct to store a set of write, read and read-only connections for a shard.
@@ -80,7 +84,7 @@ impl From<Vec1<SqlConnections>> for SqlShardedConnections {
     }
 }
 
-/// Enum that generalizes over connections to Sqlite and MyRouter.
+/// Enum that generalizes over connections to Sqlite, MyRouter and Postgres.
 #[derive(Clone)]
 pub enum Connection {
     /// Sqlite lets you use this crate with rusqlite connections such as in memory or on disk Sqlite
@@ -90,6 +94,8 @@ pub enum Connection {
     Mysql(mysql::Connection),
     /// For use in external Mysql DBs
     OssMysql(mysql::OssConnection),
+    /// Pooled connections to a Postgres database
+    Postgres(postgres::PostgresConnection),
 }
 
 impl From<sqlite::SqliteMultithreaded> for Connection {
@@ -110,12 +116,19 @@ impl From<mysql::OssConnection> for Connection {
     }
 }
 
+impl From<postgres::PostgresConnection> for Connection {
+    fn from(conn: postgres::PostgresConnection) -> Self {
+        Connection::Postgres(conn)
+    }
+}
+
 impl Debug for Connection {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Connection::Sqlite(..) => write!(f, "Sqlite"),
             Connection::Mysql(..) => write!(f, "Meta internal Mysql client"),
             Connection::OssMysql(..) => write!(f, "AWS compatible Mysql client"),
+            Connection::Postgres(..) => write!(f, "Postgres"),
         }
     }
 }
@@ -164,6 +177,44 @@ impl WriteResult {
     }
 }
 
+/// Error returned by a `read` query when a column of a returned row can't be
+/// converted into the type declared by the query, e.g. because of an
+/// unexpected NULL or a change of the column type in the schema.
+#[derive(Debug, Error)]
+#[error(
+    "Failed to parse column {column} of query {query} as `{expected_type}`, got value {value:?}"
+)]
+pub struct RowDecodeError {
+    /// Name of the query
+    pub query: &'static str,
+    /// Index of the column in the row, starting at 0
+    pub column: usize,
+    /// Rust type the column should have been converted into
+    pub expected_type: &'static str,
+    /// Value that couldn't be converted
+    pub value: Value,
+}
+
+impl RowDecodeError {
+    /// Method made public for access from inside macros, you probably don't want to use it.
+    ///
+    /// Convert `value`, read from `column` of a row returned by `query`, into
+    /// `T`, whose name as written in the query is `expected_type`.
+    pub fn decode<T: FromValue>(
+        query: &'static str,
+        column: usize,
+        expected_type: &'static str,
+        value: Value,
+    ) -> Result<T, RowDecodeError> {
+        T::from_value_opt(value).map_err(|FromValueError(value)| RowDecodeError {
+            query,
+            column,
+            expected_type,
+            value,
+        })
+    }
+}
+
 /// Telemetry returned after a query is executed or transaction is committed.
 #[derive(Debug, Clone)]
 pub enum QueryTelemetry {
@@ -172,6 +223,8 @@ pub enum QueryTelemetry {
     MySQL(mysql::MysqlQueryTelemetry),
     /// OSS MySQL
     OssMySQL(mysql::OssQueryTelemetry),
+    /// Queries run by `mysql::OssConnection`
+    OssConnection(mysql::OssMysqlQueryTelemetry),
     /// Sqlite telemetry to be used in tests
     Sqlite(sqlite::SqliteQueryTelemetry),
 }
@@ -189,6 +242,12 @@ impl From<HashMap<String, String>> for QueryTelemetry {
     }
 }
 
+impl From<mysql::OssMysqlQueryTelemetry> for QueryTelemetry {
+    fn from(telemetry: mysql::OssMysqlQueryTelemetry) -> Self {
+        QueryTelemetry::OssConnection(telemetry)
+    }
+}
+
 impl From<sqlite::SqliteQueryTelemetry> for QueryTelemetry {
     fn from(telemetry: sqlite::SqliteQueryTelemetry) -> Self {
         QueryTelemetry::Sqlite(telemetry)
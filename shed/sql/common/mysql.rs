@@ -36,8 +36,13 @@ pub use mysql_stub::Connection;
 pub use mysql_stub::MysqlError;
 #[cfg(not(fbcode_build))]
 pub use mysql_stub::Transaction;
+pub use ossmysql_wrapper::MAX_PREPARED_STATEMENT_PARAMS;
 pub use ossmysql_wrapper::OssConnection;
+pub use ossmysql_wrapper::OssMysqlQueryTelemetry;
+pub use ossmysql_wrapper::OssMysqlWarning;
+pub use ossmysql_wrapper::OssQuery;
 pub use ossmysql_wrapper::OssQueryTelemetry;
+pub use ossmysql_wrapper::order_params;
 use stats::prelude::*;
 
 use super::WriteResult as SqlWriteResult;
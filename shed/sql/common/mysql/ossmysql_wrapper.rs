@@ -13,20 +13,25 @@
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Error;
 use futures_stats::futures03::TimedFutureExt;
 use mysql_async::Conn as MysqlConnection;
 use mysql_async::Pool;
 use mysql_async::QueryResult as MysqlQueryResult;
+use mysql_async::Row;
 use mysql_async::TextProtocol;
 use mysql_async::Transaction;
 use mysql_async::TxOpts;
+use mysql_async::Value;
+use mysql_async::prelude::Protocol;
 use mysql_async::prelude::Queryable;
 use stats::prelude::*;
 use time_ext::DurationExt;
 
 use crate::QueryTelemetry;
+use crate::WriteResult as SqlWriteResult;
 use crate::mysql::ConnectionStats;
 use crate::mysql::WriteResult;
 
@@ -35,6 +40,94 @@ type QueryResult<'a> = MysqlQueryResult<'a, 'static, TextProtocol>;
 /// Telemetry returned after a query is executed or transaction is committed.
 pub type OssQueryTelemetry = HashMap<String, String>;
 
+/// Telemetry of a query run by `OssConnection`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OssMysqlQueryTelemetry {
+    /// Id of the server connection that ran the query.
+    pub connection_id: u32,
+    /// Time taken to get the connection from the pool, `None` for queries
+    /// run in a transaction.
+    pub get_connection_duration: Option<Duration>,
+    /// Time taken by the server to run the query and start sending its
+    /// result.
+    pub query_duration: Duration,
+    /// Rows examined by the query, as far as the server reports them to the
+    /// client, i.e. the rows matched by an UPDATE. `None` for other queries.
+    pub rows_examined: Option<u64>,
+    /// Rows returned by the query.
+    pub rows_returned: u64,
+    /// Rows inserted, updated or deleted by the query.
+    pub rows_affected: u64,
+    /// Warnings raised by the query, empty if they couldn't be read.
+    pub warnings: Vec<OssMysqlWarning>,
+}
+
+/// Warning raised by the server when running a query, as listed by
+/// `SHOW WARNINGS`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OssMysqlWarning {
+    /// Level of the warning: Note, Warning or Error
+    pub level: String,
+    /// MySQL error code
+    pub code: u32,
+    /// Description of the warning
+    pub message: String,
+}
+
+/// Maximum number of parameters of a prepared statement. Queries binding more
+/// parameters are run as text SQL instead.
+pub const MAX_PREPARED_STATEMENT_PARAMS: usize = u16::MAX as usize;
+
+/// SQL run by `OssConnection`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OssQuery {
+    /// SQL with the values of its parameters escaped into it, run with the
+    /// text protocol.
+    Text(String),
+    /// SQL with `?` placeholders for the given parameters, run as a
+    /// server-side prepared statement with the binary protocol.
+    Prepared(String, Vec<Value>),
+}
+
+impl OssQuery {
+    /// The SQL of this query.
+    pub fn sql_mut(&mut self) -> &mut String {
+        match self {
+            OssQuery::Text(sql) | OssQuery::Prepared(sql, _) => sql,
+        }
+    }
+}
+
+impl From<String> for OssQuery {
+    fn from(sql: String) -> Self {
+        OssQuery::Text(sql)
+    }
+}
+
+/// Order the values bound to the named arguments of `format`, a `format!`
+/// string whose arguments were replaced by `?` placeholders, as the
+/// placeholders of the formatted SQL. Arguments used several times are bound
+/// as many times, and arguments without values are ignored.
+#[doc(hidden)]
+pub fn order_params(format: &str, named: &[(&str, Vec<Value>)]) -> Vec<Value> {
+    let mut params = Vec::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        if let Some(escaped) = rest.strip_prefix('{') {
+            rest = escaped;
+            continue;
+        }
+        let end = rest.find('}').unwrap_or(rest.len());
+        let name = rest[..end].split(':').next().unwrap_or_default().trim();
+        if let Some((_, values)) = named.iter().find(|(arg, _)| *arg == name) {
+            params.extend_from_slice(values);
+        }
+        rest = &rest[end..];
+    }
+    params
+}
+
 /// OssConnection is a wrapper around a MySQL async Pool
 /// It provides read/write query and begin transaction API.
 #[derive(Clone)]
@@ -43,12 +136,40 @@ pub struct OssConnection {
     pub pool: Pool,
     /// Stats struct for logging performance
     pub stats: Arc<ConnectionStats>,
+    prepared_statements: bool,
 }
 
 impl OssConnection {
     /// Creates OssConnection from a Pool object
     pub fn new(pool: Pool, stats: Arc<ConnectionStats>) -> Self {
-        Self { pool, stats }
+        Self {
+            pool,
+            stats,
+            prepared_statements: false,
+        }
+    }
+
+    /// Run the queries of `queries!` as server-side prepared statements, see
+    /// `prepared_statements`.
+    pub fn with_prepared_statements(self) -> Self {
+        Self {
+            prepared_statements: true,
+            ..self
+        }
+    }
+
+    /// Whether the queries of `queries!` run as server-side prepared
+    /// statements with bound parameters rather than as text SQL. Queries in
+    /// transactions always run as text SQL.
+    ///
+    /// Prepared statements are cached per connection by the pool, up to
+    /// `OptsBuilder::stmt_cache_size` statements, keyed by their SQL. Each
+    /// length of a `>list` parameter, each number of `values` rows and each
+    /// comment passed to `commented_query` is a distinct statement, so
+    /// comments that vary between calls, e.g. request ids, evict the cached
+    /// statements of other queries.
+    pub fn prepared_statements(&self) -> bool {
+        self.prepared_statements
     }
 
     /// Checks out a connection from the pool while collecting stats
@@ -76,6 +197,68 @@ impl OssConnection {
         result
     }
 
+    /// Checks out a connection from the pool, performs a given query and
+    /// returns its rows mapped with `row_fn`, along with the query telemetry.
+    pub async fn read_query_with_telemetry<T, F>(
+        &self,
+        query: impl Into<OssQuery>,
+        row_fn: F,
+    ) -> Result<(Vec<T>, OssMysqlQueryTelemetry), Error>
+    where
+        F: FnMut(Row) -> T + Send,
+        T: Send + 'static,
+    {
+        let (st, conn) = OssConnection::get_conn_counted(self.pool.clone(), &self.stats)
+            .timed()
+            .await;
+        let mut conn = conn?;
+        let connection_id = conn.id();
+        let (rows, _, mut telemetry) = query_with_telemetry(
+            &mut conn,
+            connection_id,
+            Some(&self.stats),
+            query.into(),
+            row_fn,
+        )
+        .await?;
+        telemetry.get_connection_duration = Some(st.completion_time);
+        Ok((rows, telemetry))
+    }
+
+    /// Performs a given query in a transaction started by `begin_transaction`,
+    /// and returns its rows mapped with `row_fn` along with the query
+    /// telemetry.
+    pub async fn transaction_read_query<T, F>(
+        transaction: &mut Transaction<'static>,
+        query: impl Into<OssQuery>,
+        row_fn: F,
+    ) -> Result<(Vec<T>, OssMysqlQueryTelemetry), Error>
+    where
+        F: FnMut(Row) -> T + Send,
+        T: Send + 'static,
+    {
+        let connection_id = transaction.id();
+        let (rows, _, telemetry) =
+            query_with_telemetry(transaction, connection_id, None, query.into(), row_fn).await?;
+        Ok((rows, telemetry))
+    }
+
+    /// Performs a given query in a transaction started by `begin_transaction`,
+    /// and returns the write result.
+    pub async fn transaction_write_query(
+        transaction: &mut Transaction<'static>,
+        query: impl Into<OssQuery>,
+    ) -> Result<SqlWriteResult, Error> {
+        let connection_id = transaction.id();
+        let (_, last_insert_id, telemetry) =
+            query_with_telemetry(transaction, connection_id, None, query.into(), drop).await?;
+        Ok(SqlWriteResult::new(
+            last_insert_id,
+            telemetry.rows_affected,
+            Some(telemetry.into()),
+        ))
+    }
+
     /// Performs a given query and returns the result as a QueryResult
     pub async fn read_query<'a>(
         &self,
@@ -87,13 +270,41 @@ impl OssConnection {
     }
 
     /// Performs a given query and returns the write result.
-    pub async fn write_query(&self, query: String) -> Result<WriteResult, Error> {
-        let mut conn = OssConnection::get_conn_counted(self.pool.clone(), &self.stats).await?;
-        let result = OssConnection::raw_query_counted(&mut conn, &self.stats, &query).await?;
+    pub async fn write_query(&self, query: impl Into<OssQuery>) -> Result<WriteResult, Error> {
+        let result = self.write_query_with_telemetry(query).await?;
+        Ok(WriteResult::new(
+            result.last_insert_id().unwrap_or(0),
+            result.affected_rows(),
+            None,
+        ))
+    }
+
+    /// Checks out a connection from the pool, performs a given query and
+    /// returns the write result, along with the query telemetry.
+    pub async fn write_query_with_telemetry(
+        &self,
+        query: impl Into<OssQuery>,
+    ) -> Result<SqlWriteResult, Error> {
+        let (st, conn) = OssConnection::get_conn_counted(self.pool.clone(), &self.stats)
+            .timed()
+            .await;
+        let mut conn = conn?;
+        let connection_id = conn.id();
+        let (_, last_insert_id, mut telemetry) = query_with_telemetry(
+            &mut conn,
+            connection_id,
+            Some(&self.stats),
+            query.into(),
+            drop,
+        )
+        .await?;
+        telemetry.get_connection_duration = Some(st.completion_time);
 
-        let last_insert_id = result.last_insert_id().unwrap_or(0);
-        let rows_affected = result.affected_rows();
-        Ok(WriteResult::new(last_insert_id, rows_affected, None))
+        Ok(SqlWriteResult::new(
+            Some(last_insert_id.unwrap_or(0)),
+            telemetry.rows_affected,
+            Some(telemetry.into()),
+        ))
     }
 
     /// Begins transaction and returns Transaction object.
@@ -103,3 +314,126 @@ impl OssConnection {
         Ok(tr)
     }
 }
+
+async fn query_with_telemetry<C, T, F>(
+    conn: &mut C,
+    connection_id: u32,
+    stats: Option<&ConnectionStats>,
+    query: OssQuery,
+    row_fn: F,
+) -> Result<(Vec<T>, Option<u64>, OssMysqlQueryTelemetry), Error>
+where
+    C: Queryable,
+    F: FnMut(Row) -> T + Send,
+    T: Send + 'static,
+{
+    let (st, summary) = match query {
+        OssQuery::Text(sql) => {
+            let (st, result) = conn.query_iter(sql).timed().await;
+            (st, read_result(result, row_fn).await)
+        }
+        OssQuery::Prepared(sql, params) => {
+            let (st, result) = conn.exec_iter(sql, params).timed().await;
+            (st, read_result(result, row_fn).await)
+        }
+    };
+    if let Some(stats) = stats {
+        stats
+            .raw_query_ms
+            .add_value(st.completion_time.as_millis_unchecked() as i64);
+    }
+    let (rows, last_insert_id, rows_affected, rows_examined, warning_count) = summary?;
+
+    let warnings = if warning_count > 0 {
+        // The query has already run, so failing to list its warnings only
+        // leaves them out of the telemetry.
+        conn.query_map("SHOW WARNINGS", |(level, code, message)| OssMysqlWarning {
+            level,
+            code,
+            message,
+        })
+        .await
+        .unwrap_or_else(|e| {
+            tracing::warn!(
+                connection_id,
+                "Failed to read the warnings of a query: {e:#}"
+            );
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+
+    let telemetry = OssMysqlQueryTelemetry {
+        connection_id,
+        get_connection_duration: None,
+        query_duration: st.completion_time,
+        rows_examined,
+        rows_returned: rows.len() as u64,
+        rows_affected,
+        warnings,
+    };
+    Ok((rows, last_insert_id, telemetry))
+}
+
+async fn read_result<P, T, F>(
+    result: Result<MysqlQueryResult<'_, 'static, P>, mysql_async::Error>,
+    row_fn: F,
+) -> Result<(Vec<T>, Option<u64>, u64, Option<u64>, u16), mysql_async::Error>
+where
+    P: Protocol,
+    F: FnMut(Row) -> T + Send,
+    T: Send + 'static,
+{
+    let mut result = result?;
+    let rows = result.map(row_fn).await?;
+    let last_insert_id = result.last_insert_id();
+    let rows_affected = result.affected_rows();
+    let rows_examined = rows_matched(&result.info());
+    let warning_count = result.warnings();
+    result.drop_result().await?;
+    Ok((
+        rows,
+        last_insert_id,
+        rows_affected,
+        rows_examined,
+        warning_count,
+    ))
+}
+
+/// Parse the rows matched from the info of an UPDATE, e.g.
+/// "Rows matched: 1  Changed: 1  Warnings: 0".
+fn rows_matched(info: &str) -> Option<u64> {
+    info.strip_prefix("Rows matched:")?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rows_matched() {
+        assert_eq!(
+            rows_matched("Rows matched: 12  Changed: 3  Warnings: 0"),
+            Some(12)
+        );
+        assert_eq!(rows_matched("Records: 2  Duplicates: 0  Warnings: 0"), None);
+        assert_eq!(rows_matched(""), None);
+    }
+
+    #[test]
+    fn test_order_params() {
+        let named = [
+            ("a", vec![Value::Int(1)]),
+            ("list", vec![Value::Int(2), Value::Int(3)]),
+        ];
+        assert_eq!(
+            order_params("{{a}} {list} {b} {a:?} { a }", &named),
+            vec![Value::Int(2), Value::Int(3), Value::Int(1), Value::Int(1)]
+        );
+    }
+}
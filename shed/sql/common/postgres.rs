@@ -0,0 +1,388 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+//! Module containing the Postgres connection, backed by a deadpool pool of
+//! tokio-postgres clients.
+
+use std::error::Error as StdError;
+use std::pin::pin;
+
+use anyhow::Error;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::NaiveTime;
+use chrono::Timelike;
+use chrono::Utc;
+pub use deadpool_postgres;
+use deadpool_postgres::ClientWrapper;
+use deadpool_postgres::Manager;
+use deadpool_postgres::ManagerConfig;
+use deadpool_postgres::Object;
+use deadpool_postgres::Pool;
+use deadpool_postgres::RecyclingMethod;
+use futures::TryStreamExt;
+use mysql_async::Value;
+pub use tokio_postgres;
+use tokio_postgres::NoTls;
+use tokio_postgres::RowStream;
+use tokio_postgres::types::FromSql;
+use tokio_postgres::types::IsNull;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::types::Type;
+use tokio_postgres::types::private::BytesMut;
+use tokio_postgres::types::to_sql_checked;
+
+use crate::WriteResult;
+
+type BoxedError = Box<dyn StdError + Sync + Send>;
+
+impl crate::Connection {
+    /// Given a pool of Postgres clients create a connection that might be used
+    /// by this crate.
+    pub fn with_postgres(pool: Pool) -> Self {
+        PostgresConnection::new(pool).into()
+    }
+}
+
+/// How the statement of a query is prepared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatementCaching {
+    /// The statement is prepared once per client and reused by later queries
+    /// with the same text, which suits queries whose text never changes.
+    Cached,
+    /// The statement is prepared for this query only, so that queries whose
+    /// text changes between calls, e.g. because of a comment or of the length
+    /// of a list, don't fill the statement cache of every client.
+    Uncached,
+}
+
+/// Wrapper around MySql Value to bind it as a parameter of a Postgres query
+/// and to read it from a row, so that the types used with the `queries!`
+/// macro work with Postgres too.
+/// This should never be used directly, it is made public so that internal macros can make use of it
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct PostgresValue(pub Value);
+
+impl ToSql for PostgresValue {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxedError> {
+        match &self.0 {
+            Value::NULL => Ok(IsNull::Yes),
+            Value::Int(v) => int_to_sql(*v, ty, out),
+            Value::UInt(v) => int_to_sql(i64::try_from(*v)?, ty, out),
+            Value::Float(v) => float_to_sql(f64::from(*v), ty, out),
+            Value::Double(v) => float_to_sql(*v, ty, out),
+            Value::Bytes(v) if *ty == Type::BYTEA => v.as_slice().to_sql(ty, out),
+            Value::Bytes(v) if <&str as ToSql>::accepts(ty) => {
+                std::str::from_utf8(v)?.to_sql(ty, out)
+            }
+            Value::Date(year, month, day, hour, min, sec, micro) => {
+                let datetime =
+                    NaiveDate::from_ymd_opt((*year).into(), (*month).into(), (*day).into())
+                        .and_then(|date| {
+                            date.and_hms_micro_opt(
+                                (*hour).into(),
+                                (*min).into(),
+                                (*sec).into(),
+                                *micro,
+                            )
+                        })
+                        .ok_or_else(|| format!("Invalid date {:?}", self.0))?;
+                match *ty {
+                    Type::TIMESTAMP => datetime.to_sql(ty, out),
+                    Type::TIMESTAMPTZ => datetime.and_utc().to_sql(ty, out),
+                    Type::DATE => datetime.date().to_sql(ty, out),
+                    _ if <&str as ToSql>::accepts(ty) => datetime
+                        .format("%Y-%m-%d %H:%M:%S%.6f")
+                        .to_string()
+                        .to_sql(ty, out),
+                    _ => Err(unsupported_conversion(&self.0, ty)),
+                }
+            }
+            value => Err(unsupported_conversion(value, ty)),
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        // Whether the value can be converted depends on the value itself, so
+        // this is checked by `to_sql`.
+        true
+    }
+
+    to_sql_checked!();
+}
+
+fn int_to_sql(v: i64, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxedError> {
+    match *ty {
+        Type::BOOL => (v != 0).to_sql(ty, out),
+        Type::CHAR => i8::try_from(v)?.to_sql(ty, out),
+        Type::INT2 => i16::try_from(v)?.to_sql(ty, out),
+        Type::INT4 => i32::try_from(v)?.to_sql(ty, out),
+        Type::INT8 => v.to_sql(ty, out),
+        Type::OID => u32::try_from(v)?.to_sql(ty, out),
+        Type::FLOAT4 => (v as f32).to_sql(ty, out),
+        Type::FLOAT8 => (v as f64).to_sql(ty, out),
+        _ if <&str as ToSql>::accepts(ty) => v.to_string().to_sql(ty, out),
+        _ => Err(unsupported_conversion(&Value::Int(v), ty)),
+    }
+}
+
+fn float_to_sql(v: f64, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxedError> {
+    match *ty {
+        Type::FLOAT4 => (v as f32).to_sql(ty, out),
+        Type::FLOAT8 => v.to_sql(ty, out),
+        _ if <&str as ToSql>::accepts(ty) => v.to_string().to_sql(ty, out),
+        _ => Err(unsupported_conversion(&Value::Double(v), ty)),
+    }
+}
+
+fn unsupported_conversion(value: &Value, ty: &Type) -> BoxedError {
+    format!("Can't convert {:?} to Postgres type {}", value, ty).into()
+}
+
+impl<'a> FromSql<'a> for PostgresValue {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, BoxedError> {
+        let value = match *ty {
+            Type::BOOL => Value::Int(bool::from_sql(ty, raw)?.into()),
+            Type::CHAR => Value::Int(i8::from_sql(ty, raw)?.into()),
+            Type::INT2 => Value::Int(i16::from_sql(ty, raw)?.into()),
+            Type::INT4 => Value::Int(i32::from_sql(ty, raw)?.into()),
+            Type::INT8 => Value::Int(i64::from_sql(ty, raw)?),
+            Type::OID => Value::UInt(u32::from_sql(ty, raw)?.into()),
+            Type::FLOAT4 => Value::Float(f32::from_sql(ty, raw)?),
+            Type::FLOAT8 => Value::Double(f64::from_sql(ty, raw)?),
+            Type::BYTEA => Value::Bytes(raw.to_vec()),
+            Type::TIMESTAMP => date_value(NaiveDateTime::from_sql(ty, raw)?),
+            Type::TIMESTAMPTZ => date_value(DateTime::<Utc>::from_sql(ty, raw)?.naive_utc()),
+            Type::DATE => date_value(NaiveDate::from_sql(ty, raw)?.and_time(NaiveTime::MIN)),
+            _ if <&str as FromSql>::accepts(ty) => {
+                Value::Bytes(<&str as FromSql>::from_sql(ty, raw)?.as_bytes().to_vec())
+            }
+            _ => return Err(format!("Unsupported Postgres type {}", ty).into()),
+        };
+        Ok(PostgresValue(value))
+    }
+
+    fn from_sql_null(_ty: &Type) -> Result<Self, BoxedError> {
+        Ok(PostgresValue(Value::NULL))
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        // Unsupported types are reported by `from_sql`.
+        true
+    }
+}
+
+fn date_value(datetime: NaiveDateTime) -> Value {
+    Value::Date(
+        datetime.year() as u16,
+        datetime.month() as u8,
+        datetime.day() as u8,
+        datetime.hour() as u8,
+        datetime.minute() as u8,
+        datetime.second() as u8,
+        datetime.nanosecond() / 1000,
+    )
+}
+
+/// Connection to a Postgres database, checking out a client from a pool for
+/// each query.
+#[derive(Clone)]
+pub struct PostgresConnection {
+    pool: Pool,
+}
+
+impl PostgresConnection {
+    /// Create a connection from a pool of clients
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    /// Create a connection from the given config, using a new pool of up to
+    /// `max_size` clients connected without TLS.
+    pub fn from_config(config: tokio_postgres::Config, max_size: usize) -> Result<Self> {
+        let manager = Manager::from_config(
+            config,
+            NoTls,
+            ManagerConfig {
+                recycling_method: RecyclingMethod::Fast,
+            },
+        );
+        let pool = Pool::builder(manager).max_size(max_size).build()?;
+        Ok(Self::new(pool))
+    }
+
+    /// The pool used by this connection
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    /// Performs a given query and returns the values of the returned rows.
+    pub async fn read_query(
+        &self,
+        query: &str,
+        params: &[PostgresValue],
+        caching: StatementCaching,
+    ) -> Result<Vec<Vec<Value>>> {
+        let client = self.pool.get().await?;
+        read_query(&client, query, params, caching).await
+    }
+
+    /// Performs a given query and returns the write result.
+    pub async fn write_query(
+        &self,
+        query: &str,
+        params: &[PostgresValue],
+        caching: StatementCaching,
+    ) -> Result<WriteResult> {
+        let client = self.pool.get().await?;
+        write_query(&client, query, params, caching).await
+    }
+
+    /// Begins transaction and returns Transaction object.
+    pub async fn begin_transaction(&self) -> Result<PostgresTransaction> {
+        let client = self.pool.get().await?;
+        client.batch_execute("BEGIN").await?;
+        Ok(PostgresTransaction {
+            client: Some(client),
+        })
+    }
+}
+
+/// Transaction holding a client checked out from the pool until it is
+/// committed or rolled back.
+///
+/// When dropped before that, the client is closed instead of being returned to
+/// the pool, which makes the server rollback the transaction.
+pub struct PostgresTransaction {
+    client: Option<Object>,
+}
+
+impl PostgresTransaction {
+    fn client(&self) -> &Object {
+        self.client
+            .as_ref()
+            .expect("should be Some before transaction ended")
+    }
+
+    /// Performs a given query and returns the values of the returned rows.
+    pub async fn read_query(
+        &mut self,
+        query: &str,
+        params: &[PostgresValue],
+        caching: StatementCaching,
+    ) -> Result<Vec<Vec<Value>>> {
+        read_query(self.client(), query, params, caching).await
+    }
+
+    /// Performs a given query and returns the write result.
+    pub async fn write_query(
+        &mut self,
+        query: &str,
+        params: &[PostgresValue],
+        caching: StatementCaching,
+    ) -> Result<WriteResult> {
+        write_query(self.client(), query, params, caching).await
+    }
+
+    /// Commit transaction.
+    pub async fn commit(mut self) -> Result<()> {
+        self.end("COMMIT").await
+    }
+
+    /// Rollback transaction.
+    pub async fn rollback(mut self) -> Result<()> {
+        self.end("ROLLBACK").await
+    }
+
+    async fn end(&mut self, statement: &str) -> Result<()> {
+        let client = self
+            .client
+            .take()
+            .expect("Called commit or rollback after drop");
+        match client.batch_execute(statement).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                // The state of the transaction is unknown, don't reuse the
+                // client.
+                drop(Object::take(client));
+                Err(e.into())
+            }
+        }
+    }
+}
+
+impl Drop for PostgresTransaction {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            drop(Object::take(client));
+        }
+    }
+}
+
+async fn query_raw(
+    client: &ClientWrapper,
+    query: &str,
+    params: &[PostgresValue],
+    caching: StatementCaching,
+) -> Result<RowStream> {
+    let rows = match caching {
+        StatementCaching::Cached => {
+            let statement = client.prepare_cached(query).await?;
+            client.query_raw(&statement, params).await?
+        }
+        StatementCaching::Uncached => client.query_raw(query, params).await?,
+    };
+    Ok(rows)
+}
+
+async fn read_query(
+    client: &ClientWrapper,
+    query: &str,
+    params: &[PostgresValue],
+    caching: StatementCaching,
+) -> Result<Vec<Vec<Value>>> {
+    let rows = query_raw(client, query, params, caching).await?;
+    rows.map_err(Error::from)
+        .and_then(|row| async move {
+            (0..row.len())
+                .map(|idx| Ok(row.try_get::<_, PostgresValue>(idx)?.0))
+                .collect()
+        })
+        .try_collect()
+        .await
+}
+
+/// The number of affected rows is the one reported by the server, and the
+/// last insert id is the first column of the last row returned by the query,
+/// if any, e.g. using `INSERT ... RETURNING id`.
+async fn write_query(
+    client: &ClientWrapper,
+    query: &str,
+    params: &[PostgresValue],
+    caching: StatementCaching,
+) -> Result<WriteResult> {
+    let mut rows = pin!(query_raw(client, query, params, caching).await?);
+    let mut last_insert_id = None;
+    while let Some(row) = rows.try_next().await? {
+        if !row.is_empty() {
+            last_insert_id = match row.try_get::<_, PostgresValue>(0)?.0 {
+                Value::Int(id) => u64::try_from(id).ok(),
+                Value::UInt(id) => Some(id),
+                _ => None,
+            };
+        }
+    }
+    let affected_rows = rows.rows_affected().unwrap_or(0);
+    Ok(WriteResult::new(last_insert_id, affected_rows, None))
+}
@@ -0,0 +1,272 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+//! Opt-in retry and routing of read queries, see [ReadPolicy].
+
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Error;
+use anyhow::Result;
+use futures_retry::retry;
+use mysql_async::prelude::Queryable;
+use stats::prelude::*;
+
+use crate::Connection;
+use crate::SqlConnections;
+
+define_stats_struct! {
+    ReadPolicyStats("sql.read_policy.{}", label: String),
+    // Number of attempts of read queries after the first one
+    retries: timeseries(Sum, Count),
+    // Number of read queries sent to the master because the replica lagged
+    fallbacks_to_master: timeseries(Sum, Count),
+    // Number of failed measurements of the replica lag
+    lag_check_failures: timeseries(Sum, Count),
+    // Replica lag measured before routing reads
+    replica_lag_ms: quantile_stat(Average, Count; P 50, P 99; Duration::from_secs(60)),
+}
+
+impl Connection {
+    /// How far behind its master the database of this connection is, `None`
+    /// if it isn't a replica or doesn't report it. A replica that isn't
+    /// replicating is reported as `Duration::MAX` behind.
+    pub async fn replica_lag(&self) -> Result<Option<Duration>> {
+        match self {
+            Connection::Sqlite(_) | Connection::Postgres(_) => Ok(None),
+            Connection::Mysql(conn) => {
+                Ok(conn.get_replica_lag_secs().await?.map(Duration::from_secs))
+            }
+            Connection::OssMysql(conn) => {
+                let mut conn = conn.pool.get_conn().await?;
+                let status: Option<mysql_async::Row> =
+                    match conn.query_first("SHOW REPLICA STATUS").await {
+                        Ok(status) => status,
+                        // Servers older than MySQL 8.0.22 only know the old syntax.
+                        Err(_) => conn.query_first("SHOW SLAVE STATUS").await?,
+                    };
+                let Some(status) = status else {
+                    return Ok(None);
+                };
+                let lag: Option<u64> = status
+                    .get_opt("Seconds_Behind_Source")
+                    .or_else(|| status.get_opt("Seconds_Behind_Master"))
+                    .transpose()?
+                    .flatten();
+                Ok(Some(lag.map_or(Duration::MAX, Duration::from_secs)))
+            }
+        }
+    }
+}
+
+/// Whether `err` is a transient failure of a query, that may succeed if
+/// retried: a deadlock, a lock wait timeout, a busy database or a lost
+/// connection. Errors of the internal Mysql client aren't recognized, so they
+/// are never considered transient.
+pub fn is_transient_error(err: &Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(err) = cause.downcast_ref::<mysql_async::Error>() {
+            match err {
+                // ER_LOCK_WAIT_TIMEOUT, ER_LOCK_DEADLOCK
+                mysql_async::Error::Server(err) => matches!(err.code, 1205 | 1213),
+                mysql_async::Error::Io(_) => true,
+                mysql_async::Error::Driver(mysql_async::DriverError::ConnectionClosed) => true,
+                _ => false,
+            }
+        } else if let Some(err) = cause.downcast_ref::<rusqlite::Error>() {
+            matches!(
+                err.sqlite_error_code(),
+                Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+            )
+        } else if let Some(err) = cause.downcast_ref::<tokio_postgres::Error>() {
+            err.is_closed()
+                || err.code().is_some_and(|code| {
+                    *code == tokio_postgres::error::SqlState::T_R_DEADLOCK_DETECTED
+                        || *code == tokio_postgres::error::SqlState::T_R_SERIALIZATION_FAILURE
+                })
+        } else {
+            cause.downcast_ref::<std::io::Error>().is_some()
+        }
+    })
+}
+
+#[derive(Clone, Copy)]
+struct LagMeasurement {
+    measured_at: Instant,
+    lag: Option<Duration>,
+}
+
+/// Policy for running read queries on [SqlConnections]: transient failures
+/// (see [is_transient_error]) are retried with exponential backoff, and reads
+/// go to the master instead of the replica while the replica lags more than
+/// a threshold.
+///
+/// The replica lag is measured at most once per `lag_check_interval`, so a
+/// policy should be created once per [SqlConnections] and shared by cloning
+/// it.
+///
+/// # Example
+/// ```
+/// use anyhow::Error;
+/// use sql::SqlConnections;
+/// use sql::queries;
+/// use sql::read_policy::ReadPolicy;
+///
+/// queries! {
+///     read MySelect(id: u64) -> (i64) {
+///         "SELECT x FROM foo WHERE id = {id}"
+///     }
+/// }
+///
+/// async fn foo(
+///     policy: &ReadPolicy,
+///     connections: &SqlConnections,
+///     id: u64,
+/// ) -> Result<Vec<(i64,)>, Error> {
+///     policy
+///         .read(connections, |conn| async move {
+///             MySelect::query(&conn, &id).await
+///         })
+///         .await
+/// }
+/// #
+/// # fn main() {}
+/// ```
+#[derive(Clone)]
+pub struct ReadPolicy {
+    max_attempts: usize,
+    initial_interval: Duration,
+    max_interval: Duration,
+    max_replica_lag: Option<Duration>,
+    lag_check_interval: Duration,
+    lag: Arc<Mutex<Option<LagMeasurement>>>,
+    stats: Arc<ReadPolicyStats>,
+}
+
+impl ReadPolicy {
+    /// Create a policy that makes up to 3 attempts of each read, and never
+    /// falls back to the master. Its stats are reported under `label`.
+    pub fn new(label: impl Into<String>) -> Self {
+        Self {
+            max_attempts: 3,
+            initial_interval: Duration::from_millis(50),
+            max_interval: Duration::from_secs(1),
+            max_replica_lag: None,
+            lag_check_interval: Duration::from_secs(1),
+            lag: Arc::new(Mutex::new(None)),
+            stats: Arc::new(ReadPolicyStats::new(label.into())),
+        }
+    }
+
+    /// Limit the number of attempts of each read, including the first one.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Wait `initial_interval` before the first retry, doubling the wait for
+    /// each subsequent retry up to `max_interval`.
+    pub fn backoff(mut self, initial_interval: Duration, max_interval: Duration) -> Self {
+        self.initial_interval = initial_interval;
+        self.max_interval = max_interval;
+        self
+    }
+
+    /// Send reads to the master while the replica lags more than
+    /// `max_replica_lag`, measuring the lag at most once per
+    /// `lag_check_interval`. If the lag can't be measured reads stay on the
+    /// replica.
+    pub fn max_replica_lag(
+        mut self,
+        max_replica_lag: Duration,
+        lag_check_interval: Duration,
+    ) -> Self {
+        self.max_replica_lag = Some(max_replica_lag);
+        self.lag_check_interval = lag_check_interval;
+        self
+    }
+
+    /// Record a replica lag measured outside of the policy, e.g. by a health
+    /// check, which is then used instead of measuring it until
+    /// `lag_check_interval` elapses.
+    pub fn record_replica_lag(&self, lag: Option<Duration>) {
+        if let Some(lag) = lag {
+            self.stats
+                .replica_lag_ms
+                .add_value(lag.as_millis().try_into().unwrap_or(i64::MAX));
+        }
+        *self.lag.lock().expect("poisoned lock") = Some(LagMeasurement {
+            measured_at: Instant::now(),
+            lag,
+        });
+    }
+
+    /// Run the read `query` on the read connection of `connections`, or on
+    /// their read master connection if the replica lags too much, retrying
+    /// it while it fails with a transient error.
+    pub async fn read<T, F, Fut>(&self, connections: &SqlConnections, mut query: F) -> Result<T>
+    where
+        F: FnMut(Connection) -> Fut + Send,
+        Fut: Future<Output = Result<T>>,
+        T: Send + 'static,
+    {
+        let connection = if self.replica_lags(&connections.read_connection).await {
+            self.stats.fallbacks_to_master.add_value(1);
+            &connections.read_master_connection
+        } else {
+            &connections.read_connection
+        };
+
+        let (value, _attempts) = retry(
+            |attempt| {
+                if attempt > 1 {
+                    self.stats.retries.add_value(1);
+                }
+                query(connection.clone())
+            },
+            self.initial_interval,
+        )
+        .binary_exponential_backoff()
+        .max_interval(self.max_interval)
+        .max_attempts(self.max_attempts)
+        .retry_if(|_attempt, err: &Error| is_transient_error(err))
+        .await?;
+        Ok(value)
+    }
+
+    async fn replica_lags(&self, replica: &Connection) -> bool {
+        let Some(max_replica_lag) = self.max_replica_lag else {
+            return false;
+        };
+        let cached = *self.lag.lock().expect("poisoned lock");
+        let lag = match cached {
+            Some(LagMeasurement { measured_at, lag })
+                if measured_at.elapsed() < self.lag_check_interval =>
+            {
+                lag
+            }
+            _ => {
+                let lag = match replica.replica_lag().await {
+                    Ok(lag) => lag,
+                    Err(_) => {
+                        self.stats.lag_check_failures.add_value(1);
+                        None
+                    }
+                };
+                self.record_replica_lag(lag);
+                lag
+            }
+        };
+        lag.is_some_and(|lag| lag > max_replica_lag)
+    }
+}
@@ -10,25 +10,17 @@
 
 //! Module containing sqlite related structures and traits
 
-#![allow(clippy::mutex_atomic)]
-
 use std::ops::Deref;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::Condvar;
 use std::sync::Mutex;
+use std::time::Duration;
 
 use anyhow::Result;
 use async_trait::async_trait;
 use rusqlite::Connection as SqliteConnection;
 
-/// Lock to ensure that only one connection is in use for writes at a time
-/// inside the process TODO: Remove this lock, and replace by better connection
-/// handling (as SQLite will get this right if we use a single connection to
-/// each file). See T59837828
-static CONN_LOCK: Mutex<bool> = Mutex::new(true);
-
-static CONN_CONDVAR: Condvar = Condvar::new();
-
 impl crate::Connection {
     /// Given a `rusqlite::Connection` create a connection to Sqlite database that might be used
     /// by this crate.
@@ -60,6 +52,42 @@ impl crate::Connection {
         )
         .into()
     }
+
+    /// Open a pool of connections to the Sqlite database at `path`, that
+    /// serves reads from `readers` connections concurrently with the writer
+    /// connection, see `SqliteMultithreaded::open_pool`.
+    pub fn with_sqlite_pool(path: impl AsRef<Path>, readers: usize) -> Result<Self> {
+        Ok(SqliteMultithreaded::open_pool(path, readers)?.into())
+    }
+
+    /// Open a pool of connections to the Sqlite database at `path`, and add
+    /// callbacks for when operations happen.
+    pub fn with_sqlite_pool_and_callbacks(
+        path: impl AsRef<Path>,
+        readers: usize,
+        callbacks: Box<dyn SqliteCallbacks>,
+    ) -> Result<Self> {
+        Ok(SqliteMultithreaded::open_pool_with_callbacks(path, readers, callbacks)?.into())
+    }
+
+    /// Open a pool of connections to the Sqlite database at `path`, plus a
+    /// function that returns the HLC from the replica that serves reads.
+    pub fn with_sqlite_pool_hlc_provider_and_callbacks(
+        path: impl AsRef<Path>,
+        readers: usize,
+        hlc_provider: Arc<Box<SqliteHlcProvider>>,
+        callbacks: Box<dyn SqliteCallbacks>,
+    ) -> Result<Self> {
+        Ok(
+            SqliteMultithreaded::open_pool_with_sqlite_hlc_provider_and_callbacks(
+                path,
+                readers,
+                hlc_provider,
+                callbacks,
+            )?
+            .into(),
+        )
+    }
 }
 
 /// Sqlite query categorization to allow callbacks to perform different
@@ -99,6 +127,11 @@ pub trait SqliteCallbacks: Send + Sync {
 pub type SqliteHlcProvider = dyn Fn() -> i64 + Send + Sync;
 
 /// Wrapper around rusqlite connection that makes it fully thread safe (but not deadlock safe)
+///
+/// It either wraps a single connection that serves all queries, or a pool of
+/// connections to a database in WAL mode (see `open_pool`), where reads are
+/// served by the reader connections while the writer connection serves
+/// writes, schema changes and transactions.
 #[derive(Clone)]
 pub struct SqliteMultithreaded {
     inner: Arc<SqliteMultithreadedInner>,
@@ -107,11 +140,49 @@ pub struct SqliteMultithreaded {
 
 /// Shared inner part of SqliteMultithreded plus any active connection guard.
 pub struct SqliteMultithreadedInner {
-    connection: Mutex<Option<SqliteConnection>>,
-    condvar: Condvar,
+    writer: SqliteConnectionSlots,
+    readers: SqliteConnectionSlots,
     callbacks: Option<Box<dyn SqliteCallbacks>>,
 }
 
+/// Idle connections of one kind, plus a condvar to wait for one of them to
+/// be released.
+struct SqliteConnectionSlots {
+    connections: Mutex<Vec<SqliteConnection>>,
+    condvar: Condvar,
+    size: usize,
+}
+
+impl SqliteConnectionSlots {
+    fn new(connections: Vec<SqliteConnection>) -> Self {
+        Self {
+            size: connections.len(),
+            connections: Mutex::new(connections),
+            condvar: Condvar::new(),
+        }
+    }
+
+    fn take(&self) -> SqliteConnection {
+        self.condvar
+            .wait_while(
+                self.connections.lock().expect("poisoned lock"),
+                |connections| connections.is_empty(),
+            )
+            .expect("poisoned lock")
+            .pop()
+            .expect("connection should not be empty")
+    }
+
+    fn put(&self, connection: SqliteConnection) {
+        self.connections
+            .lock()
+            .expect("poisoned lock")
+            .push(connection);
+        // notify others that wait for a connection
+        self.condvar.notify_one();
+    }
+}
+
 /// Guard containing an active connection.
 ///
 /// When this guard is destroyed, the connection is put back and threads that
@@ -120,33 +191,21 @@ pub struct SqliteConnectionGuard {
     inner: Arc<SqliteMultithreadedInner>,
     // drop() needs to remove the connection, so use Option<...> here
     connection: Option<SqliteConnection>,
+    reader: bool,
 }
 
 impl SqliteConnectionGuard {
-    fn new(inner: Arc<SqliteMultithreadedInner>) -> SqliteConnectionGuard {
-        let _global_lock =
-            CONN_CONDVAR.wait_while(CONN_LOCK.lock().expect("lock poisoned"), |allowed| {
-                if *allowed {
-                    *allowed = false;
-                    false
-                } else {
-                    true
-                }
-            });
-        let connection = {
-            let mut connection = inner
-                .condvar
-                .wait_while(inner.connection.lock().expect("poisoned lock"), |con| {
-                    con.is_none()
-                })
-                .expect("poisoned lock");
-
-            connection.take().expect("connection should not be empty")
+    fn new(inner: Arc<SqliteMultithreadedInner>, reader: bool) -> SqliteConnectionGuard {
+        let connection = if reader {
+            inner.readers.take()
+        } else {
+            inner.writer.take()
         };
 
         SqliteConnectionGuard {
             inner,
             connection: Some(connection),
+            reader,
         }
     }
 
@@ -186,42 +245,44 @@ impl Deref for SqliteConnectionGuard {
 
 impl Drop for SqliteConnectionGuard {
     fn drop(&mut self) {
-        *(CONN_LOCK.lock().expect("lock poisoned")) = true;
-        let mut connection = self.inner.connection.lock().expect("poisoned lock");
-        connection.get_or_insert(self.connection.take().unwrap());
-        // notify others that wait for this connection
-        self.inner.condvar.notify_one();
-        CONN_CONDVAR.notify_one();
+        let connection = self.connection.take().unwrap();
+        if self.reader {
+            self.inner.readers.put(connection);
+        } else {
+            self.inner.writer.put(connection);
+        }
     }
 }
 
 impl SqliteMultithreaded {
-    /// Create a new instance wrapping the provided sqlite connection.
-    pub fn new(connection: SqliteConnection) -> Self {
+    fn from_connections(
+        writer: SqliteConnection,
+        readers: Vec<SqliteConnection>,
+        callbacks: Option<Box<dyn SqliteCallbacks>>,
+        hlc_provider: Option<Arc<Box<SqliteHlcProvider>>>,
+    ) -> Self {
         Self {
             inner: Arc::new(SqliteMultithreadedInner {
-                connection: Mutex::new(Some(connection)),
-                condvar: Condvar::new(),
-                callbacks: None,
+                writer: SqliteConnectionSlots::new(vec![writer]),
+                readers: SqliteConnectionSlots::new(readers),
+                callbacks,
             }),
-            hlc_provider: None,
+            hlc_provider,
         }
     }
 
+    /// Create a new instance wrapping the provided sqlite connection.
+    pub fn new(connection: SqliteConnection) -> Self {
+        Self::from_connections(connection, Vec::new(), None, None)
+    }
+
     /// Create a new instance wrapping the provided sqlite connection, and
     /// with callbacks that are called when sqlite operations happen.
     pub fn new_with_callbacks(
         connection: SqliteConnection,
         callbacks: Box<dyn SqliteCallbacks>,
     ) -> Self {
-        Self {
-            inner: Arc::new(SqliteMultithreadedInner {
-                connection: Mutex::new(Some(connection)),
-                condvar: Condvar::new(),
-                callbacks: Some(callbacks),
-            }),
-            hlc_provider: None,
-        }
+        Self::from_connections(connection, Vec::new(), Some(callbacks), None)
     }
 
     /// Create a new instance wrapping the provided sqlite connection, and
@@ -231,14 +292,59 @@ impl SqliteMultithreaded {
         hlc_provider: Arc<Box<SqliteHlcProvider>>,
         callbacks: Box<dyn SqliteCallbacks>,
     ) -> Self {
-        Self {
-            inner: Arc::new(SqliteMultithreadedInner {
-                connection: Mutex::new(Some(connection)),
-                condvar: Condvar::new(),
-                callbacks: Some(callbacks),
-            }),
-            hlc_provider: Some(hlc_provider),
-        }
+        Self::from_connections(connection, Vec::new(), Some(callbacks), Some(hlc_provider))
+    }
+
+    /// Open a pool of connections to the Sqlite database at `path`: one writer
+    /// connection and `readers` read-only connections.
+    ///
+    /// The database is switched to WAL mode, so that reads served by the
+    /// reader connections don't wait for, nor block, the writer. Writes,
+    /// schema changes and transactions are serialized on the writer
+    /// connection. With no readers every query is served by the writer.
+    /// Each connection waits for locks held by the others for up to
+    /// 5 seconds before failing with `SQLITE_BUSY`.
+    ///
+    /// Each connection to an in-memory database is a separate database, so
+    /// `path` has to be a file.
+    pub fn open_pool(path: impl AsRef<Path>, readers: usize) -> Result<Self> {
+        let (writer, readers) = open_pool_connections(path.as_ref(), readers)?;
+        Ok(Self::from_connections(writer, readers, None, None))
+    }
+
+    /// Open a pool of connections to the Sqlite database at `path`, see
+    /// `open_pool`, with callbacks that are called when sqlite operations
+    /// happen.
+    pub fn open_pool_with_callbacks(
+        path: impl AsRef<Path>,
+        readers: usize,
+        callbacks: Box<dyn SqliteCallbacks>,
+    ) -> Result<Self> {
+        let (writer, readers) = open_pool_connections(path.as_ref(), readers)?;
+        Ok(Self::from_connections(
+            writer,
+            readers,
+            Some(callbacks),
+            None,
+        ))
+    }
+
+    /// Open a pool of connections to the Sqlite database at `path`, see
+    /// `open_pool`, with callbacks that are called when sqlite operations
+    /// happen and a function that returns the HLC from the last update.
+    pub fn open_pool_with_sqlite_hlc_provider_and_callbacks(
+        path: impl AsRef<Path>,
+        readers: usize,
+        hlc_provider: Arc<Box<SqliteHlcProvider>>,
+        callbacks: Box<dyn SqliteCallbacks>,
+    ) -> Result<Self> {
+        let (writer, readers) = open_pool_connections(path.as_ref(), readers)?;
+        Ok(Self::from_connections(
+            writer,
+            readers,
+            Some(callbacks),
+            Some(hlc_provider),
+        ))
     }
 
     /// Returns a guard that acquires the sqlite connection.
@@ -246,8 +352,12 @@ impl SqliteMultithreaded {
     /// When guard is destroyed then connection is put back and threads that are waiting for it
     /// are notified.
     ///
-    /// NOTE: This is a lock which will block any other `acquire_sqlite_connection()` calls, so
-    /// you must not hold this over an await point as this may cause a deadlock.
+    /// Reads are served by a reader connection if this is a pool with
+    /// readers, any other query by the writer connection.
+    ///
+    /// NOTE: This is a lock which will block any other `acquire_sqlite_connection()` calls
+    /// needing the same kind of connection once they are all in use, so you must not hold this
+    /// over an await point as this may cause a deadlock.
     pub async fn acquire_sqlite_connection(
         &self,
         query_type: SqliteQueryType,
@@ -255,7 +365,8 @@ impl SqliteMultithreaded {
         if let Some(callbacks) = &self.inner.callbacks {
             callbacks.query_start(query_type).await?;
         }
-        Ok(SqliteConnectionGuard::new(self.inner.clone()))
+        let reader = query_type == SqliteQueryType::Read && self.inner.readers.size > 0;
+        Ok(SqliteConnectionGuard::new(self.inner.clone(), reader))
     }
 
     /// Get the timestamp of the last write to the database.
@@ -264,6 +375,41 @@ impl SqliteMultithreaded {
         self.hlc_provider.clone().map(|prov| prov())
     }
 }
+
+/// How long a pooled connection waits for a lock held by another connection
+/// of the pool, e.g. a reader during a WAL checkpoint, before failing with
+/// `SQLITE_BUSY`.
+const POOL_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn open_pool_connection(path: &Path) -> Result<SqliteConnection> {
+    let con = SqliteConnection::open(path)?;
+    con.busy_timeout(POOL_BUSY_TIMEOUT)?;
+    Ok(con)
+}
+
+fn open_pool_connections(
+    path: &Path,
+    readers: usize,
+) -> Result<(SqliteConnection, Vec<SqliteConnection>)> {
+    let writer = open_pool_connection(path)?;
+    let journal_mode: String =
+        writer.query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))?;
+    anyhow::ensure!(
+        journal_mode.eq_ignore_ascii_case("wal"),
+        "Failed to switch sqlite database {} to WAL mode, it uses {}",
+        path.display(),
+        journal_mode,
+    );
+    let readers = (0..readers)
+        .map(|_| {
+            let reader = open_pool_connection(path)?;
+            reader.pragma_update(None, "query_only", true)?;
+            Ok(reader)
+        })
+        .collect::<Result<_>>()?;
+    Ok((writer, readers))
+}
+
 /// Query Telemetry for Sqlite queries
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SqliteQueryTelemetry {
@@ -12,8 +12,11 @@
 
 use anyhow::Error;
 use futures::future::TryFutureExt;
+use mysql_async::prelude::Queryable;
 
 use crate::mysql;
+use crate::postgres::PostgresTransaction;
+use crate::postgres::StatementCaching;
 use crate::sqlite::SqliteConnectionGuard;
 use crate::sqlite::SqliteQueryType;
 
@@ -25,7 +28,7 @@ impl crate::Connection {
     }
 }
 
-/// Enum for generalizing transactions over Sqlite and MyRouter.
+/// Enum for generalizing transactions over Sqlite, MyRouter and Postgres.
 ///
 /// # Example
 /// ```
@@ -57,6 +60,21 @@ impl crate::Connection {
 /// #
 /// # fn main() {}
 /// ```
+///
+/// Savepoints allow to roll back part of a transaction, e.g. for a helper that
+/// runs its queries in a transaction that it doesn't own:
+/// ```
+/// use anyhow::Error;
+/// use sql::Transaction;
+///
+/// async fn helper(transaction: Transaction) -> Result<Transaction, Error> {
+///     let transaction = transaction.savepoint("helper").await?;
+///     // ... queries that fail or are undone with `rollback_to_savepoint` ...
+///     transaction.release_savepoint("helper").await
+/// }
+/// #
+/// # fn main() {}
+/// ```
 pub enum Transaction {
     /// It is important to know that when creating a transaction with Sqlite any next attempt at
     /// creating a transaction will wait until the previous transaction has been completed. This
@@ -69,6 +87,9 @@ pub enum Transaction {
     Mysql(Option<mysql::Transaction>),
     /// A variant used for the external Mysql client connection.
     OssMysql(Option<mysql_async::Transaction<'static>>),
+    /// A variant used for Postgres connections. When dropped without being committed the
+    /// transaction is rolled back by closing its client.
+    Postgres(Option<PostgresTransaction>),
 }
 
 impl Transaction {
@@ -93,6 +114,10 @@ impl Transaction {
                     .await?;
                 Ok(Transaction::OssMysql(Some(transaction)))
             }
+            super::Connection::Postgres(conn) => {
+                let transaction = conn.begin_transaction().await?;
+                Ok(Transaction::Postgres(Some(transaction)))
+            }
         }
     }
 
@@ -119,14 +144,23 @@ impl Transaction {
                 let tr = tr.take().expect("Called rollback after drop");
                 Ok(tr.commit().await?)
             }
+            Transaction::Postgres(ref mut tr) => {
+                let tr = tr.take().expect("Called commit after drop");
+                tr.commit().await
+            }
         }
     }
 
     /// Perform a rollback on this transaction
     pub async fn rollback(mut self) -> Result<(), Error> {
         match self {
-            // Sqlite will rollback on drop
-            Transaction::Sqlite(..) => Ok(()),
+            Transaction::Sqlite(ref mut tr_con) => {
+                // Taken before rolling back, so that a failed rollback isn't
+                // attempted again, and turned into a panic, on drop.
+                let con = tr_con.take().expect("Called rollback after drop");
+                con.execute_batch("ROLLBACK")?;
+                Ok(())
+            }
             Transaction::Mysql(ref mut tr) => {
                 let tr = tr.take().expect("Called rollback after drop");
                 Ok(tr.rollback().await?)
@@ -135,8 +169,75 @@ impl Transaction {
                 let tr = tr.take().expect("Called rollback after drop");
                 Ok(tr.rollback().await?)
             }
+            Transaction::Postgres(ref mut tr) => {
+                let tr = tr.take().expect("Called rollback after drop");
+                tr.rollback().await
+            }
         }
     }
+
+    /// Create a savepoint named `name` in this transaction, which can then be
+    /// released with `release_savepoint` or rolled back to with
+    /// `rollback_to_savepoint`. Savepoints can be nested, and reusing the name
+    /// of an existing savepoint hides it until the new one is released.
+    pub async fn savepoint(mut self, name: &str) -> Result<Self, Error> {
+        self.execute(format!("SAVEPOINT {}", savepoint_name(name)?))
+            .await?;
+        Ok(self)
+    }
+
+    /// Release the savepoint named `name`, and the savepoints created after
+    /// it, keeping the changes made since it was created in the transaction.
+    pub async fn release_savepoint(mut self, name: &str) -> Result<Self, Error> {
+        self.execute(format!("RELEASE SAVEPOINT {}", savepoint_name(name)?))
+            .await?;
+        Ok(self)
+    }
+
+    /// Undo the changes made since the savepoint named `name` was created, and
+    /// release the savepoints created after it. The savepoint itself is kept,
+    /// so it can be rolled back to again.
+    pub async fn rollback_to_savepoint(mut self, name: &str) -> Result<Self, Error> {
+        self.execute(format!("ROLLBACK TO SAVEPOINT {}", savepoint_name(name)?))
+            .await?;
+        Ok(self)
+    }
+
+    async fn execute(&mut self, statement: String) -> Result<(), Error> {
+        match self {
+            Transaction::Sqlite(con) => {
+                let con = con.as_ref().expect("Called execute after drop");
+                Ok(con.execute_batch(&statement)?)
+            }
+            Transaction::Mysql(tr) => {
+                let tr = tr.as_mut().expect("Called execute after drop");
+                tr.write_query(statement).await?;
+                Ok(())
+            }
+            Transaction::OssMysql(tr) => {
+                let tr = tr.as_mut().expect("Called execute after drop");
+                Ok(tr.query_drop(statement).await?)
+            }
+            Transaction::Postgres(tr) => {
+                let tr = tr.as_mut().expect("Called execute after drop");
+                tr.write_query(&statement, &[], StatementCaching::Uncached)
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Savepoint names are interpolated in the statements, so only identifiers
+/// that don't need quoting in any of the supported databases are allowed.
+fn savepoint_name(name: &str) -> Result<&str, Error> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    anyhow::ensure!(valid, "Invalid savepoint name {name:?}");
+    Ok(name)
 }
 
 impl Drop for Transaction {
@@ -154,7 +255,7 @@ impl Drop for Transaction {
                     panic!("Rollback on drop of Sqlite connection has failed: {err:#?}");
                 }
             }
-            Transaction::Mysql(_) | Transaction::OssMysql(_) => {}
+            Transaction::Mysql(_) | Transaction::OssMysql(_) | Transaction::Postgres(_) => {}
         }
     }
 }
@@ -19,6 +19,19 @@
 //! `read` if you perform a SELECT and expect the result to be parsed into a tuple or `write` if
 //! you execute an INSERT/UPDATE/DELETE query which will give you `WriteResult` upon completion.
 //!
+//! A query can use a different text for each database with `mysql(...) sqlite(...)` and an
+//! optional `postgres(...)`, which defaults to the SQLite text. Parameters of Postgres queries are
+//! bound as `$1`, `$2`... placeholders, and `insert_or_ignore` writes get an
+//! `ON CONFLICT DO NOTHING` clause, which is appended to the query unless its `postgres(...)`
+//! text places it with `{on_conflict}`, e.g. before a `RETURNING` clause. The last insert id of a
+//! Postgres write is read from the first column of its last returned row, e.g. with `RETURNING id`.
+//!
+//! MySQL queries are run as text SQL with the values of their parameters escaped into it, unless
+//! the connection is an [OssConnection] created with `with_prepared_statements`, which binds them
+//! to server-side prepared statements instead, outside of transactions. Prepared statements are
+//! cached by their SQL, including the comment of `commented_query`, see
+//! [OssConnection::prepared_statements].
+//!
 //! This crate also supports SQL transactions, see [Transaction] for more details.
 //!
 //! For some working example usage you can look at `tests.rs`, below is a simplified one.
@@ -77,11 +90,14 @@ use rusqlite::types::ValueRef as SqliteValueRef;
 pub use sql_common;
 pub use sql_common::Connection;
 pub use sql_common::QueryTelemetry;
+pub use sql_common::RowDecodeError;
 pub use sql_common::SqlConnections;
 pub use sql_common::SqlShardedConnections;
 pub use sql_common::WriteResult;
 pub use sql_common::mysql;
 pub use sql_common::mysql::OssConnection;
+pub use sql_common::postgres;
+pub use sql_common::read_policy;
 pub use sql_common::sqlite;
 pub use sql_common::transaction::Transaction;
 
@@ -139,7 +155,7 @@ macro_rules! queries {
             $vi read $name (
                 $( $pname: $ptype ),*
                 $( >list $lname: $ltype )*
-            ) -> ($( $rtype ),*) { mysql($q) sqlite($q) }
+            ) -> ($( $rtype ),*) { mysql($q) sqlite($q) postgres($q) }
             $( $tt )*
         }
     );
@@ -150,13 +166,33 @@ macro_rules! queries {
             $( >list $lname:ident: $ltype:ty )*
         ) -> ($( $rtype:ty ),* $(,)*) { mysql($mysql_q:expr) sqlite($sqlite_q:expr) }
         $( $tt:tt )*
+    ) => (
+        $crate::queries! {
+            $vi read $name (
+                $( $pname: $ptype ),*
+                $( >list $lname: $ltype )*
+            ) -> ($( $rtype ),*) { mysql($mysql_q) sqlite($sqlite_q) postgres($sqlite_q) }
+            $( $tt )*
+        }
+    );
+
+    (
+        $vi:vis read $name:ident (
+            $( $pname:ident: $ptype:ty ),* $(,)*
+            $( >list $lname:ident: $ltype:ty )*
+        ) -> ($( $rtype:ty ),* $(,)*) {
+            mysql($mysql_q:expr) sqlite($sqlite_q:expr) postgres($postgres_q:expr)
+        }
+        $( $tt:tt )*
     ) => (
         #[allow(non_snake_case)]
         $vi mod $name {
-            $crate::_read_query_impl!((
+            $crate::_read_query_impl!($name (
                 $( $pname: $ptype, )*
                 $( >list $lname: $ltype )*
-            ) -> ($( $rtype ),*) { mysql($mysql_q) sqlite($sqlite_q) });
+            ) -> ($( $rtype ),*) {
+                mysql($mysql_q) sqlite($sqlite_q) postgres($postgres_q)
+            });
 
             #[allow(dead_code)]
             pub async fn query(
@@ -228,7 +264,7 @@ macro_rules! queries {
             $vi write $name (
                 values: ($( $vname: $vtype ),*)
                 $( , $pname: $ptype )*
-            ) { $qtype, mysql($q) sqlite($q) }
+            ) { $qtype, mysql($q) sqlite($q) postgres($q) }
             $( $tt )*
         }
     );
@@ -239,6 +275,25 @@ macro_rules! queries {
             $( , $pname:ident: $ptype:ty )* $(,)*
         ) { $qtype:ident, mysql($mysql_q:expr) sqlite($sqlite_q:expr) }
         $( $tt:tt )*
+    ) => (
+        $crate::queries! {
+            $vi write $name (
+                values: ($( $vname: $vtype ),*)
+                $( , $pname: $ptype )*
+            ) { $qtype, mysql($mysql_q) sqlite($sqlite_q) postgres($sqlite_q) }
+            $( $tt )*
+        }
+    );
+
+    (
+        $vi:vis write $name:ident (
+            values: ($( $vname:ident: $vtype:ty ),* $(,)*)
+            $( , $pname:ident: $ptype:ty )* $(,)*
+        ) {
+            $qtype:ident,
+            mysql($mysql_q:expr) sqlite($sqlite_q:expr) postgres($postgres_q:expr)
+        }
+        $( $tt:tt )*
     ) => (
         #[allow(non_snake_case)]
         $vi mod $name {
@@ -246,6 +301,7 @@ macro_rules! queries {
                 $qtype,
                 mysql($mysql_q)
                 sqlite($sqlite_q)
+                postgres($postgres_q)
             });
 
             #[allow(dead_code)]
@@ -314,7 +370,7 @@ macro_rules! queries {
             $vi write $name (
                 $( $pname: $ptype ),*
                 $( >list $lname: $ltype )*
-            ) { $qtype, mysql($q) sqlite($q) }
+            ) { $qtype, mysql($q) sqlite($q) postgres($q) }
             $( $tt )*
         }
     );
@@ -325,6 +381,25 @@ macro_rules! queries {
             $( >list $lname:ident: $ltype:ty )*
         ) { $qtype:ident, mysql($mysql_q:expr) sqlite($sqlite_q:expr) }
         $( $tt:tt )*
+    ) => (
+        $crate::queries! {
+            $vi write $name (
+                $( $pname: $ptype ),*
+                $( >list $lname: $ltype )*
+            ) { $qtype, mysql($mysql_q) sqlite($sqlite_q) postgres($sqlite_q) }
+            $( $tt )*
+        }
+    );
+
+    (
+        $vi:vis write $name:ident (
+            $( $pname:ident: $ptype:ty ),* $(,)*
+            $( >list $lname:ident: $ltype:ty )*
+        ) {
+            $qtype:ident,
+            mysql($mysql_q:expr) sqlite($sqlite_q:expr) postgres($postgres_q:expr)
+        }
+        $( $tt:tt )*
     ) => (
         #[allow(non_snake_case)]
         $vi mod $name {
@@ -335,6 +410,7 @@ macro_rules! queries {
                 $qtype,
                 mysql($mysql_q)
                 sqlite($sqlite_q)
+                postgres($postgres_q)
             });
 
             #[allow(dead_code)]
@@ -403,6 +479,7 @@ macro_rules! _query_common {
 
         use $crate::Connection;
         use $crate::HList;
+        use $crate::RowDecodeError;
         use $crate::Transaction;
         use $crate::ValueWrapper;
         use $crate::anyhow::Context;
@@ -435,10 +512,12 @@ macro_rules! _query_common {
 #[macro_export]
 #[doc(hidden)]
 macro_rules! _read_query_impl {
-    ( (
+    ( $name:ident (
         $( $pname:ident: $ptype:ty, )*
         $( >list $lname:ident: $ltype:ty )*
-    ) -> ($( $rtype:ty ),*) { mysql($mysql_q:expr) sqlite($sqlite_q:expr) } ) => (
+    ) -> ($( $rtype:ty ),*) {
+        mysql($mysql_q:expr) sqlite($sqlite_q:expr) postgres($postgres_q:expr)
+    } ) => (
         $crate::_query_common!();
 
         async fn query_internal(
@@ -480,18 +559,34 @@ macro_rules! _read_query_impl {
                     }
                 }
                 Connection::OssMysql(conn) => {
-                    let query = mysql_query($( $pname, )* $( $lname, )*);
+                    let mut query = oss_query(conn.prepared_statements(), $( $pname, )* $( $lname, )*);
+                    if let Some(comment) = comment {
+                        query.sql_mut().insert_str(0, &format!("/* {} */", comment));
+                    }
 
-                    let mut con = OssConnection::get_conn_counted(conn.pool.clone(), &conn.stats).await?;
-                    let (mut res, _tel) = conn.read_query(&mut con, &query).map_err(Error::from).await?;
+                    let (result, tel) = conn
+                        .read_query_with_telemetry(query, mysql_async_row_to_tuple)
+                        .await?;
+                    let result = result
+                        .into_iter()
+                        .collect::<Result<Vec<($( $rtype, )*)>, Error>>()?;
 
-                    let result = res
-                        .map( |row| mysql_async_row_to_tuple(row))
+                    Ok((result, Some(tel.into())))
+                }
+                Connection::Postgres(conn) => {
+                    let (mut query, params) = postgres_query($( $pname, )* $( $lname, )*);
+                    let caching = $crate::_postgres_statement_caching!(comment $( , $lname )*);
+                    if let Some(comment) = comment {
+                        query.insert_str(0, &format!("/* {} */", comment));
+                    }
+                    let result = conn
+                        .read_query(&query, &params, caching)
                         .await?
                         .into_iter()
+                        .map(postgres_row_to_tuple)
                         .collect::<Result<Vec<($( $rtype, )*)>, Error>>()?;
 
-                    // OssMysql doesn't support query telemetry
+                    // Postgres doesn't support query telemetry
                     Ok((result, None))
                 }
             }
@@ -504,10 +599,33 @@ macro_rules! _read_query_impl {
                     $({
                         let res: $crate::mysql_async::Value = row.get(idx).ok_or($crate::anyhow::anyhow!("Failed to parse idx"))?;
                         idx += 1;
-                        <$rtype as FromValue>::from_value_opt(res)
-                            .unwrap_or_else(|err| {
-                                panic!("Failed to parse `{}`: {}", stringify!($rtype), err)
-                            })
+                        RowDecodeError::decode::<$rtype>(
+                            stringify!($name),
+                            idx - 1,
+                            stringify!($rtype),
+                            res,
+                        )?
+                    },)*
+                );
+                // suppress unused_assignments warning
+                let _ = idx;
+                Ok(res)
+        }
+
+        fn postgres_row_to_tuple(row: Vec<$crate::mysql_async::Value>) -> Result<($( $rtype, )*), Error> {
+            #[allow(clippy::eval_order_dependence)]
+                let mut row = row.into_iter();
+                let mut idx = 0;
+                let res = (
+                    $({
+                        let res = row.next().ok_or($crate::anyhow::anyhow!("Failed to parse idx"))?;
+                        idx += 1;
+                        RowDecodeError::decode::<$rtype>(
+                            stringify!($name),
+                            idx - 1,
+                            stringify!($rtype),
+                            res,
+                        )?
                     },)*
                 );
                 // suppress unused_assignments warning
@@ -556,20 +674,40 @@ macro_rules! _read_query_impl {
 
                 }
                 Transaction::OssMysql(ref mut transaction) => {
-                    let query = mysql_query($( $pname, )* $( $lname, )*);
+                    let mut tr = transaction.take().expect("should be Some before transaction ended");
+                    let mut query = mysql_query($( $pname, )* $( $lname, )*);
+                    if let Some(comment) = comment {
+                        query.insert_str(0, &format!("/* {} */", comment));
+                    }
+
+                    let (result, tel) = OssConnection::transaction_read_query(
+                        &mut tr,
+                        query,
+                        mysql_async_row_to_tuple,
+                    )
+                    .await?;
+                    let result = result
+                        .into_iter()
+                        .collect::<Result<Vec<($( $rtype, )*)>, Error>>()?;
 
+                    Ok((Transaction::OssMysql(Some(tr)), (result, Some(tel.into()))))
+                }
+                Transaction::Postgres(ref mut transaction) => {
+                    let (mut query, params) = postgres_query($( $pname, )* $( $lname, )*);
+                    let caching = $crate::_postgres_statement_caching!(comment $( , $lname )*);
+                    if let Some(comment) = comment {
+                        query.insert_str(0, &format!("/* {} */", comment));
+                    }
                     let mut tr = transaction.take().expect("should be Some before transaction ended");
-                    let mut query_result  = tr.query_iter(query).map_err(Error::from).await?;
-                    let result = query_result
-                        .map(
-                        |row| mysql_async_row_to_tuple(row)
-                        )
+                    let result = tr
+                        .read_query(&query, &params, caching)
                         .await?
                         .into_iter()
+                        .map(postgres_row_to_tuple)
                         .collect::<Result<Vec<($( $rtype, )*)>, Error>>()?;
 
-                    // OssMysql doesn't support query telemetry
-                    Ok((Transaction::OssMysql(Some(tr)), (result, None)))
+                    // Postgres doesn't support query telemetry
+                    Ok((Transaction::Postgres(Some(tr)), (result, None)))
                 }
             }
         }
@@ -597,8 +735,11 @@ macro_rules! _read_query_impl {
                     stmt.query_map(
                         &ref_params[..],
                         sqlite_row_to_tuple
-                    )?.collect()
-                }).map_err(Error::from)
+                    )?.collect::<SqliteResult<Vec<_>>>()
+                })?
+                .into_iter()
+                .collect::<Result<Vec<($( $rtype, )*)>, RowDecodeError>>()
+                .map_err(Error::from)
         }
 
         async fn sqlite_query_with_transaction(
@@ -617,7 +758,7 @@ macro_rules! _read_query_impl {
                 ref_params.push((&params[idx].0, &params[idx].1))
             }
 
-            let res: SqliteResult<Vec<($( $rtype, )*)>> = {
+            let res: SqliteResult<Vec<Result<($( $rtype, )*), RowDecodeError>>> = {
                 let mut stmt = sqlite_statement(&transaction  $( , $lname )*)?;
                 let res = stmt.query_map(
                     &ref_params[..],
@@ -625,8 +766,11 @@ macro_rules! _read_query_impl {
                 )?.collect();
                 res
             };
+            let res = res?
+                .into_iter()
+                .collect::<Result<Vec<($( $rtype, )*)>, RowDecodeError>>()?;
 
-            Ok((transaction, res?))
+            Ok((transaction, res))
         }
 
         fn mysql_query($( $pname: & $ptype, )* $( $lname: & [ $ltype ], )*) -> String {
@@ -638,6 +782,42 @@ macro_rules! _read_query_impl {
             )
         }
 
+        // Visible to the crate so that the SQL and parameters can be checked in tests.
+        pub(crate) fn oss_query(
+            prepared_statements: bool,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> $crate::sql_common::mysql::OssQuery {
+            if prepared_statements {
+                let mut bound = Vec::new();
+                $crate::_emit_mysql_prepared_params!(bound, $( $pname ),* $( >list $lname )*);
+                let params = $crate::sql_common::mysql::order_params($mysql_q, &bound);
+                if params.len() <= $crate::sql_common::mysql::MAX_PREPARED_STATEMENT_PARAMS {
+                    let query = format!(
+                        $mysql_q,
+                        $( $pname = $pname, )*
+                        $( $lname = $lname, )*
+                    );
+                    return $crate::sql_common::mysql::OssQuery::Prepared(query, params);
+                }
+            }
+            mysql_query($( $pname, )* $( $lname, )*).into()
+        }
+
+        fn postgres_query(
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> (String, Vec<$crate::sql_common::postgres::PostgresValue>) {
+            let mut params = Vec::new();
+            $crate::_emit_postgres_params!(params, $( $pname ),* $( >list $lname )*);
+            let query = format!(
+                $postgres_q,
+                $( $pname = $pname, )*
+                $( $lname = $lname, )*
+            );
+            (query, params)
+        }
+
         fn sqlite_statement<'a>(
             connection: &'a SqliteConnection,
             $( $lname: usize, )*
@@ -650,7 +830,9 @@ macro_rules! _read_query_impl {
             ))
         }
 
-        fn sqlite_row_to_tuple(row: &SqliteRow) -> SqliteResult<($( $rtype, )*)> {
+        fn sqlite_row_to_tuple(
+            row: &SqliteRow,
+        ) -> SqliteResult<Result<($( $rtype, )*), RowDecodeError>> {
             // This is currently necessary to use the `mut idx` to keep track of which element of
             // the tuple we are constructing.
             // Once the feature: `macro_metavar_expr` is stable, we can replace `row.get(idx)` with
@@ -662,15 +844,20 @@ macro_rules! _read_query_impl {
                     $({
                         let res: ValueWrapper = row.get(idx)?;
                         idx += 1;
-                        <$rtype as FromValue>::from_value_opt(res.0)
-                            .unwrap_or_else(|err| {
-                                panic!("Failed to parse `{}`: {}", stringify!($rtype), err)
-                            })
+                        match RowDecodeError::decode::<$rtype>(
+                            stringify!($name),
+                            idx - 1,
+                            stringify!($rtype),
+                            res.0,
+                        ) {
+                            Ok(value) => value,
+                            Err(err) => return Ok(Err(err)),
+                        }
                     },)*
                 );
                 // suppress unused_assignments warning
                 let _ = idx;
-                Ok(res)
+                Ok(Ok(res))
             }
         }
     );
@@ -683,6 +870,7 @@ macro_rules! _write_query_impl {
         $qtype:ident,
         mysql($mysql_q:expr)
         sqlite($sqlite_q:expr)
+        postgres($postgres_q:expr)
     } ) => (
         use $crate::WriteResult;
 
@@ -711,10 +899,21 @@ macro_rules! _write_query_impl {
                     Ok(res.into())
                 }
                 Connection::OssMysql(conn)=> {
-                    let query = mysql_query(values, $( $pname ),*);
-                    let res = conn.write_query(query).map_err(Error::from).await?;
-                    Ok(res.into())
+                    let mut query = oss_query(conn.prepared_statements(), values, $( $pname ),*);
+                    if let Some(comment) = comment {
+                        query.sql_mut().insert_str(0, &format!("/* {} */", comment));
+                    }
+                    conn.write_query_with_telemetry(query).await
                 },
+                Connection::Postgres(conn) => {
+                    let (mut query, params) = postgres_query(values, $( $pname ),*);
+                    // The text of the query depends on the number of values
+                    let caching = $crate::sql_common::postgres::StatementCaching::Uncached;
+                    if let Some(comment) = comment {
+                        query.insert_str(0, &format!("/* {} */", comment));
+                    }
+                    conn.write_query(&query, &params, caching).await
+                }
             }
         }
 
@@ -752,19 +951,27 @@ macro_rules! _write_query_impl {
                     Ok((Transaction::Mysql(Some(tr)), result.into()))
                 },
                 Transaction::OssMysql(ref mut transaction)=>{
-                    let query = mysql_query(values, $( $pname ),*);
                     let mut tr = transaction.take().expect("should be Some before transaction ended");
+                    let mut query = mysql_query(values, $( $pname ),*);
+                    if let Some(comment) = comment {
+                        query.insert_str(0, &format!("/* {} */", comment));
+                    }
 
-                    let query_result = tr.query_iter(query).await?;
-
-                    let last_insert_id = query_result.last_insert_id();
-                    let rows_affected = query_result.affected_rows();
-
-                    let result = WriteResult::new(last_insert_id, rows_affected, None);
-
-                    Ok((Transaction::OssMysql(Some(tr)), result.into()))
-
+                    let result = OssConnection::transaction_write_query(&mut tr, query).await?;
+                    Ok((Transaction::OssMysql(Some(tr)), result))
                 },
+                Transaction::Postgres(ref mut transaction) => {
+                    let (mut query, params) = postgres_query(values, $( $pname ),*);
+                    // The text of the query depends on the number of values
+                    let caching = $crate::sql_common::postgres::StatementCaching::Uncached;
+                    if let Some(comment) = comment {
+                        query.insert_str(0, &format!("/* {} */", comment));
+                    }
+                    let mut tr = transaction.take()
+                        .expect("should be Some before transaction ended");
+                    let result = tr.write_query(&query, &params, caching).await?;
+                    Ok((Transaction::Postgres(Some(tr)), result))
+                }
             }
         }
 
@@ -785,6 +992,65 @@ macro_rules! _write_query_impl {
             $crate::_write_mysql_query!($qtype, $mysql_q, values: val, $( $pname ),*)
         }
 
+        // Visible to the crate so that the SQL and parameters can be checked in tests.
+        pub(crate) fn oss_query(
+            prepared_statements: bool,
+            values: &[($( & $vtype, )*)],
+            $( $pname: & $ptype ),*
+        ) -> $crate::sql_common::mysql::OssQuery {
+            if prepared_statements {
+                let mut values_params = Vec::new();
+                let mut val = String::new();
+                let mut first = true;
+                for value in values {
+                    if first {
+                        first = false;
+                    } else {
+                        write!(&mut val, ", ").unwrap();
+                    }
+                    write!(&mut val, "(").unwrap();
+                    $crate::_append_to_mysql_prepared_values!(val, values_params, value $( , $vname )*);
+                    write!(&mut val, ")").unwrap();
+                }
+                let mut bound = vec![("values", values_params)];
+                $crate::_emit_mysql_prepared_params!(bound, $( $pname ),*);
+                let params = $crate::sql_common::mysql::order_params($mysql_q, &bound);
+                if params.len() <= $crate::sql_common::mysql::MAX_PREPARED_STATEMENT_PARAMS {
+                    let query = $crate::_write_mysql_prepared_query!(
+                        $qtype,
+                        $mysql_q,
+                        values: val,
+                        $( $pname ),*
+                    );
+                    return $crate::sql_common::mysql::OssQuery::Prepared(query, params);
+                }
+            }
+            mysql_query(values, $( $pname ),*).into()
+        }
+
+        fn postgres_query(
+            values: &[($( & $vtype, )*)],
+            $( $pname: & $ptype ),*
+        ) -> (String, Vec<$crate::sql_common::postgres::PostgresValue>) {
+            let mut params = Vec::new();
+            let mut val = String::new();
+            let mut first = true;
+            for value in values {
+                if first {
+                    first = false;
+                } else {
+                    write!(&mut val, ", ").unwrap();
+                }
+                write!(&mut val, "(").unwrap();
+                $crate::_append_to_postgres_values!(val, params, value $( , $vname )*);
+                write!(&mut val, ")").unwrap();
+            }
+            $crate::_emit_postgres_params!(params, $( $pname ),*);
+
+            let query = $crate::_write_postgres_query!($qtype, $postgres_q, values: val, $( $pname ),*);
+            (query, params)
+        }
+
         async fn sqlite_exec_query(
             multithread_con: &SqliteMultithreaded,
             values: &[($( & $vtype, )*)],
@@ -892,7 +1158,12 @@ macro_rules! _write_query_impl {
     ( (
         $( $pname:ident: $ptype:ty, )*
         $( >list $lname:ident: $ltype:ty )*
-    ) { $qtype:ident, mysql($mysql_q:expr) sqlite($sqlite_q:expr) } ) => (
+    ) {
+        $qtype:ident,
+        mysql($mysql_q:expr)
+        sqlite($sqlite_q:expr)
+        postgres($postgres_q:expr)
+    } ) => (
         use $crate::WriteResult;
 
         $crate::_query_common!();
@@ -916,10 +1187,20 @@ macro_rules! _write_query_impl {
                     Ok(res.into())
                 }
                 Connection::OssMysql(conn) => {
-                    let query = mysql_query($( $pname, )* $( $lname, )*);
-                    let res = conn.write_query(query).map_err(Error::from).await?;
-                    Ok(res.into())
+                    let mut query = oss_query(conn.prepared_statements(), $( $pname, )* $( $lname, )*);
+                    if let Some(comment) = comment {
+                        query.sql_mut().insert_str(0, &format!("/* {} */", comment));
+                    }
+                    conn.write_query_with_telemetry(query).await
                 },
+                Connection::Postgres(conn) => {
+                    let (mut query, params) = postgres_query($( $pname, )* $( $lname, )*);
+                    let caching = $crate::_postgres_statement_caching!(comment $( , $lname )*);
+                    if let Some(comment) = comment {
+                        query.insert_str(0, &format!("/* {} */", comment));
+                    }
+                    conn.write_query(&query, &params, caching).await
+                }
             }
         }
 
@@ -952,16 +1233,26 @@ macro_rules! _write_query_impl {
                     Ok((Transaction::Mysql(Some(tr)), result.into()))
                 },
                 Transaction::OssMysql(ref mut transaction) => {
-                    let query = mysql_query($( $pname, )* $( $lname, )*);
                     let mut tr = transaction.take()
                         .expect("should be Some before transaction ended");
-                    let query_result = tr.query_iter(query).await?;
-
-                    let last_insert_id = query_result.last_insert_id();
-                    let rows_affected = query_result.affected_rows();
-                    let result = WriteResult::new(last_insert_id, rows_affected, None);
+                    let mut query = mysql_query($( $pname, )* $( $lname, )*);
+                    if let Some(comment) = comment {
+                        query.insert_str(0, &format!("/* {} */", comment));
+                    }
+                    let result = OssConnection::transaction_write_query(&mut tr, query).await?;
                     Ok((Transaction::OssMysql(Some(tr)), result))
                 }
+                Transaction::Postgres(ref mut transaction) => {
+                    let (mut query, params) = postgres_query($( $pname, )* $( $lname, )*);
+                    let caching = $crate::_postgres_statement_caching!(comment $( , $lname )*);
+                    if let Some(comment) = comment {
+                        query.insert_str(0, &format!("/* {} */", comment));
+                    }
+                    let mut tr = transaction.take()
+                        .expect("should be Some before transaction ended");
+                    let result = tr.write_query(&query, &params, caching).await?;
+                    Ok((Transaction::Postgres(Some(tr)), result))
+                }
             }
         }
 
@@ -970,6 +1261,44 @@ macro_rules! _write_query_impl {
             $crate::_write_mysql_query!($qtype, $mysql_q, $( $pname ),* $( >list $lname )*)
         }
 
+        // Visible to the crate so that the SQL and parameters can be checked in tests.
+        pub(crate) fn oss_query(
+            prepared_statements: bool,
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> $crate::sql_common::mysql::OssQuery {
+            if prepared_statements {
+                let mut bound = Vec::new();
+                $crate::_emit_mysql_prepared_params!(bound, $( $pname ),* $( >list $lname )*);
+                let params = $crate::sql_common::mysql::order_params($mysql_q, &bound);
+                if params.len() <= $crate::sql_common::mysql::MAX_PREPARED_STATEMENT_PARAMS {
+                    let query = $crate::_write_mysql_prepared_query!(
+                        $qtype,
+                        $mysql_q,
+                        $( $pname ),*
+                        $( >list $lname )*
+                    );
+                    return $crate::sql_common::mysql::OssQuery::Prepared(query, params);
+                }
+            }
+            mysql_query($( $pname, )* $( $lname, )*).into()
+        }
+
+        fn postgres_query(
+            $( $pname: & $ptype, )*
+            $( $lname: & [ $ltype ], )*
+        ) -> (String, Vec<$crate::sql_common::postgres::PostgresValue>) {
+            let mut params = Vec::new();
+            $crate::_emit_postgres_params!(params, $( $pname ),* $( >list $lname )*);
+            let query = $crate::_write_postgres_query!(
+                $qtype,
+                $postgres_q,
+                $( $pname ),*
+                $( >list $lname )*
+            );
+            (query, params)
+        }
+
         async fn sqlite_exec_query(
             multithread_con: &SqliteMultithreaded,
             $( $pname: & $ptype, )*
@@ -1085,6 +1414,45 @@ macro_rules! _write_mysql_query {
     };
 }
 
+#[macro_export]
+#[doc(hidden)]
+/// Like `_write_mysql_query`, for parameters already replaced by their placeholders.
+macro_rules! _write_mysql_prepared_query {
+    (insert_or_ignore, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
+        format!(
+            $q,
+            insert_or_ignore = "INSERT IGNORE",
+            values = $values,
+            $( $pname = $pname, )*
+        )
+    };
+
+    (insert_or_ignore, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+        format!(
+            $q,
+            insert_or_ignore = "INSERT IGNORE",
+            $( $pname = $pname, )*
+            $( $lname = $lname, )*
+        )
+    };
+
+    (none, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
+        format!(
+            $q,
+            values = $values,
+            $( $pname = $pname, )*
+        )
+    };
+
+    (none, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+        format!(
+            $q,
+            $( $pname = $pname, )*
+            $( $lname = $lname, )*
+        )
+    };
+}
+
 #[macro_export]
 #[doc(hidden)]
 macro_rules! _write_sqlite_query {
@@ -1123,6 +1491,73 @@ macro_rules! _write_sqlite_query {
     };
 }
 
+#[macro_export]
+#[doc(hidden)]
+/// Statements are only cached for queries whose text is the same on every call, i.e. without a
+/// comment or lists, whose length changes the number of placeholders.
+macro_rules! _postgres_statement_caching {
+    ($comment:expr $( , $lname:ident )*) => {{
+        let lists: &[&str] = &[$( stringify!($lname) ),*];
+        if $comment.is_none() && lists.is_empty() {
+            $crate::sql_common::postgres::StatementCaching::Cached
+        } else {
+            $crate::sql_common::postgres::StatementCaching::Uncached
+        }
+    }};
+}
+
+#[macro_export]
+#[doc(hidden)]
+/// Postgres has no equivalent of `INSERT IGNORE`, so `insert_or_ignore` queries get an
+/// `ON CONFLICT DO NOTHING` clause instead. It is written where the query has an
+/// `{on_conflict}` argument, or appended to the query if it has none. The `{on_conflict:.0}`
+/// suffix prints nothing, it is only there so that `on_conflict` is always used.
+macro_rules! _write_postgres_query {
+    (insert_or_ignore, $q:expr, values: $values:expr, $( $pname:ident ),*) => {{
+        let mut query = format!(
+            concat!($q, "{on_conflict:.0}"),
+            insert_or_ignore = "INSERT",
+            on_conflict = "ON CONFLICT DO NOTHING",
+            values = $values,
+            $( $pname = $pname, )*
+        );
+        if !$q.contains("{on_conflict}") {
+            query.push_str(" ON CONFLICT DO NOTHING");
+        }
+        query
+    }};
+
+    (insert_or_ignore, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {{
+        let mut query = format!(
+            concat!($q, "{on_conflict:.0}"),
+            insert_or_ignore = "INSERT",
+            on_conflict = "ON CONFLICT DO NOTHING",
+            $( $pname = $pname, )*
+            $( $lname = $lname, )*
+        );
+        if !$q.contains("{on_conflict}") {
+            query.push_str(" ON CONFLICT DO NOTHING");
+        }
+        query
+    }};
+
+    (none, $q:expr, values: $values:expr, $( $pname:ident ),*) => {
+        format!(
+            $q,
+            values = $values,
+            $( $pname = $pname, )*
+        )
+    };
+
+    (none, $q:expr, $( $pname:ident ),* $( >list $lname:ident )*) => {
+        format!(
+            $q,
+            $( $pname = $pname, )*
+            $( $lname = $lname, )*
+        )
+    };
+}
+
 #[macro_export]
 #[doc(hidden)]
 macro_rules! _sqlite_named_params {
@@ -1226,6 +1661,110 @@ macro_rules! _emit_mysql_lnames {
     }
 }
 
+#[macro_export]
+#[doc(hidden)]
+/// Bind the values of a row of `values` as parameters of a MySQL prepared statement, appending
+/// their placeholders to $values.
+macro_rules! _append_to_mysql_prepared_values {
+    ($values:ident, $params:ident, $tup:ident $( , $vname:ident )*) => (
+        match $tup {
+            ( $( $vname , )* ) => {
+                let mut first = true;
+                $(
+                    if first {
+                        first = false;
+                    } else {
+                        write!(&mut $values, ", ").unwrap();
+                    }
+                    $params.push(ToValue::to_value($vname));
+                    write!(&mut $values, "?").unwrap();
+                )*
+                // suppress unused_assignments warning
+                let _ = first;
+            }
+        }
+    );
+}
+
+#[macro_export]
+#[doc(hidden)]
+/// Bind all $pname and >list $lname parameters of a MySQL prepared statement by name, replacing
+/// them with the placeholders to interpolate into the query.
+macro_rules! _emit_mysql_prepared_params {
+    ($bound:ident, $( $pname:ident ),* $( >list $lname:ident )*) => {
+        $(
+            $bound.push((
+                stringify!($pname).trim_start_matches("r#"),
+                vec![ToValue::to_value($pname)],
+            ));
+            let $pname = "?";
+        )*
+        $(
+            $bound.push((
+                stringify!($lname).trim_start_matches("r#"),
+                $lname.iter().map(ToValue::to_value).collect(),
+            ));
+            let $lname = format!("({})", vec!["?"; $lname.len()].join(", "));
+        )*
+    }
+}
+
+#[macro_export]
+#[doc(hidden)]
+/// Bind the values of a row of `values` as parameters of a Postgres query, appending their
+/// placeholders to $values.
+macro_rules! _append_to_postgres_values {
+    ($values:ident, $params:ident, $tup:ident $( , $vname:ident )*) => (
+        match $tup {
+            ( $( $vname , )* ) => {
+                let mut first = true;
+                $(
+                    if first {
+                        first = false;
+                    } else {
+                        write!(&mut $values, ", ").unwrap();
+                    }
+                    $params.push($crate::sql_common::postgres::PostgresValue(ToValue::to_value($vname)));
+                    write!(&mut $values, "${}", $params.len()).unwrap();
+                )*
+                // suppress unused_assignments warning
+                let _ = first;
+            }
+        }
+    );
+}
+
+#[macro_export]
+#[doc(hidden)]
+/// Bind all $pname and >list $lname parameters of a Postgres query, replacing them with the
+/// placeholders to interpolate into the query.
+macro_rules! _emit_postgres_params {
+    ($params:ident, $( $pname:ident ),* $( >list $lname:ident )*) => {
+        $(
+            $params.push($crate::sql_common::postgres::PostgresValue(ToValue::to_value($pname)));
+            let $pname = format!("${}", $params.len());
+        )*
+        $(
+            let $lname = {
+                let mut val = String::new();
+                write!(&mut val, "(").unwrap();
+                let mut first = true;
+                for lval in $lname {
+                    if first {
+                        first = false;
+                    } else {
+                        write!(&mut val, ", ").unwrap();
+                    }
+                    $params.push($crate::sql_common::postgres::PostgresValue(ToValue::to_value(lval)));
+                    write!(&mut val, "${}", $params.len()).unwrap();
+                }
+                write!(&mut val, ")").unwrap();
+                val
+            };
+        )*
+    }
+}
+
 #[macro_export]
 #[doc(hidden)]
 /// Serialize all >list $lname elements into strings suitable for interpolation into a SQLite
@@ -10,17 +10,34 @@
 
 #![deny(warnings)]
 
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::Error;
 use sql_tests_lib::TestSemantics;
 use sql_tests_lib::test_datetime_query;
+use sql_tests_lib::test_oss_query_sql_and_params;
 use sql_tests_lib::test_query_visibility_modifiers_compile;
+use sql_tests_lib::test_read_during_transaction;
 use sql_tests_lib::test_read_query;
+use sql_tests_lib::test_read_query_decode_error;
 use sql_tests_lib::test_transaction_commit;
 use sql_tests_lib::test_transaction_rollback;
 use sql_tests_lib::test_transaction_rollback_on_drop;
+use sql_tests_lib::test_transaction_savepoints;
 use sql_tests_lib::test_write_query;
+use stats::test_helpers::with_recorded_stats_async;
+use tempfile::TempDir;
 
 use crate::Connection;
+use crate::SqlConnections;
+use crate::read_policy::ReadPolicy;
+use crate::read_policy::is_transient_error;
 use crate::rusqlite::Connection as SqliteConnection;
+use crate::rusqlite::ffi;
+use crate::sqlite::SqliteQueryType;
 
 #[tokio::test]
 async fn test_read_query_sqlite() {
@@ -31,6 +48,14 @@ async fn test_read_query_sqlite() {
     .await
 }
 
+#[tokio::test]
+async fn test_read_query_decode_error_sqlite() {
+    test_read_query_decode_error(Connection::with_sqlite(
+        SqliteConnection::open_in_memory().unwrap(),
+    ))
+    .await
+}
+
 fn prepare_sqlite_con() -> Connection {
     let conn = SqliteConnection::open_in_memory().unwrap();
     conn.execute_batch(
@@ -71,11 +96,217 @@ async fn test_transaction_commit_with_sqlite() {
     test_transaction_commit(prepare_sqlite_con(), TestSemantics::Sqlite).await;
 }
 
+#[tokio::test]
+async fn test_transaction_savepoints_with_sqlite() {
+    test_transaction_savepoints(prepare_sqlite_con()).await;
+}
+
 #[tokio::test]
 async fn test_visibility_modifiers_compile_with_sqlite() {
     test_query_visibility_modifiers_compile(prepare_sqlite_con()).await;
 }
 
+fn prepare_sqlite_pool() -> (TempDir, Connection) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sqlite.db");
+    SqliteConnection::open(&path)
+        .unwrap()
+        .execute_batch(
+            "CREATE TABLE foo(
+                x INTEGER,
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                y DATETIME DEFAULT CURRENT_TIMESTAMP
+            );",
+        )
+        .unwrap();
+    (dir, Connection::with_sqlite_pool(&path, 2).unwrap())
+}
+
+#[tokio::test]
+async fn test_write_query_with_sqlite_pool() {
+    let (_dir, conn) = prepare_sqlite_pool();
+    test_write_query(conn).await;
+}
+
+#[tokio::test]
+async fn test_transaction_rollback_with_sqlite_pool() {
+    let (_dir, conn) = prepare_sqlite_pool();
+    test_transaction_rollback(conn, TestSemantics::Sqlite).await;
+}
+
+#[tokio::test]
+async fn test_transaction_commit_with_sqlite_pool() {
+    let (_dir, conn) = prepare_sqlite_pool();
+    test_transaction_commit(conn, TestSemantics::Sqlite).await;
+}
+
+#[tokio::test]
+async fn test_transaction_savepoints_with_sqlite_pool() {
+    let (_dir, conn) = prepare_sqlite_pool();
+    test_transaction_savepoints(conn).await;
+}
+
+#[tokio::test]
+async fn test_busy_timeout_with_sqlite_pool() {
+    let (_dir, conn) = prepare_sqlite_pool();
+    let Connection::Sqlite(multithread_con) = conn else {
+        panic!("not a sqlite connection");
+    };
+    for query_type in [SqliteQueryType::Read, SqliteQueryType::Write] {
+        let con = multithread_con
+            .acquire_sqlite_connection(query_type)
+            .await
+            .unwrap();
+        let busy_timeout: i64 = con
+            .query_row("PRAGMA busy_timeout", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(busy_timeout, 5000);
+    }
+}
+
+#[tokio::test]
+async fn test_read_during_transaction_with_sqlite_pool() {
+    let (_dir, conn) = prepare_sqlite_pool();
+    test_read_during_transaction(conn).await;
+}
+
+fn sqlite_busy() -> Error {
+    crate::rusqlite::Error::SqliteFailure(ffi::Error::new(ffi::SQLITE_BUSY), None).into()
+}
+
+#[test]
+fn test_is_transient_error() {
+    assert!(is_transient_error(&sqlite_busy()));
+    assert!(is_transient_error(&sqlite_busy().context("while reading")));
+    assert!(!is_transient_error(&Error::msg("not transient")));
+}
+
+#[tokio::test]
+async fn test_read_policy_retries_transient_errors() {
+    let connections = SqlConnections::new_single(prepare_sqlite_con());
+    let policy = ReadPolicy::new("test")
+        .max_attempts(3)
+        .backoff(Duration::from_millis(1), Duration::from_millis(1));
+    let attempts = Arc::new(AtomicUsize::new(0));
+
+    let value = policy
+        .read(&connections, |_conn| {
+            let attempts = attempts.clone();
+            async move {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err(sqlite_busy())
+                } else {
+                    Ok(42)
+                }
+            }
+        })
+        .await
+        .unwrap();
+    assert_eq!(value, 42);
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+    attempts.store(0, Ordering::SeqCst);
+    let res: Result<(), Error> = policy
+        .read(&connections, |_conn| {
+            let attempts = attempts.clone();
+            async move {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(sqlite_busy())
+            }
+        })
+        .await;
+    assert!(res.is_err());
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn test_read_policy_does_not_retry_other_errors() {
+    let connections = SqlConnections::new_single(prepare_sqlite_con());
+    let policy = ReadPolicy::new("test").max_attempts(3);
+    let attempts = Arc::new(AtomicUsize::new(0));
+
+    let res: Result<(), Error> = policy
+        .read(&connections, |_conn| {
+            let attempts = attempts.clone();
+            async move {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(Error::msg("not transient"))
+            }
+        })
+        .await;
+    assert!(res.is_err());
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_read_policy_stays_on_sqlite_replica() {
+    let connections = SqlConnections::new_single(prepare_sqlite_con());
+    assert_eq!(
+        connections.read_connection.replica_lag().await.unwrap(),
+        None
+    );
+    let policy = ReadPolicy::new("test").max_replica_lag(Duration::ZERO, Duration::from_secs(1));
+    let value = policy
+        .read(&connections, |conn| async move { conn.replica_lag().await })
+        .await
+        .unwrap();
+    assert_eq!(value, None);
+}
+
+fn sqlite_con_with_value(x: i64) -> Connection {
+    let conn = SqliteConnection::open_in_memory().unwrap();
+    conn.execute_batch(&format!(
+        "CREATE TABLE t(x INTEGER); INSERT INTO t VALUES ({x});"
+    ))
+    .unwrap();
+    Connection::with_sqlite(conn)
+}
+
+async fn read_value(conn: Connection) -> Result<i64, Error> {
+    let Connection::Sqlite(multithread_con) = conn else {
+        panic!("not a sqlite connection");
+    };
+    let con = multithread_con
+        .acquire_sqlite_connection(SqliteQueryType::Read)
+        .await?;
+    Ok(con.query_row("SELECT x FROM t", [], |row| row.get(0))?)
+}
+
+#[tokio::test]
+async fn test_read_policy_falls_back_to_master() {
+    let master = sqlite_con_with_value(1);
+    let connections = SqlConnections {
+        write_connection: master.clone(),
+        read_connection: sqlite_con_with_value(2),
+        read_master_connection: master,
+    };
+    let policy = ReadPolicy::new("test_fallback")
+        .max_replica_lag(Duration::from_secs(1), Duration::from_secs(3600));
+
+    policy.record_replica_lag(Some(Duration::from_secs(1)));
+    let (value, stats) =
+        with_recorded_stats_async(Box::pin(policy.read(&connections, read_value))).await;
+    assert_eq!(value.unwrap(), 2);
+    assert_eq!(
+        stats.total("sql.read_policy.test_fallback.fallbacks_to_master"),
+        0
+    );
+
+    policy.record_replica_lag(Some(Duration::from_secs(5)));
+    let (value, stats) =
+        with_recorded_stats_async(Box::pin(policy.read(&connections, read_value))).await;
+    assert_eq!(value.unwrap(), 1);
+    assert_eq!(
+        stats.total("sql.read_policy.test_fallback.fallbacks_to_master"),
+        1
+    );
+}
+
+#[test]
+fn test_oss_query() {
+    test_oss_query_sql_and_params();
+}
+
 #[cfg(fbcode_build)]
 #[cfg(test)]
 mod mysql {
@@ -124,6 +355,13 @@ mod mysql {
         Ok(())
     }
 
+    #[fbinit::test]
+    async fn test_mysql_transaction_savepoints(fb: FacebookInit) -> Result<()> {
+        let conn = setup_connection(fb).await?;
+        test_transaction_savepoints(conn).await;
+        Ok(())
+    }
+
     #[fbinit::test]
     async fn test_mysql_basic_read_query_telemetry(fb: FacebookInit) -> Result<()> {
         let conn = setup_connection(fb).await?;
@@ -151,3 +389,41 @@ mod mysql {
         Ok(())
     }
 }
+
+/// Tests run against the database at `SQL_TEST_POSTGRES_URL`, and are skipped when it is unset.
+mod postgres {
+    use anyhow::Context;
+    use anyhow::Result;
+    use sql_tests_lib::postgres_test_lib::setup_postgres_test_connection;
+    use sql_tests_lib::postgres_test_lib::test_postgres_queries;
+    use sql_tests_lib::postgres_test_lib::test_postgres_transaction_savepoints;
+    use sql_tests_lib::postgres_test_lib::test_postgres_transactions;
+
+    use super::*;
+
+    // These tests need a Postgres server, so they are ignored by default. Run them with
+    // `SQL_TEST_POSTGRES_URL` set to its connection string and `cargo test -- --ignored`.
+    async fn setup_connection() -> Result<Connection> {
+        let url = std::env::var("SQL_TEST_POSTGRES_URL")
+            .context("SQL_TEST_POSTGRES_URL must be set to run the Postgres tests")?;
+        setup_postgres_test_connection(&url).await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres server, see setup_connection"]
+    async fn test_postgres_basic_queries() -> Result<()> {
+        test_postgres_queries(setup_connection().await?).await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres server, see setup_connection"]
+    async fn test_postgres_transaction() -> Result<()> {
+        test_postgres_transactions(setup_connection().await?).await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres server, see setup_connection"]
+    async fn test_postgres_savepoints() -> Result<()> {
+        test_postgres_transaction_savepoints(setup_connection().await?).await
+    }
+}
@@ -16,6 +16,7 @@ use rand::Rng;
 use rand::distributions::Alphanumeric;
 use rand::thread_rng;
 use sql::Connection;
+use sql::RowDecodeError;
 use sql::Transaction;
 use sql::anyhow::Error;
 use sql::mysql_async::FromValueError;
@@ -23,6 +24,7 @@ use sql::mysql_async::Value;
 use sql::mysql_async::prelude::*;
 use sql::queries;
 use sql::sql_common::mysql;
+use sql::sql_common::mysql::OssQuery;
 
 pub struct A;
 
@@ -110,6 +112,22 @@ queries! {
     read TestQuery14(date: NaiveDateTime) -> (String) {
         "SELECT datetime(y) FROM foo WHERE y = {date}"
     }
+
+    read TestQuery15() -> (u64, i64) {
+        "SELECT 44, NULL"
+    }
+
+    read OssSelect(id: u64, name: String, >list ids: u64) -> (u64) {
+        "SELECT id FROM foo WHERE name = {name} AND (id = {id} OR id IN {ids}) AND {id} > 0"
+    }
+    write OssInsert(values: (x: i64, name: String), y: i64) {
+        none,
+        "INSERT INTO foo (x, name, y) VALUES {values} ON DUPLICATE KEY UPDATE y = {y}"
+    }
+    write OssInsertOrIgnore(x: i64, name: String) {
+        insert_or_ignore,
+        "{insert_or_ignore} INTO foo (x, name) VALUES ({x}, {name})"
+    }
 }
 
 pub async fn test_basic_query(conn: Connection) -> Result<(), Error> {
@@ -163,6 +181,24 @@ pub async fn test_read_query(conn: Connection, semantics: TestSemantics) {
     );
 }
 
+pub async fn test_read_query_decode_error(conn: Connection) {
+    let err = TestQuery15::query(&conn).await.unwrap_err();
+    let err = err
+        .downcast_ref::<RowDecodeError>()
+        .expect("should fail with a RowDecodeError");
+    assert_eq!(err.query, "TestQuery15");
+    assert_eq!(err.column, 1);
+    assert_eq!(err.expected_type, "i64");
+    assert_eq!(err.value, Value::NULL);
+
+    let transaction = conn.start_transaction().await.unwrap();
+    let err = TestQuery15::query_with_transaction(transaction)
+        .await
+        .err()
+        .expect("should fail to decode the row");
+    assert!(err.downcast_ref::<RowDecodeError>().is_some());
+}
+
 pub async fn test_datetime_query(conn: Connection) {
     let date = NaiveDate::from_ymd_opt(2021, 1, 21)
         .unwrap()
@@ -231,6 +267,64 @@ pub enum TestSemantics {
     Mysql,
 }
 
+fn text(value: &str) -> Value {
+    Value::Bytes(value.as_bytes().to_vec())
+}
+
+/// Check the SQL and parameters of MySQL queries run by an `OssConnection`,
+/// with and without prepared statements.
+pub fn test_oss_query_sql_and_params() {
+    let (a, b) = ("a".to_owned(), "b'c".to_owned());
+
+    assert_eq!(
+        OssSelect::oss_query(true, &1, &b, &[2, 3]),
+        OssQuery::Prepared(
+            "SELECT id FROM foo WHERE name = ? AND (id = ? OR id IN (?, ?)) AND ? > 0".to_owned(),
+            vec![
+                text("b'c"),
+                Value::UInt(1),
+                Value::UInt(2),
+                Value::UInt(3),
+                Value::UInt(1),
+            ],
+        )
+    );
+    assert_eq!(
+        OssSelect::oss_query(false, &1, &b, &[2, 3]),
+        OssQuery::Text(
+            "SELECT id FROM foo WHERE name = 'b\\'c' AND (id = 1 OR id IN (2, 3)) AND 1 > 0"
+                .to_owned()
+        )
+    );
+
+    assert_eq!(
+        OssInsert::oss_query(true, &[(&1, &a), (&2, &b)], &3),
+        OssQuery::Prepared(
+            "INSERT INTO foo (x, name, y) VALUES (?, ?), (?, ?) ON DUPLICATE KEY UPDATE y = ?"
+                .to_owned(),
+            vec![
+                Value::Int(1),
+                text("a"),
+                Value::Int(2),
+                text("b'c"),
+                Value::Int(3),
+            ],
+        )
+    );
+
+    assert_eq!(
+        OssInsertOrIgnore::oss_query(true, &1, &a),
+        OssQuery::Prepared(
+            "INSERT IGNORE INTO foo (x, name) VALUES (?, ?)".to_owned(),
+            vec![Value::Int(1), text("a")],
+        )
+    );
+    assert_eq!(
+        OssInsertOrIgnore::oss_query(false, &1, &a),
+        OssQuery::Text("INSERT IGNORE INTO foo (x, name) VALUES (1, 'a')".to_owned())
+    );
+}
+
 pub async fn in_transaction(transaction: Transaction, semantics: TestSemantics) -> Transaction {
     let (transaction, res) = TestQuery3::query_with_transaction(transaction, &[(&44,)])
         .await
@@ -299,6 +393,62 @@ pub async fn test_transaction_commit(conn: Connection, semantics: TestSemantics)
     );
 }
 
+pub async fn test_transaction_savepoints(conn: Connection) {
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&44,)])
+        .await
+        .unwrap();
+
+    let transaction = transaction.savepoint("first").await.unwrap();
+    let (transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&72,)])
+        .await
+        .unwrap();
+    let transaction = transaction.savepoint("second").await.unwrap();
+    let (transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&53,)])
+        .await
+        .unwrap();
+    let transaction = transaction.rollback_to_savepoint("first").await.unwrap();
+
+    let transaction = transaction.savepoint("third").await.unwrap();
+    let (transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&12,)])
+        .await
+        .unwrap();
+    let transaction = transaction.release_savepoint("third").await.unwrap();
+    let transaction = transaction.release_savepoint("first").await.unwrap();
+
+    let (transaction, res) = TestQuery4::query_with_transaction(transaction, &1, &10)
+        .await
+        .unwrap();
+    assert_eq!(res, vec![(44,), (12,)]);
+    transaction.commit().await.unwrap();
+    assert_eq!(
+        TestQuery4::query(&conn, &1, &10).await.unwrap(),
+        vec![(44,), (12,)]
+    );
+
+    let transaction = conn.start_transaction().await.unwrap();
+    assert!(transaction.savepoint("not a name").await.is_err());
+    let transaction = conn.start_transaction().await.unwrap();
+    assert!(transaction.release_savepoint("unknown").await.is_err());
+}
+
+/// Only for connections that serve reads concurrently with an open transaction, e.g. a pool of
+/// Sqlite connections.
+pub async fn test_read_during_transaction(conn: Connection) {
+    let transaction = conn.start_transaction().await.unwrap();
+    let (transaction, _) = TestQuery3::query_with_transaction(transaction, &[(&44,), (&72,)])
+        .await
+        .unwrap();
+
+    // Reads don't wait for the transaction, and don't see its writes until it is committed.
+    assert_eq!(TestQuery4::query(&conn, &1, &2).await.unwrap(), vec![]);
+    transaction.commit().await.unwrap();
+    assert_eq!(
+        TestQuery4::query(&conn, &1, &2).await.unwrap(),
+        vec![(44,), (72,)]
+    );
+}
+
 pub async fn test_query_visibility_modifiers_compile(conn: Connection) {
     mod b {
         use crate::queries;
@@ -452,3 +602,178 @@ pub mod mysql_test_lib {
         Ok(Connection::from(conn))
     }
 }
+
+pub mod postgres_test_lib {
+    use sql::anyhow::Result;
+    use sql::postgres::PostgresConnection;
+    use sql::postgres::tokio_postgres;
+
+    use super::*;
+
+    queries! {
+        write PgInsert(values: (x: i64, test: String)) {
+            none,
+            mysql("INSERT INTO foo (x, test) VALUES {values}")
+            sqlite("INSERT INTO foo (x, test) VALUES {values}")
+            postgres("INSERT INTO foo (x, test) VALUES {values} RETURNING id")
+        }
+        write PgInsertOrIgnore(values: (id: u64, x: i64)) {
+            insert_or_ignore,
+            "{insert_or_ignore} INTO foo (id, x) VALUES {values}"
+        }
+        write PgInsertOrIgnoreReturning(values: (id: u64, x: i64)) {
+            insert_or_ignore,
+            mysql("{insert_or_ignore} INTO foo (id, x) VALUES {values}")
+            sqlite("{insert_or_ignore} INTO foo (id, x) VALUES {values}")
+            postgres("{insert_or_ignore} INTO foo (id, x) VALUES {values} {on_conflict} RETURNING id")
+        }
+        write PgUpdate(x: i64, >list ids: u64) {
+            none,
+            "UPDATE foo SET x = {x} WHERE id IN {ids}"
+        }
+        read PgSelect(id1: u64, id2: u64) -> (i64, Option<String>) {
+            "SELECT x, test FROM foo WHERE {id1} <= id AND id <= {id2} ORDER BY id"
+        }
+        read PgSelectList(>list ids: u64) -> (i64) {
+            "SELECT x FROM foo WHERE id IN {ids} ORDER BY id"
+        }
+        read PgSelectTypes(s: String, f: f64, date: NaiveDateTime) -> (String, f64, NaiveDateTime, bool) {
+            mysql("SELECT {s}, {f}, {date}, TRUE")
+            sqlite("SELECT {s}, {f}, {date}, TRUE")
+            postgres("SELECT {s}::TEXT, {f}::FLOAT8, {date}::TIMESTAMP, TRUE")
+        }
+        read PgSelectNull() -> (i64) {
+            "SELECT NULL::INT8"
+        }
+    }
+
+    /// Connect to the Postgres database at `url`, in a new schema holding the
+    /// `foo` table used by the tests of this module.
+    pub async fn setup_postgres_test_connection(url: &str) -> Result<Connection> {
+        let schema: String = thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(16)
+            .map(|c| char::from(c).to_ascii_lowercase())
+            .collect();
+        let schema = format!("sql_test_{schema}");
+
+        let mut config: tokio_postgres::Config = url.parse()?;
+        let conn = PostgresConnection::from_config(config.clone(), 1)?;
+        conn.pool()
+            .get()
+            .await?
+            .batch_execute(&format!("CREATE SCHEMA {schema}"))
+            .await?;
+
+        config.options(format!("-c search_path={schema}"));
+        let conn = PostgresConnection::from_config(config, 4)?;
+        conn.pool()
+            .get()
+            .await?
+            .batch_execute(
+                "CREATE TABLE foo(
+                    id SERIAL PRIMARY KEY,
+                    x BIGINT,
+                    test TEXT
+                )",
+            )
+            .await?;
+        Ok(Connection::from(conn))
+    }
+
+    pub async fn test_postgres_queries(conn: Connection) -> Result<()> {
+        let a = "a".to_owned();
+        let b = "b".to_owned();
+        let res = PgInsert::commented_query(&conn, "comment", &[(&44, &a)]).await?;
+        assert_eq!(res.affected_rows(), 1);
+        assert_eq!(res.last_insert_id(), Some(1));
+
+        let res = PgInsert::query(&conn, &[(&72, &b), (&53, &b)]).await?;
+        assert_eq!(res.affected_rows(), 2);
+        assert_eq!(res.last_insert_id(), Some(3));
+
+        let res = PgInsertOrIgnore::query(&conn, &[(&3, &1), (&4, &2)]).await?;
+        assert_eq!(res.affected_rows(), 1);
+        assert_eq!(res.last_insert_id(), None);
+
+        let res = PgInsertOrIgnoreReturning::query(&conn, &[(&1, &3), (&5, &4)]).await?;
+        assert_eq!(res.affected_rows(), 1);
+        assert_eq!(res.last_insert_id(), Some(5));
+
+        assert_eq!(
+            PgSelect::query(&conn, &1, &4).await?,
+            vec![
+                (44, Some(a)),
+                (72, Some(b.clone())),
+                (53, Some(b)),
+                (2, None)
+            ]
+        );
+
+        let res = PgUpdate::query(&conn, &123, &[1, 2]).await?;
+        assert_eq!(res.affected_rows(), 2);
+        assert_eq!(
+            PgSelectList::query(&conn, &[1, 2, 3]).await?,
+            vec![(123,), (123,), (53,)]
+        );
+
+        let date = NaiveDate::from_ymd_opt(2021, 1, 21)
+            .unwrap()
+            .and_hms_micro_opt(21, 21, 21, 123456)
+            .unwrap();
+        assert_eq!(
+            PgSelectTypes::query(&conn, &"s".to_owned(), &0.5, &date).await?,
+            vec![("s".to_owned(), 0.5, date, true)]
+        );
+
+        let err = PgSelectNull::query(&conn).await.unwrap_err();
+        assert!(err.downcast_ref::<RowDecodeError>().is_some());
+        Ok(())
+    }
+
+    pub async fn test_postgres_transactions(conn: Connection) -> Result<()> {
+        let transaction = conn.start_transaction().await?;
+        let (transaction, res) =
+            PgInsert::query_with_transaction(transaction, &[(&1, &"a".to_owned())]).await?;
+        assert_eq!(res.last_insert_id(), Some(1));
+        let (transaction, res) = PgSelect::query_with_transaction(transaction, &1, &1).await?;
+        assert_eq!(res, vec![(1, Some("a".to_owned()))]);
+        transaction.rollback().await?;
+        assert_eq!(PgSelect::query(&conn, &1, &10).await?, vec![]);
+
+        let transaction = conn.start_transaction().await?;
+        let (transaction, _) =
+            PgInsert::query_with_transaction(transaction, &[(&2, &"b".to_owned())]).await?;
+        drop(transaction);
+        assert_eq!(PgSelect::query(&conn, &1, &10).await?, vec![]);
+
+        let transaction = conn.start_transaction().await?;
+        let (transaction, res) =
+            PgInsert::query_with_transaction(transaction, &[(&3, &"c".to_owned())]).await?;
+        let (transaction, _) =
+            PgUpdate::query_with_transaction(transaction, &4, &[res.last_insert_id().unwrap()])
+                .await?;
+        transaction.commit().await?;
+        assert_eq!(
+            PgSelect::query(&conn, &1, &10).await?,
+            vec![(4, Some("c".to_owned()))]
+        );
+        Ok(())
+    }
+
+    pub async fn test_postgres_transaction_savepoints(conn: Connection) -> Result<()> {
+        let a = "a".to_owned();
+        let transaction = conn.start_transaction().await?;
+        let transaction = transaction.savepoint("first").await?;
+        let (transaction, _) = PgInsert::query_with_transaction(transaction, &[(&1, &a)]).await?;
+        let transaction = transaction.rollback_to_savepoint("first").await?;
+        let (transaction, _) = PgInsert::query_with_transaction(transaction, &[(&2, &a)]).await?;
+        let transaction = transaction.release_savepoint("first").await?;
+        transaction.commit().await?;
+        assert_eq!(PgSelect::query(&conn, &1, &10).await?, vec![(2, Some(a))]);
+
+        let transaction = conn.start_transaction().await?;
+        assert!(transaction.release_savepoint("unknown").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,1181 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+//! In-process implementation of [StatsManagerFactory] that keeps the values
+//! of all stats in memory, so that they can be read back through a
+//! [StatsSnapshot]. This is the default stats backend for non-fbcode builds.
+//!
+//! Thread local stats (counters, timeseries and histograms) buffer writes on
+//! the thread that makes them and are merged into the shared [StatsRegistry]
+//! whenever [StatsManager::aggregate] is called, which normally happens
+//! periodically thanks to [crate::schedule_stats_aggregation_preview].
+//! Quantile stats are not thread local, so they are written to the registry
+//! directly, and so are singleton counters, which live in [global_registry]
+//! (see [InMemorySingletonCounter]).
+//!
+//! Timeseries and quantile stats are aggregated over the intervals they were
+//! created with, with a resolution of one second. Histograms are aggregated
+//! over the default intervals, but their buckets and percentiles cover the
+//! whole lifetime of the histogram.
+
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+use fbinit::FacebookInit;
+use stats_traits::stat_types::BoxHistogram;
+use stats_traits::stat_types::BoxLocalCounter;
+use stats_traits::stat_types::BoxLocalHistogram;
+use stats_traits::stat_types::BoxLocalTimeseries;
+use stats_traits::stat_types::Counter;
+use stats_traits::stat_types::Histogram;
+use stats_traits::stat_types::SingletonCounter;
+use stats_traits::stat_types::Timeseries;
+use stats_traits::stats_manager::AggregationType;
+use stats_traits::stats_manager::BoxStatsManager;
+use stats_traits::stats_manager::BucketConfig;
+use stats_traits::stats_manager::StatsManager;
+use stats_traits::stats_manager::StatsManagerFactory;
+
+/// Intervals used when a stat is created without any, matching the defaults
+/// of Folly's timeseries.
+const DEFAULT_INTERVALS: [Duration; 3] = [
+    Duration::from_secs(60),
+    Duration::from_secs(600),
+    Duration::from_secs(3600),
+];
+const DEFAULT_AGGREGATION_TYPES: [AggregationType; 1] = [AggregationType::Average];
+const DEFAULT_HISTOGRAM_PERCENTILES: [u8; 3] = [50, 95, 99];
+const DEFAULT_QUANTILE_PERCENTILES: [f32; 3] = [50.0, 95.0, 99.0];
+/// Upper bound on the number of samples a quantile stat keeps around to
+/// estimate its percentiles, older samples are discarded first.
+const MAX_QUANTILE_SAMPLES: usize = 100_000;
+
+static GLOBAL_REGISTRY: LazyLock<Arc<StatsRegistry>> =
+    LazyLock::new(|| Arc::new(StatsRegistry::new()));
+
+/// Returns the process wide registry used by [InMemoryStatsFactory::default].
+pub fn global_registry() -> Arc<StatsRegistry> {
+    GLOBAL_REGISTRY.clone()
+}
+
+/// [StatsManagerFactory] that creates [InMemoryStatsManager]s, all of them
+/// aggregating into the same [StatsRegistry].
+pub struct InMemoryStatsFactory {
+    registry: Arc<StatsRegistry>,
+}
+
+impl InMemoryStatsFactory {
+    /// Create a factory whose stats are aggregated into the given registry.
+    pub fn new(registry: Arc<StatsRegistry>) -> Self {
+        Self { registry }
+    }
+
+    /// The registry into which stats created by this factory are aggregated.
+    pub fn registry(&self) -> &Arc<StatsRegistry> {
+        &self.registry
+    }
+}
+
+impl Default for InMemoryStatsFactory {
+    /// Create a factory that aggregates into [global_registry].
+    fn default() -> Self {
+        Self::new(global_registry())
+    }
+}
+
+impl StatsManagerFactory for InMemoryStatsFactory {
+    fn create(&self) -> BoxStatsManager {
+        Box::new(InMemoryStatsManager::new(self.registry.clone()))
+    }
+}
+
+/// Sum and number of samples added to a stat.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Sample {
+    sum: i64,
+    count: u64,
+}
+
+impl Sample {
+    fn new(value: i64, nsamples: u32) -> Self {
+        Self {
+            sum: value,
+            count: u64::from(nsamples),
+        }
+    }
+
+    fn merge(&mut self, other: Sample) {
+        self.sum = self.sum.saturating_add(other.sum);
+        self.count = self.count.saturating_add(other.count);
+    }
+
+    fn aggregate(&self, aggregation_type: AggregationType, elapsed_secs: u64) -> f64 {
+        let average = if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        };
+        match aggregation_type {
+            AggregationType::Sum => self.sum as f64,
+            AggregationType::Count => self.count as f64,
+            AggregationType::Average => average,
+            AggregationType::Rate => self.sum as f64 / elapsed_secs.max(1) as f64,
+            AggregationType::Percent => average * 100.0,
+        }
+    }
+}
+
+/// Samples bucketed per second, retained for as long as the longest interval
+/// that is being aggregated.
+#[derive(Debug)]
+struct Window {
+    buckets: VecDeque<(u64, Sample)>,
+    retention_secs: u64,
+}
+
+impl Window {
+    fn new(intervals: &[Duration]) -> Self {
+        Self {
+            buckets: VecDeque::new(),
+            retention_secs: intervals.iter().map(Duration::as_secs).max().unwrap_or(0),
+        }
+    }
+
+    fn add(&mut self, now: u64, sample: Sample) {
+        match self.buckets.back_mut() {
+            // Concurrent aggregations might race and arrive slightly out of
+            // order, in that case just account them to the latest bucket.
+            Some((sec, last)) if *sec >= now => last.merge(sample),
+            _ => self.buckets.push_back((now, sample)),
+        }
+        while let Some((sec, _)) = self.buckets.front() {
+            if sec + self.retention_secs > now {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    fn sum_over(&self, now: u64, interval: Duration) -> Sample {
+        let mut total = Sample::default();
+        for (sec, sample) in self.buckets.iter().rev() {
+            if sec + interval.as_secs() <= now {
+                break;
+            }
+            total.merge(*sample);
+        }
+        total
+    }
+}
+
+fn aggregated_values(
+    window: &Window,
+    aggregation_types: &[AggregationType],
+    intervals: &[Duration],
+    created: u64,
+    now: u64,
+) -> Vec<AggregatedValue> {
+    let mut values = Vec::with_capacity(aggregation_types.len() * intervals.len());
+    for interval in intervals {
+        let sample = window.sum_over(now, *interval);
+        // Rates of stats younger than the interval are computed over their
+        // lifetime, so that they are not underestimated right after startup.
+        let elapsed_secs = interval.as_secs().min(now.saturating_sub(created) + 1);
+        for aggregation_type in aggregation_types {
+            values.push(AggregatedValue {
+                aggregation_type: *aggregation_type,
+                interval: *interval,
+                value: sample.aggregate(*aggregation_type, elapsed_secs),
+            });
+        }
+    }
+    values
+}
+
+fn or_default<'a, T>(values: &'a [T], default: &'a [T]) -> &'a [T] {
+    if values.is_empty() { default } else { values }
+}
+
+/// Bucketed samples of a histogram. The first bucket holds samples below
+/// `min`, the last one samples at or above `max`.
+#[derive(Debug)]
+struct Buckets {
+    config: BucketConfig,
+    counts: Vec<u64>,
+    total: Sample,
+}
+
+impl Buckets {
+    fn new(config: BucketConfig) -> Self {
+        let width = config.width.max(1);
+        let range = config.max.saturating_sub(config.min);
+        let len = range.div_ceil(width) as usize + 2;
+        Self {
+            config,
+            counts: vec![0; len],
+            total: Sample::default(),
+        }
+    }
+
+    fn index(&self, value: i64) -> usize {
+        let min = i64::from(self.config.min);
+        if value < min {
+            0
+        } else if value >= i64::from(self.config.max) {
+            self.counts.len() - 1
+        } else {
+            1 + ((value - min) / i64::from(self.config.width.max(1))) as usize
+        }
+    }
+
+    fn add(&mut self, value: i64, nsamples: u32) {
+        let index = self.index(value);
+        self.counts[index] += u64::from(nsamples);
+        self.total.merge(Sample::new(
+            value.saturating_mul(i64::from(nsamples)),
+            nsamples,
+        ));
+    }
+
+    /// Moves all samples from `self` into `other`, leaving `self` empty.
+    fn drain_into(&mut self, other: &mut Buckets) -> Sample {
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts.iter_mut()) {
+            *other_count += std::mem::take(count);
+        }
+        let total = std::mem::take(&mut self.total);
+        other.total.merge(total);
+        total
+    }
+
+    fn percentile(&self, percentile: f64) -> Option<f64> {
+        if self.total.count == 0 {
+            return None;
+        }
+        let last = self.counts.len() - 1;
+        let target = percentile / 100.0 * self.total.count as f64;
+        let mut seen = 0.0;
+        for (index, count) in self.counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let count = *count as f64;
+            if seen + count >= target {
+                if index == 0 {
+                    return Some(f64::from(self.config.min));
+                }
+                if index == last {
+                    return Some(f64::from(self.config.max));
+                }
+                let width = f64::from(self.config.width.max(1));
+                let lower = f64::from(self.config.min) + (index - 1) as f64 * width;
+                let value = lower + (target - seen) / count * width;
+                return Some(value.min(f64::from(self.config.max)));
+            }
+            seen += count;
+        }
+        Some(f64::from(self.config.max))
+    }
+}
+
+#[derive(Debug)]
+struct TimeseriesState {
+    aggregation_types: Vec<AggregationType>,
+    intervals: Vec<Duration>,
+    created: u64,
+    total: Sample,
+    window: Window,
+}
+
+impl TimeseriesState {
+    fn add(&mut self, now: u64, sample: Sample) {
+        self.total.merge(sample);
+        self.window.add(now, sample);
+    }
+
+    fn snapshot(&self, now: u64) -> TimeseriesSnapshot {
+        TimeseriesSnapshot {
+            sum: self.total.sum,
+            count: self.total.count,
+            values: aggregated_values(
+                &self.window,
+                &self.aggregation_types,
+                &self.intervals,
+                self.created,
+                now,
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HistogramState {
+    aggregation_types: Vec<AggregationType>,
+    percentiles: Vec<u8>,
+    created: u64,
+    buckets: Buckets,
+    window: Window,
+}
+
+impl HistogramState {
+    fn snapshot(&self, now: u64) -> HistogramSnapshot {
+        HistogramSnapshot {
+            bucket_config: self.buckets.config,
+            buckets: self.buckets.counts.clone(),
+            sum: self.buckets.total.sum,
+            count: self.buckets.total.count,
+            values: aggregated_values(
+                &self.window,
+                &self.aggregation_types,
+                &DEFAULT_INTERVALS,
+                self.created,
+                now,
+            ),
+            percentiles: self
+                .percentiles
+                .iter()
+                .filter_map(|p| Some((*p, self.buckets.percentile(f64::from(*p))?)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct QuantileStatState {
+    aggregation_types: Vec<AggregationType>,
+    percentiles: Vec<f32>,
+    intervals: Vec<Duration>,
+    created: u64,
+    total: Sample,
+    window: Window,
+    samples: VecDeque<(u64, i64)>,
+}
+
+impl QuantileStatState {
+    fn add(&mut self, now: u64, value: i64, nsamples: u32) {
+        let sample = Sample::new(value.saturating_mul(i64::from(nsamples)), nsamples);
+        self.total.merge(sample);
+        self.window.add(now, sample);
+
+        for _ in 0..(nsamples as usize).min(MAX_QUANTILE_SAMPLES) {
+            if self.samples.len() == MAX_QUANTILE_SAMPLES {
+                self.samples.pop_front();
+            }
+            self.samples.push_back((now, value));
+        }
+        while let Some((sec, _)) = self.samples.front() {
+            if sec + self.window.retention_secs > now {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    fn snapshot(&self, now: u64) -> QuantileStatSnapshot {
+        let mut percentiles = Vec::with_capacity(self.percentiles.len() * self.intervals.len());
+        for interval in &self.intervals {
+            let mut values: Vec<i64> = self
+                .samples
+                .iter()
+                .rev()
+                .take_while(|(sec, _)| sec + interval.as_secs() > now)
+                .map(|(_, value)| *value)
+                .collect();
+            if values.is_empty() {
+                continue;
+            }
+            values.sort_unstable();
+            for percentile in &self.percentiles {
+                let rank = (f64::from(*percentile) / 100.0 * values.len() as f64).ceil() as usize;
+                percentiles.push(QuantileValue {
+                    percentile: *percentile,
+                    interval: *interval,
+                    value: values[rank.clamp(1, values.len()) - 1] as f64,
+                });
+            }
+        }
+
+        QuantileStatSnapshot {
+            sum: self.total.sum,
+            count: self.total.count,
+            values: aggregated_values(
+                &self.window,
+                &self.aggregation_types,
+                &self.intervals,
+                self.created,
+                now,
+            ),
+            percentiles,
+        }
+    }
+}
+
+/// Value of a stat aggregated over an interval.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AggregatedValue {
+    /// How the samples were aggregated.
+    pub aggregation_type: AggregationType,
+    /// Over what time period the samples were aggregated.
+    pub interval: Duration,
+    /// The aggregated value.
+    pub value: f64,
+}
+
+/// Estimated percentile of a quantile stat over an interval.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuantileValue {
+    /// The percentile, e.g. `99.0` for P99.
+    pub percentile: f32,
+    /// Over what time period the samples were considered.
+    pub interval: Duration,
+    /// The estimated value.
+    pub value: f64,
+}
+
+fn find_value(
+    values: &[AggregatedValue],
+    aggregation_type: AggregationType,
+    interval: Duration,
+) -> Option<f64> {
+    values
+        .iter()
+        .find(|v| v.aggregation_type == aggregation_type && v.interval == interval)
+        .map(|v| v.value)
+}
+
+/// State of a timeseries at the time of the snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeseriesSnapshot {
+    /// Sum of all samples added over the lifetime of the timeseries.
+    pub sum: i64,
+    /// Number of samples added over the lifetime of the timeseries.
+    pub count: u64,
+    /// Values for every aggregation type and interval of the timeseries.
+    pub values: Vec<AggregatedValue>,
+}
+
+impl TimeseriesSnapshot {
+    /// Get the value aggregated with the given type over the given interval.
+    pub fn get(&self, aggregation_type: AggregationType, interval: Duration) -> Option<f64> {
+        find_value(&self.values, aggregation_type, interval)
+    }
+}
+
+/// State of a histogram at the time of the snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistogramSnapshot {
+    /// The configuration the histogram was created with.
+    pub bucket_config: BucketConfig,
+    /// Number of samples in each bucket. The first bucket counts samples
+    /// below `bucket_config.min`, the last one samples at or above
+    /// `bucket_config.max` and the ones in between are `bucket_config.width`
+    /// wide.
+    pub buckets: Vec<u64>,
+    /// Sum of all samples added over the lifetime of the histogram.
+    pub sum: i64,
+    /// Number of samples added over the lifetime of the histogram.
+    pub count: u64,
+    /// Values for every aggregation type of the histogram over the default
+    /// intervals.
+    pub values: Vec<AggregatedValue>,
+    /// Percentiles estimated from the buckets.
+    pub percentiles: Vec<(u8, f64)>,
+}
+
+impl HistogramSnapshot {
+    /// Get the value aggregated with the given type over the given interval.
+    pub fn get(&self, aggregation_type: AggregationType, interval: Duration) -> Option<f64> {
+        find_value(&self.values, aggregation_type, interval)
+    }
+
+    /// Get the estimate of the given percentile.
+    pub fn percentile(&self, percentile: u8) -> Option<f64> {
+        self.percentiles
+            .iter()
+            .find(|(p, _)| *p == percentile)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// State of a quantile stat at the time of the snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuantileStatSnapshot {
+    /// Sum of all samples added over the lifetime of the quantile stat.
+    pub sum: i64,
+    /// Number of samples added over the lifetime of the quantile stat.
+    pub count: u64,
+    /// Values for every aggregation type and interval of the quantile stat.
+    pub values: Vec<AggregatedValue>,
+    /// Percentiles for every interval that had any samples in it.
+    pub percentiles: Vec<QuantileValue>,
+}
+
+impl QuantileStatSnapshot {
+    /// Get the value aggregated with the given type over the given interval.
+    pub fn get(&self, aggregation_type: AggregationType, interval: Duration) -> Option<f64> {
+        find_value(&self.values, aggregation_type, interval)
+    }
+
+    /// Get the estimate of the given percentile over the given interval.
+    pub fn percentile(&self, percentile: f32, interval: Duration) -> Option<f64> {
+        self.percentiles
+            .iter()
+            .find(|v| v.percentile == percentile && v.interval == interval)
+            .map(|v| v.value)
+    }
+}
+
+/// Values of all stats in a [StatsRegistry] at a point in time, keyed by the
+/// stat keys.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StatsSnapshot {
+    /// Values of counters.
+    pub counters: BTreeMap<String, i64>,
+    /// Values of singleton counters that were set or incremented.
+    pub singleton_counters: BTreeMap<String, i64>,
+    /// State of timeseries.
+    pub timeseries: BTreeMap<String, TimeseriesSnapshot>,
+    /// State of histograms.
+    pub histograms: BTreeMap<String, HistogramSnapshot>,
+    /// State of quantile stats.
+    pub quantile_stats: BTreeMap<String, QuantileStatSnapshot>,
+}
+
+type Stats<T> = Mutex<BTreeMap<String, Arc<T>>>;
+
+fn get_or_create<T>(stats: &Stats<T>, key: &str, create: impl FnOnce() -> T) -> Arc<T> {
+    let mut stats = stats.lock().expect("poisoned lock");
+    if let Some(stat) = stats.get(key) {
+        return stat.clone();
+    }
+    let stat = Arc::new(create());
+    stats.insert(key.to_owned(), stat.clone());
+    stat
+}
+
+fn load_all(stats: &Stats<AtomicI64>) -> BTreeMap<String, i64> {
+    stats
+        .lock()
+        .expect("poisoned lock")
+        .iter()
+        .map(|(key, value)| (key.clone(), value.load(Ordering::Relaxed)))
+        .collect()
+}
+
+/// Holds the aggregated values of all stats created by [InMemoryStatsManager]s
+/// bound to it. Stats with the same key are aggregated together, the
+/// configuration of the stat that was created first is used.
+pub struct StatsRegistry {
+    start: Instant,
+    counters: Stats<AtomicI64>,
+    singleton_counters: Stats<AtomicI64>,
+    timeseries: Stats<Mutex<TimeseriesState>>,
+    histograms: Stats<Mutex<HistogramState>>,
+    quantile_stats: Stats<Mutex<QuantileStatState>>,
+}
+
+impl Default for StatsRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatsRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            counters: Mutex::new(BTreeMap::new()),
+            singleton_counters: Mutex::new(BTreeMap::new()),
+            timeseries: Mutex::new(BTreeMap::new()),
+            histograms: Mutex::new(BTreeMap::new()),
+            quantile_stats: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Seconds since the registry was created, used as timestamps of samples.
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_secs()
+    }
+
+    fn counter(&self, key: &str) -> Arc<AtomicI64> {
+        get_or_create(&self.counters, key, || AtomicI64::new(0))
+    }
+
+    fn singleton_counter(&self, key: &str) -> Arc<AtomicI64> {
+        get_or_create(&self.singleton_counters, key, || AtomicI64::new(0))
+    }
+
+    fn timeseries(
+        &self,
+        key: &str,
+        aggregation_types: &[AggregationType],
+        intervals: &[Duration],
+    ) -> Arc<Mutex<TimeseriesState>> {
+        get_or_create(&self.timeseries, key, || {
+            let intervals = or_default(intervals, &DEFAULT_INTERVALS);
+            Mutex::new(TimeseriesState {
+                aggregation_types: or_default(aggregation_types, &DEFAULT_AGGREGATION_TYPES)
+                    .to_vec(),
+                intervals: intervals.to_vec(),
+                created: self.now(),
+                total: Sample::default(),
+                window: Window::new(intervals),
+            })
+        })
+    }
+
+    fn histogram(
+        &self,
+        key: &str,
+        aggregation_types: &[AggregationType],
+        conf: BucketConfig,
+        percentiles: &[u8],
+    ) -> Arc<Mutex<HistogramState>> {
+        get_or_create(&self.histograms, key, || {
+            Mutex::new(HistogramState {
+                aggregation_types: or_default(aggregation_types, &DEFAULT_AGGREGATION_TYPES)
+                    .to_vec(),
+                percentiles: or_default(percentiles, &DEFAULT_HISTOGRAM_PERCENTILES).to_vec(),
+                created: self.now(),
+                buckets: Buckets::new(conf),
+                window: Window::new(&DEFAULT_INTERVALS),
+            })
+        })
+    }
+
+    fn quantile_stat(
+        &self,
+        key: &str,
+        aggregation_types: &[AggregationType],
+        percentiles: &[f32],
+        intervals: &[Duration],
+    ) -> Arc<Mutex<QuantileStatState>> {
+        get_or_create(&self.quantile_stats, key, || {
+            let intervals = or_default(intervals, &DEFAULT_INTERVALS);
+            Mutex::new(QuantileStatState {
+                aggregation_types: or_default(aggregation_types, &DEFAULT_AGGREGATION_TYPES)
+                    .to_vec(),
+                percentiles: or_default(percentiles, &DEFAULT_QUANTILE_PERCENTILES).to_vec(),
+                intervals: intervals.to_vec(),
+                created: self.now(),
+                total: Sample::default(),
+                window: Window::new(intervals),
+                samples: VecDeque::new(),
+            })
+        })
+    }
+
+    /// Take a snapshot of all stats in this registry. Note that values of
+    /// thread local stats are only visible after they were aggregated.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        self.snapshot_at(self.now())
+    }
+
+    fn snapshot_at(&self, now: u64) -> StatsSnapshot {
+        fn snapshot_all<T, S>(
+            stats: &Stats<Mutex<T>>,
+            snapshot: impl Fn(&T) -> S,
+        ) -> BTreeMap<String, S> {
+            stats
+                .lock()
+                .expect("poisoned lock")
+                .iter()
+                .map(|(key, stat)| (key.clone(), snapshot(&stat.lock().expect("poisoned lock"))))
+                .collect()
+        }
+
+        StatsSnapshot {
+            counters: load_all(&self.counters),
+            singleton_counters: load_all(&self.singleton_counters),
+            timeseries: snapshot_all(&self.timeseries, |s| s.snapshot(now)),
+            histograms: snapshot_all(&self.histograms, |s| s.snapshot(now)),
+            quantile_stats: snapshot_all(&self.quantile_stats, |s| s.snapshot(now)),
+        }
+    }
+
+    /// Get the current value of the counter with the given key.
+    pub fn get_counter(&self, key: &str) -> Option<i64> {
+        let counters = self.counters.lock().expect("poisoned lock");
+        counters.get(key).map(|value| value.load(Ordering::Relaxed))
+    }
+
+    /// Get the current value of the singleton counter with the given key, if
+    /// it was ever set or incremented.
+    pub fn get_singleton_counter(&self, key: &str) -> Option<i64> {
+        let counters = self.singleton_counters.lock().expect("poisoned lock");
+        counters.get(key).map(|value| value.load(Ordering::Relaxed))
+    }
+
+    /// Get the current values of all singleton counters that were ever set or
+    /// incremented.
+    pub fn singleton_counters(&self) -> BTreeMap<String, i64> {
+        load_all(&self.singleton_counters)
+    }
+
+    /// Get the current state of the timeseries with the given key.
+    pub fn get_timeseries(&self, key: &str) -> Option<TimeseriesSnapshot> {
+        let stat = self
+            .timeseries
+            .lock()
+            .expect("poisoned lock")
+            .get(key)?
+            .clone();
+        let snapshot = stat.lock().expect("poisoned lock").snapshot(self.now());
+        Some(snapshot)
+    }
+
+    /// Get the current state of the histogram with the given key.
+    pub fn get_histogram(&self, key: &str) -> Option<HistogramSnapshot> {
+        let stat = self
+            .histograms
+            .lock()
+            .expect("poisoned lock")
+            .get(key)?
+            .clone();
+        let snapshot = stat.lock().expect("poisoned lock").snapshot(self.now());
+        Some(snapshot)
+    }
+
+    /// Get the current state of the quantile stat with the given key.
+    pub fn get_quantile_stat(&self, key: &str) -> Option<QuantileStatSnapshot> {
+        let stat = self
+            .quantile_stats
+            .lock()
+            .expect("poisoned lock")
+            .get(key)?
+            .clone();
+        let snapshot = stat.lock().expect("poisoned lock").snapshot(self.now());
+        Some(snapshot)
+    }
+}
+
+/// Thread local stat buffers bound to a manager, together with the shared
+/// state they are aggregated into.
+enum PendingStat {
+    Counter {
+        local: Arc<AtomicI64>,
+        global: Arc<AtomicI64>,
+    },
+    Timeseries {
+        local: Arc<Mutex<Sample>>,
+        global: Arc<Mutex<TimeseriesState>>,
+    },
+    Histogram {
+        local: Arc<Mutex<Buckets>>,
+        global: Arc<Mutex<HistogramState>>,
+    },
+}
+
+impl PendingStat {
+    fn aggregate(&self, now: u64) {
+        match self {
+            PendingStat::Counter { local, global } => {
+                global.fetch_add(local.swap(0, Ordering::Relaxed), Ordering::Relaxed);
+            }
+            PendingStat::Timeseries { local, global } => {
+                let sample = std::mem::take(&mut *local.lock().expect("poisoned lock"));
+                if sample.count != 0 {
+                    global.lock().expect("poisoned lock").add(now, sample);
+                }
+            }
+            PendingStat::Histogram { local, global } => {
+                let mut local = local.lock().expect("poisoned lock");
+                if local.total.count != 0 {
+                    let mut global = global.lock().expect("poisoned lock");
+                    let sample = local.drain_into(&mut global.buckets);
+                    global.window.add(now, sample);
+                }
+            }
+        }
+    }
+}
+
+/// [StatsManager] that buffers the stats it creates and merges them into a
+/// [StatsRegistry] when aggregated. Any values that were not aggregated yet
+/// are merged when the manager is dropped.
+pub struct InMemoryStatsManager {
+    registry: Arc<StatsRegistry>,
+    pending: Mutex<Vec<PendingStat>>,
+}
+
+impl InMemoryStatsManager {
+    /// Create a manager that aggregates into the given registry.
+    pub fn new(registry: Arc<StatsRegistry>) -> Self {
+        Self {
+            registry,
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn aggregate_at(&self, now: u64) {
+        for stat in self.pending.lock().expect("poisoned lock").iter() {
+            stat.aggregate(now);
+        }
+    }
+
+    fn add_pending(&self, stat: PendingStat) {
+        self.pending.lock().expect("poisoned lock").push(stat);
+    }
+}
+
+impl Drop for InMemoryStatsManager {
+    fn drop(&mut self) {
+        self.aggregate();
+    }
+}
+
+impl StatsManager for InMemoryStatsManager {
+    fn aggregate(&self) {
+        self.aggregate_at(self.registry.now());
+    }
+
+    fn create_counter(&self, name: &str) -> BoxLocalCounter {
+        let local = Arc::new(AtomicI64::new(0));
+        self.add_pending(PendingStat::Counter {
+            local: local.clone(),
+            global: self.registry.counter(name),
+        });
+        Box::new(LocalCounter(local))
+    }
+
+    fn create_timeseries(
+        &self,
+        name: &str,
+        aggregation_types: &[AggregationType],
+        intervals: &[Duration],
+    ) -> BoxLocalTimeseries {
+        let local = Arc::new(Mutex::new(Sample::default()));
+        self.add_pending(PendingStat::Timeseries {
+            local: local.clone(),
+            global: self.registry.timeseries(name, aggregation_types, intervals),
+        });
+        Box::new(LocalTimeseries(local))
+    }
+
+    fn create_histogram(
+        &self,
+        name: &str,
+        aggregation_types: &[AggregationType],
+        conf: BucketConfig,
+        percentiles: &[u8],
+    ) -> BoxLocalHistogram {
+        let global = self
+            .registry
+            .histogram(name, aggregation_types, conf, percentiles);
+        // Use the configuration of the shared histogram, it might have been
+        // created with a different one by another manager.
+        let config = global.lock().expect("poisoned lock").buckets.config;
+        let local = Arc::new(Mutex::new(Buckets::new(config)));
+        self.add_pending(PendingStat::Histogram {
+            local: local.clone(),
+            global,
+        });
+        Box::new(LocalHistogram(local))
+    }
+
+    fn create_quantile_stat(
+        &self,
+        name: &str,
+        aggregation_types: &[AggregationType],
+        percentiles: &[f32],
+        intervals: &[Duration],
+    ) -> BoxHistogram {
+        Box::new(QuantileStat {
+            registry: self.registry.clone(),
+            state: self
+                .registry
+                .quantile_stat(name, aggregation_types, percentiles, intervals),
+        })
+    }
+}
+
+struct LocalCounter(Arc<AtomicI64>);
+
+impl Counter for LocalCounter {
+    fn increment_value(&self, value: i64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+}
+
+struct LocalTimeseries(Arc<Mutex<Sample>>);
+
+impl Timeseries for LocalTimeseries {
+    fn add_value(&self, value: i64) {
+        self.add_value_aggregated(value, 1);
+    }
+
+    fn add_value_aggregated(&self, value: i64, nsamples: u32) {
+        self.0
+            .lock()
+            .expect("poisoned lock")
+            .merge(Sample::new(value, nsamples));
+    }
+}
+
+struct LocalHistogram(Arc<Mutex<Buckets>>);
+
+impl Histogram for LocalHistogram {
+    fn add_value(&self, value: i64) {
+        self.add_repeated_value(value, 1);
+    }
+
+    fn add_repeated_value(&self, value: i64, nsamples: u32) {
+        self.0.lock().expect("poisoned lock").add(value, nsamples);
+    }
+}
+
+struct QuantileStat {
+    registry: Arc<StatsRegistry>,
+    state: Arc<Mutex<QuantileStatState>>,
+}
+
+impl Histogram for QuantileStat {
+    fn add_value(&self, value: i64) {
+        self.add_repeated_value(value, 1);
+    }
+
+    fn add_repeated_value(&self, value: i64, nsamples: u32) {
+        let now = self.registry.now();
+        self.state
+            .lock()
+            .expect("poisoned lock")
+            .add(now, value, nsamples);
+    }
+}
+
+/// [SingletonCounter] whose value is stored in a [StatsRegistry]. All
+/// instances with the same key share the same value, which is registered on
+/// the first call to `set_value` or `increment_value`.
+pub struct InMemorySingletonCounter {
+    registry: Arc<StatsRegistry>,
+    key: String,
+    value: OnceLock<Arc<AtomicI64>>,
+}
+
+impl InMemorySingletonCounter {
+    /// Create a singleton counter with the given key stored in the registry.
+    pub fn new(registry: Arc<StatsRegistry>, key: String) -> Self {
+        Self {
+            registry,
+            key,
+            value: OnceLock::new(),
+        }
+    }
+
+    fn value(&self) -> &AtomicI64 {
+        self.value
+            .get_or_init(|| self.registry.singleton_counter(&self.key))
+    }
+}
+
+impl SingletonCounter for InMemorySingletonCounter {
+    fn set_value(&self, _fb: FacebookInit, value: i64) {
+        self.value().store(value, Ordering::Relaxed);
+    }
+
+    fn increment_value(&self, _fb: FacebookInit, value: i64) {
+        self.value().fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn get_value(&self, _fb: FacebookInit) -> Option<i64> {
+        if let Some(value) = self.value.get() {
+            return Some(value.load(Ordering::Relaxed));
+        }
+        self.registry.get_singleton_counter(&self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use AggregationType::*;
+
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_counters_are_merged_on_aggregate() {
+        let registry = Arc::new(StatsRegistry::new());
+        let factory = InMemoryStatsFactory::new(registry.clone());
+        let manager1 = factory.create();
+        let manager2 = factory.create();
+
+        let c1 = manager1.create_counter("counter");
+        let c2 = manager2.create_counter("counter");
+        c1.increment_value(1);
+        c2.increment_value(5);
+        assert_eq!(registry.get_counter("counter"), Some(0));
+
+        manager1.aggregate();
+        assert_eq!(registry.get_counter("counter"), Some(1));
+        manager2.aggregate();
+        assert_eq!(registry.get_counter("counter"), Some(6));
+
+        c2.increment_value(4);
+        drop(manager2);
+        assert_eq!(registry.get_counter("counter"), Some(10));
+        assert_eq!(registry.get_counter("missing"), None);
+    }
+
+    #[fbinit::test]
+    fn test_singleton_counters(fb: FacebookInit) {
+        let registry = Arc::new(StatsRegistry::new());
+        let c1 = InMemorySingletonCounter::new(registry.clone(), "gauge".to_owned());
+        let c2 = InMemorySingletonCounter::new(registry.clone(), "gauge".to_owned());
+        let other = InMemorySingletonCounter::new(registry.clone(), "other".to_owned());
+        assert_eq!(c1.get_value(fb), None);
+
+        c1.set_value(fb, 10);
+        assert_eq!(c2.get_value(fb), Some(10));
+        c2.increment_value(fb, -3);
+        assert_eq!(c1.get_value(fb), Some(7));
+        assert_eq!(other.get_value(fb), None);
+
+        other.increment_value(fb, 2);
+        let expected = [("gauge".to_owned(), 7), ("other".to_owned(), 2)];
+        assert_eq!(
+            registry.singleton_counters(),
+            BTreeMap::from(expected.clone())
+        );
+        assert_eq!(
+            registry.snapshot().singleton_counters,
+            BTreeMap::from(expected)
+        );
+    }
+
+    #[test]
+    fn test_timeseries_intervals() {
+        let registry = Arc::new(StatsRegistry::new());
+        let manager = InMemoryStatsManager::new(registry.clone());
+        let ts = manager.create_timeseries(
+            "ts",
+            &[Sum, Count, Average, Rate],
+            &[Duration::from_secs(10), MINUTE],
+        );
+
+        ts.add_value(10);
+        ts.add_value_aggregated(20, 3);
+        manager.aggregate_at(0);
+        ts.add_value(30);
+        manager.aggregate_at(30);
+
+        let snapshot = registry.snapshot_at(30).timeseries.remove("ts").unwrap();
+        assert_eq!(snapshot.sum, 60);
+        assert_eq!(snapshot.count, 5);
+        assert_eq!(snapshot.get(Sum, Duration::from_secs(10)), Some(30.0));
+        assert_eq!(snapshot.get(Sum, MINUTE), Some(60.0));
+        assert_eq!(snapshot.get(Count, MINUTE), Some(5.0));
+        assert_eq!(snapshot.get(Average, MINUTE), Some(12.0));
+        assert_eq!(snapshot.get(Rate, Duration::from_secs(10)), Some(3.0));
+        assert_eq!(snapshot.get(Rate, MINUTE), Some(60.0 / 31.0));
+        assert_eq!(snapshot.get(Percent, MINUTE), None);
+
+        let snapshot = registry.snapshot_at(70).timeseries.remove("ts").unwrap();
+        assert_eq!(snapshot.get(Sum, MINUTE), Some(30.0));
+        assert_eq!(snapshot.get(Sum, Duration::from_secs(10)), Some(0.0));
+    }
+
+    #[test]
+    fn test_timeseries_defaults() {
+        let registry = Arc::new(StatsRegistry::new());
+        let manager = InMemoryStatsManager::new(registry.clone());
+        let ts = manager.create_timeseries("ts", &[], &[]);
+        ts.add_value(4);
+        ts.add_value(2);
+        manager.aggregate();
+
+        let snapshot = registry.get_timeseries("ts").unwrap();
+        let values: Vec<_> = snapshot
+            .values
+            .iter()
+            .map(|v| (v.aggregation_type, v.interval.as_secs(), v.value))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                (Average, 60, 3.0),
+                (Average, 600, 3.0),
+                (Average, 3600, 3.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let registry = Arc::new(StatsRegistry::new());
+        let manager1 = InMemoryStatsManager::new(registry.clone());
+        let manager2 = InMemoryStatsManager::new(registry.clone());
+        let conf = BucketConfig {
+            width: 10,
+            min: 0,
+            max: 100,
+        };
+        let h1 = manager1.create_histogram("h", &[Sum, Count], conf, &[50, 90]);
+        let h2 = manager2.create_histogram("h", &[], conf, &[]);
+
+        h1.add_value(-1);
+        h1.add_repeated_value(15, 4);
+        h2.add_value(55);
+        h2.add_repeated_value(200, 2);
+        h2.add_value(99);
+        manager1.aggregate();
+        manager2.aggregate();
+
+        let snapshot = registry.get_histogram("h").unwrap();
+        assert_eq!(snapshot.buckets, vec![1, 0, 4, 0, 0, 0, 1, 0, 0, 0, 1, 2]);
+        assert_eq!(snapshot.count, 9);
+        assert_eq!(snapshot.sum, -1 + 60 + 55 + 400 + 99);
+        assert_eq!(snapshot.get(Count, MINUTE), Some(9.0));
+        assert_eq!(snapshot.get(Average, MINUTE), None);
+        assert_eq!(snapshot.percentile(50), Some(18.75));
+        assert_eq!(snapshot.percentile(90), Some(100.0));
+        assert_eq!(snapshot.percentile(99), None);
+    }
+
+    #[test]
+    fn test_quantile_stat() {
+        let registry = Arc::new(StatsRegistry::new());
+        let manager = InMemoryStatsManager::new(registry.clone());
+        let _qs = manager.create_quantile_stat("qs", &[Count, Sum], &[50.0, 99.0], &[MINUTE]);
+        let state = registry.quantile_stat("qs", &[], &[], &[]);
+
+        for value in 1..=100 {
+            state.lock().unwrap().add(0, value, 1);
+        }
+        state.lock().unwrap().add(50, 1000, 2);
+
+        let snapshot = registry
+            .snapshot_at(50)
+            .quantile_stats
+            .remove("qs")
+            .unwrap();
+        assert_eq!(snapshot.count, 102);
+        assert_eq!(snapshot.get(Count, MINUTE), Some(102.0));
+        assert_eq!(snapshot.get(Sum, MINUTE), Some(5050.0 + 2000.0));
+        assert_eq!(snapshot.percentile(50.0, MINUTE), Some(51.0));
+        assert_eq!(snapshot.percentile(99.0, MINUTE), Some(1000.0));
+
+        let snapshot = registry
+            .snapshot_at(65)
+            .quantile_stats
+            .remove("qs")
+            .unwrap();
+        assert_eq!(snapshot.get(Count, MINUTE), Some(2.0));
+        assert_eq!(snapshot.percentile(50.0, MINUTE), Some(1000.0));
+    }
+}
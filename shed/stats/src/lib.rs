@@ -14,9 +14,12 @@
 
 #![deny(warnings, missing_docs, clippy::all, rustdoc::broken_intra_doc_links)]
 
+pub mod in_memory_stats;
 pub mod macros;
 #[allow(dead_code)]
 mod noop_stats;
+pub mod prometheus;
+mod recording_stats;
 pub mod thread_local_aggregator;
 
 pub mod prelude {
@@ -45,12 +48,22 @@ pub mod prelude {
     pub use crate::define_stats_struct;
 }
 
+/// Those should be only used in tests.
+pub mod test_helpers {
+    pub use crate::recording_stats::RecordedStats;
+    pub use crate::recording_stats::StatOp;
+    pub use crate::recording_stats::with_recorded_stats;
+    pub use crate::recording_stats::with_recorded_stats_async;
+}
+
 use std::sync::RwLock;
 
 use stats_traits::stat_types::BoxSingletonCounter;
 use stats_traits::stats_manager::BoxStatsManager;
 use stats_traits::stats_manager::StatsManagerFactory;
 
+use self::recording_stats::Recorded;
+use self::recording_stats::RecordingStatsManager;
 pub use self::thread_local_aggregator::schedule_stats_aggregation_preview;
 
 static STATS_MANAGER_FACTORY: RwLock<Option<Box<dyn StatsManagerFactory + Send + Sync>>> =
@@ -59,7 +72,8 @@ static STATS_MANAGER_FACTORY: RwLock<Option<Box<dyn StatsManagerFactory + Send +
 /// This function must be called exactly once before accessing any of the stats,
 /// otherwise it will panic.
 /// If it won't be called a default stats manager factory will be assumed that
-/// does nothing. (Facebook only: the default will use fb303 counters)
+/// aggregates stats in memory, see [in_memory_stats]. (Facebook only: the
+/// default will use fb303 counters)
 pub fn register_stats_manager_factory(factory: impl StatsManagerFactory + Send + Sync + 'static) {
     let mut global_factory = STATS_MANAGER_FACTORY.write().expect("poisoned lock");
     assert!(
@@ -72,8 +86,13 @@ pub fn register_stats_manager_factory(factory: impl StatsManagerFactory + Send +
 #[doc(hidden)]
 /// You probably don't have to use this function, it is made public so that it
 /// might be used by the macros in this crate. It reads the globally registered
-/// StatsManagerFactory and creates a new instance of StatsManager.
+/// StatsManagerFactory and creates a new instance of StatsManager, whose stats
+/// can be recorded with [test_helpers::with_recorded_stats].
 pub fn create_stats_manager() -> BoxStatsManager {
+    Box::new(RecordingStatsManager::new(create_registered_stats_manager()))
+}
+
+fn create_registered_stats_manager() -> BoxStatsManager {
     if let Some(factory) = STATS_MANAGER_FACTORY
         .read()
         .expect("poisoned lock")
@@ -96,22 +115,25 @@ fn get_default_stats_manager_factory() -> Box<dyn StatsManagerFactory + Send + S
     }
     #[cfg(not(fbcode_build))]
     {
-        Box::new(crate::noop_stats::NoopStatsFactory)
+        Box::new(crate::in_memory_stats::InMemoryStatsFactory::default())
     }
 }
 
 #[doc(hidden)]
 /// You probably don't have to use this function, it is made public so that it
-/// might be used by the macros in this crate. It creates a new SingletonCounter.
+/// might be used by the macros in this crate. It creates a new SingletonCounter,
+/// which can be recorded with [test_helpers::with_recorded_stats].
 pub fn create_singleton_counter(name: String) -> BoxSingletonCounter {
+    let key = name.clone();
+
     #[cfg(fbcode_build)]
-    {
-        Box::new(::stats_facebook::singleton_counter::ServiceDataSingletonCounter::new(name))
-    }
+    let counter = ::stats_facebook::singleton_counter::ServiceDataSingletonCounter::new(name);
 
     #[cfg(not(fbcode_build))]
-    {
-        let _ = name;
-        Box::new(crate::noop_stats::Noop)
-    }
+    let counter = crate::in_memory_stats::InMemorySingletonCounter::new(
+        crate::in_memory_stats::global_registry(),
+        name,
+    );
+
+    Box::new(Recorded::new(&key, counter))
 }
@@ -0,0 +1,579 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+//! Exports the stats aggregated by [crate::in_memory_stats] in the Prometheus
+//! text exposition format, either as a string or served over HTTP.
+//!
+//! Stat keys are turned into metric names by replacing every character that is
+//! not valid in a Prometheus metric name with `_`, so `my.test.counter` is
+//! exported as `my_test_counter`. Stats are exported as follows:
+//!
+//! * counters as `counter`s,
+//! * singleton counters as `gauge`s,
+//! * every aggregation type and interval of timeseries as a `gauge` named
+//!   like the fb303 counter, e.g. `{name}_sum_60`,
+//! * histograms as `histogram`s, plus gauges for their aggregation types,
+//! * quantile stats as `summary`s with quantiles computed over their shortest
+//!   interval, plus gauges for their aggregation types.
+//!
+//! Keys of dynamic stats and of stats defined with
+//! [crate::define_stats_struct] can be split into a metric name and labels by
+//! registering the templates they were created with, see
+//! [PrometheusExporter::with_key_template].
+//!
+//! Keep in mind that thread local stats are only visible after they were
+//! aggregated, see [crate::schedule_stats_aggregation_preview].
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use stats_traits::stats_manager::AggregationType;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+
+use crate::in_memory_stats::AggregatedValue;
+use crate::in_memory_stats::StatsRegistry;
+use crate::in_memory_stats::StatsSnapshot;
+
+/// Content type of the Prometheus text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Maximum size of the request head accepted by [PrometheusExporter::serve].
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Time a client of [PrometheusExporter::serve] has to send the request head.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Renders the stats of a [StatsRegistry] in the Prometheus text format.
+pub struct PrometheusExporter {
+    registry: Arc<StatsRegistry>,
+    templates: Vec<KeyTemplate>,
+}
+
+impl PrometheusExporter {
+    /// Create an exporter of the stats in the given registry.
+    pub fn new(registry: Arc<StatsRegistry>) -> Self {
+        Self {
+            registry,
+            templates: Vec::new(),
+        }
+    }
+
+    /// Export stats whose key was created from `template` (a format string
+    /// with `{}` placeholders, as used by dynamic stats and
+    /// [crate::define_stats_struct]) under a metric name without the
+    /// placeholders, with the placeholder values as labels named `labels`.
+    /// Keys may continue after the end of the template, so that the template
+    /// of a [crate::define_stats_struct] applies to all its fields.
+    ///
+    /// A placeholder matches everything up to the text that follows it in the
+    /// template, or up to the next `.` if it is followed by nothing. Templates
+    /// are tried in the order they were added.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of `labels` does not match the number of
+    /// placeholders in `template`.
+    pub fn with_key_template(mut self, template: &str, labels: &[&str]) -> Self {
+        self.templates.push(KeyTemplate::new(template, labels));
+        self
+    }
+
+    /// Render a snapshot of the registry.
+    pub fn render(&self) -> String {
+        self.render_snapshot(&self.registry.snapshot())
+    }
+
+    /// Render the given snapshot.
+    pub fn render_snapshot(&self, snapshot: &StatsSnapshot) -> String {
+        let mut families = Families::default();
+
+        for (key, value) in &snapshot.counters {
+            let (name, labels) = self.split_key(key);
+            families.add(&name, "counter", "", &labels, *value as f64);
+        }
+
+        for (key, value) in &snapshot.singleton_counters {
+            let (name, labels) = self.split_key(key);
+            families.add(&name, "gauge", "", &labels, *value as f64);
+        }
+
+        for (key, timeseries) in &snapshot.timeseries {
+            let (name, labels) = self.split_key(key);
+            families.add_aggregated(&name, &labels, &timeseries.values);
+        }
+
+        for (key, histogram) in &snapshot.histograms {
+            let (name, labels) = self.split_key(key);
+            let config = histogram.bucket_config;
+            let last = histogram.buckets.len() - 1;
+            let mut cumulative = 0;
+            for (index, count) in histogram.buckets.iter().enumerate() {
+                cumulative += count;
+                // Buckets are exclusive of their upper bound, since samples
+                // are integers the inclusive one is one less than that.
+                let le = if index == last {
+                    "+Inf".to_owned()
+                } else {
+                    let upper = i64::from(config.min) + index as i64 * i64::from(config.width);
+                    (upper.min(i64::from(config.max)) - 1).to_string()
+                };
+                let mut labels = labels.clone();
+                labels.push(("le".to_owned(), le));
+                families.add(&name, "histogram", "_bucket", &labels, cumulative as f64);
+            }
+            families.add(&name, "histogram", "_sum", &labels, histogram.sum as f64);
+            families.add(
+                &name,
+                "histogram",
+                "_count",
+                &labels,
+                histogram.count as f64,
+            );
+            families.add_aggregated(&name, &labels, &histogram.values);
+        }
+
+        for (key, quantile_stat) in &snapshot.quantile_stats {
+            let (name, labels) = self.split_key(key);
+            if let Some(interval) = quantile_stat.percentiles.iter().map(|v| v.interval).min() {
+                for value in &quantile_stat.percentiles {
+                    if value.interval != interval {
+                        continue;
+                    }
+                    let mut labels = labels.clone();
+                    labels.push((
+                        "quantile".to_owned(),
+                        (value.percentile / 100.0).to_string(),
+                    ));
+                    families.add(&name, "summary", "", &labels, value.value);
+                }
+            }
+            families.add(&name, "summary", "_sum", &labels, quantile_stat.sum as f64);
+            families.add(
+                &name,
+                "summary",
+                "_count",
+                &labels,
+                quantile_stat.count as f64,
+            );
+            families.add_aggregated(&name, &labels, &quantile_stat.values);
+        }
+
+        families.render()
+    }
+
+    /// Serve the rendered stats over HTTP on `127.0.0.1:{port}`.
+    pub async fn serve_on_port(self, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+        self.serve(listener).await
+    }
+
+    /// Serve the rendered stats over HTTP to `GET /metrics` requests accepted
+    /// on the given listener. This future only completes if accepting a
+    /// connection fails.
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        let exporter = Arc::new(self);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let exporter = exporter.clone();
+            tokio::spawn(async move {
+                // There is nobody to report the error to, the client will
+                // notice the connection being closed.
+                let _ = exporter.handle_connection(stream).await;
+            });
+        }
+    }
+
+    async fn handle_connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        let deadline = tokio::time::Instant::now() + REQUEST_TIMEOUT;
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            if request.len() > MAX_REQUEST_SIZE {
+                return write_response(&mut stream, "431 Request Header Fields Too Large", "")
+                    .await;
+            }
+            let Ok(read) = tokio::time::timeout_at(deadline, stream.read(&mut buf)).await else {
+                return write_response(&mut stream, "408 Request Timeout", "").await;
+            };
+            let read = read?;
+            if read == 0 {
+                return Ok(());
+            }
+            request.extend_from_slice(&buf[..read]);
+        }
+
+        let request = String::from_utf8_lossy(&request);
+        let mut request_line = request.lines().next().unwrap_or("").split(' ');
+        match (request_line.next(), request_line.next()) {
+            (Some("GET"), Some("/metrics")) => {
+                write_response(&mut stream, "200 OK", &self.render()).await
+            }
+            (Some("GET"), _) => write_response(&mut stream, "404 Not Found", "").await,
+            _ => write_response(&mut stream, "405 Method Not Allowed", "").await,
+        }
+    }
+
+    fn split_key(&self, key: &str) -> (String, Vec<(String, String)>) {
+        self.templates
+            .iter()
+            .find_map(|template| template.split(key))
+            .map_or_else(
+                || (sanitize_name(key), Vec::new()),
+                |(name, labels)| (sanitize_name(&name), labels),
+            )
+    }
+}
+
+async fn write_response(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Format string of a stat key, split into the text around its placeholders.
+struct KeyTemplate {
+    literals: Vec<String>,
+    labels: Vec<String>,
+}
+
+impl KeyTemplate {
+    fn new(template: &str, labels: &[&str]) -> Self {
+        let literals: Vec<String> = template.split("{}").map(str::to_owned).collect();
+        assert_eq!(
+            literals.len() - 1,
+            labels.len(),
+            "Key template {template:?} has a different number of placeholders than labels {labels:?}"
+        );
+        Self {
+            literals,
+            labels: labels.iter().map(|label| sanitize_name(label)).collect(),
+        }
+    }
+
+    /// Split the key into a metric name (not sanitized yet) and labels, if it
+    /// matches this template.
+    fn split(&self, key: &str) -> Option<(String, Vec<(String, String)>)> {
+        let mut rest = key.strip_prefix(self.literals[0].as_str())?;
+        let mut name = self.literals[0].clone();
+        let mut labels = Vec::with_capacity(self.labels.len());
+        for (label, literal) in self.labels.iter().zip(&self.literals[1..]) {
+            let end = if literal.is_empty() {
+                rest.find('.').unwrap_or(rest.len())
+            } else {
+                rest.find(literal.as_str())?
+            };
+            if end == 0 {
+                return None;
+            }
+            labels.push((label.clone(), rest[..end].to_owned()));
+            rest = &rest[end + literal.len()..];
+            name.push_str(literal);
+        }
+        if !rest.is_empty() && !rest.starts_with('.') {
+            return None;
+        }
+        name.push_str(rest);
+        Some((name, labels))
+    }
+}
+
+/// Turn a stat key into a valid Prometheus metric (or label) name.
+fn sanitize_name(key: &str) -> String {
+    let mut name = String::with_capacity(key.len() + 1);
+    for c in key.chars() {
+        let c = if c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+            c
+        } else {
+            '_'
+        };
+        if c == '_' && (name.is_empty() || name.ends_with('_')) {
+            continue;
+        }
+        name.push(c);
+    }
+    while name.ends_with('_') {
+        name.pop();
+    }
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+fn aggregation_suffix(aggregation_type: AggregationType) -> &'static str {
+    match aggregation_type {
+        AggregationType::Sum => "sum",
+        AggregationType::Count => "count",
+        AggregationType::Average => "avg",
+        AggregationType::Rate => "rate",
+        AggregationType::Percent => "pct",
+    }
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+struct Family {
+    kind: &'static str,
+    samples: String,
+}
+
+/// Samples grouped by metric family, so that every family is rendered in one
+/// block under its `TYPE` line.
+#[derive(Default)]
+struct Families(BTreeMap<String, Family>);
+
+impl Families {
+    fn add(
+        &mut self,
+        name: &str,
+        kind: &'static str,
+        suffix: &str,
+        labels: &[(String, String)],
+        value: f64,
+    ) {
+        let family = self.0.entry(name.to_owned()).or_insert_with(|| Family {
+            kind,
+            samples: String::new(),
+        });
+        // Two stats with a different type ended up with the same name, only
+        // the first one can be exported.
+        if family.kind != kind {
+            return;
+        }
+
+        let samples = &mut family.samples;
+        samples.push_str(name);
+        samples.push_str(suffix);
+        if !labels.is_empty() {
+            samples.push('{');
+            for (index, (label, value)) in labels.iter().enumerate() {
+                if index != 0 {
+                    samples.push(',');
+                }
+                let _ = write!(samples, "{label}=\"{}\"", escape_label_value(value));
+            }
+            samples.push('}');
+        }
+        let _ = writeln!(samples, " {}", format_value(value));
+    }
+
+    fn add_aggregated(
+        &mut self,
+        name: &str,
+        labels: &[(String, String)],
+        values: &[AggregatedValue],
+    ) {
+        for value in values {
+            let name = format!(
+                "{name}_{}_{}",
+                aggregation_suffix(value.aggregation_type),
+                value.interval.as_secs()
+            );
+            self.add(&name, "gauge", "", labels, value.value);
+        }
+    }
+
+    fn render(self) -> String {
+        let mut output = String::new();
+        for (name, family) in self.0 {
+            let _ = writeln!(output, "# TYPE {name} {}", family.kind);
+            output.push_str(&family.samples);
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fbinit::FacebookInit;
+    use stats_traits::stat_types::SingletonCounter;
+    use stats_traits::stats_manager::AggregationType::*;
+    use stats_traits::stats_manager::BucketConfig;
+    use stats_traits::stats_manager::StatsManager;
+
+    use super::*;
+    use crate::in_memory_stats::InMemorySingletonCounter;
+    use crate::in_memory_stats::InMemoryStatsManager;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("my.test.counter"), "my_test_counter");
+        assert_eq!(sanitize_name("a-b..c:d_"), "a_b_c:d");
+        assert_eq!(sanitize_name("1st"), "_1st");
+        assert_eq!(sanitize_name("..."), "_");
+    }
+
+    #[test]
+    fn test_key_template() {
+        let template = KeyTemplate::new("things.{}.{}", &["name", "idx"]);
+        assert_eq!(
+            template.split("things.foo.3.cache_miss"),
+            Some((
+                "things...cache_miss".to_owned(),
+                vec![
+                    ("name".to_owned(), "foo".to_owned()),
+                    ("idx".to_owned(), "3".to_owned())
+                ]
+            ))
+        );
+        assert_eq!(template.split("other.foo.3"), None);
+
+        let template = KeyTemplate::new("test_t.{}.latency", &["region"]);
+        assert_eq!(
+            template.split("test_t.east.1.latency"),
+            Some((
+                "test_t..latency".to_owned(),
+                vec![("region".to_owned(), "east.1".to_owned())]
+            ))
+        );
+        assert_eq!(template.split("test_t.east.latency_ms"), None);
+    }
+
+    #[fbinit::test]
+    fn test_render(fb: FacebookInit) {
+        let registry = Arc::new(StatsRegistry::new());
+        InMemorySingletonCounter::new(registry.clone(), "queue.depth".to_owned()).set_value(fb, 12);
+        let manager = InMemoryStatsManager::new(registry.clone());
+        manager.create_counter("my.counter").increment_value(3);
+        manager
+            .create_timeseries("requests.east", &[Sum, Average], &[MINUTE])
+            .add_value_aggregated(10, 4);
+        manager
+            .create_timeseries("requests.west", &[Sum, Average], &[MINUTE])
+            .add_value(1);
+        let conf = BucketConfig {
+            width: 10,
+            min: 0,
+            max: 20,
+        };
+        let histogram = manager.create_histogram("latency", &[Count], conf, &[]);
+        histogram.add_value(5);
+        histogram.add_repeated_value(15, 2);
+        histogram.add_value(100);
+        let quantile_stat = manager.create_quantile_stat("qs", &[], &[50.0], &[MINUTE]);
+        quantile_stat.add_value(7);
+        manager.aggregate();
+
+        let exporter =
+            PrometheusExporter::new(registry).with_key_template("requests.{}", &["region"]);
+        let expected = r#"# TYPE latency histogram
+latency_bucket{le="-1"} 0
+latency_bucket{le="9"} 1
+latency_bucket{le="19"} 3
+latency_bucket{le="+Inf"} 4
+latency_sum 135
+latency_count 4
+# TYPE latency_count_3600 gauge
+latency_count_3600 4
+# TYPE latency_count_60 gauge
+latency_count_60 4
+# TYPE latency_count_600 gauge
+latency_count_600 4
+# TYPE my_counter counter
+my_counter 3
+# TYPE qs summary
+qs{quantile="0.5"} 7
+qs_sum 7
+qs_count 1
+# TYPE qs_avg_60 gauge
+qs_avg_60 7
+# TYPE queue_depth gauge
+queue_depth 12
+# TYPE requests_avg_60 gauge
+requests_avg_60{region="east"} 2.5
+requests_avg_60{region="west"} 1
+# TYPE requests_sum_60 gauge
+requests_sum_60{region="east"} 10
+requests_sum_60{region="west"} 1
+"#;
+        assert_eq!(exporter.render(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_serve() -> io::Result<()> {
+        let registry = Arc::new(StatsRegistry::new());
+        let manager = InMemoryStatsManager::new(registry.clone());
+        manager.create_counter("served").increment_value(1);
+        manager.aggregate();
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(PrometheusExporter::new(registry).serve(listener));
+
+        let request = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await?;
+            stream
+                .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+                .await?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await?;
+            io::Result::Ok(response)
+        };
+
+        let response = request("/metrics").await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.ends_with("\r\n\r\n# TYPE served counter\nserved 1\n"));
+
+        let response = request("/").await?;
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{response}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_serve_request_timeout() -> io::Result<()> {
+        let registry = Arc::new(StatsRegistry::new());
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(PrometheusExporter::new(registry).serve(listener));
+
+        // A client that never sends its request is disconnected.
+        let mut stream = TcpStream::connect(addr).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        assert!(
+            response.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+            "{response}"
+        );
+        Ok(())
+    }
+}
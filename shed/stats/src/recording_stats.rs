@@ -0,0 +1,455 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is dual-licensed under either the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree or the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree. You may select, at your option, one of the
+ * above-listed licenses.
+ */
+
+//! Recording of stat operations, meant to be used in unit tests to assert that
+//! code paths bump the right stats.
+//!
+//! Every stat created by this crate is wrapped so that, while a recording is
+//! active on the current thread (see [with_recorded_stats] and
+//! [with_recorded_stats_async]), the operations made on it are captured keyed
+//! by the stat key, in addition to being forwarded to the actual stats
+//! manager. Since the recording is thread local, tests running in parallel do
+//! not see each other's stats, but operations made on other threads (e.g. by
+//! tasks spawned on a multi-threaded runtime) are not recorded either.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use fbinit::FacebookInit;
+use futures::FutureExt;
+use futures::future::poll_fn;
+use stats_traits::stat_types::BoxHistogram;
+use stats_traits::stat_types::BoxLocalCounter;
+use stats_traits::stat_types::BoxLocalHistogram;
+use stats_traits::stat_types::BoxLocalTimeseries;
+use stats_traits::stat_types::Counter;
+use stats_traits::stat_types::Histogram;
+use stats_traits::stat_types::SingletonCounter;
+use stats_traits::stat_types::Timeseries;
+use stats_traits::stats_manager::AggregationType;
+use stats_traits::stats_manager::BoxStatsManager;
+use stats_traits::stats_manager::BucketConfig;
+use stats_traits::stats_manager::StatsManager;
+
+/// Set once any recording was started, so that stats don't have to look up
+/// the thread local recordings in processes that never record.
+static RECORDING_USED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static RECORDINGS: RefCell<Vec<RecordedStats>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A single operation made on a stat.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StatOp {
+    /// `increment_value` of a counter or singleton counter.
+    IncrementValue(i64),
+    /// `set_value` of a singleton counter.
+    SetValue(i64),
+    /// `add_value` of a timeseries, histogram or quantile stat.
+    AddValue(i64),
+    /// `add_value_aggregated` of a timeseries.
+    AddValueAggregated {
+        /// Sum of the samples.
+        value: i64,
+        /// Number of samples.
+        nsamples: u32,
+    },
+    /// `add_repeated_value` of a histogram or quantile stat.
+    AddRepeatedValue {
+        /// Value of every sample.
+        value: i64,
+        /// Number of samples.
+        nsamples: u32,
+    },
+}
+
+/// Operations recorded while the recording was active, keyed by stat key.
+#[derive(Clone, Default)]
+pub struct RecordedStats(Arc<Mutex<BTreeMap<String, Vec<StatOp>>>>);
+
+impl RecordedStats {
+    fn record(&self, key: &str, op: StatOp) {
+        let mut ops = self.0.lock().expect("poisoned lock");
+        match ops.get_mut(key) {
+            Some(ops) => ops.push(op),
+            None => {
+                ops.insert(key.to_owned(), vec![op]);
+            }
+        }
+    }
+
+    /// Keys of all stats that had any operation recorded.
+    pub fn keys(&self) -> Vec<String> {
+        self.0
+            .lock()
+            .expect("poisoned lock")
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// All operations recorded for the stat with the given key, in order.
+    pub fn ops(&self, key: &str) -> Vec<StatOp> {
+        let ops = self.0.lock().expect("poisoned lock");
+        ops.get(key).cloned().unwrap_or_default()
+    }
+
+    /// Sum of all values recorded for the stat with the given key. For
+    /// singleton counters `set_value` resets the total to the value set.
+    pub fn total(&self, key: &str) -> i64 {
+        self.ops(key).into_iter().fold(0, |total, op| match op {
+            StatOp::IncrementValue(value) | StatOp::AddValue(value) => total + value,
+            StatOp::SetValue(value) => value,
+            StatOp::AddValueAggregated { value, .. } => total + value,
+            StatOp::AddRepeatedValue { value, nsamples } => total + value * i64::from(nsamples),
+        })
+    }
+
+    /// Number of samples recorded for the stat with the given key, where
+    /// every increment or set of a counter counts as one sample.
+    pub fn count(&self, key: &str) -> u64 {
+        self.ops(key)
+            .into_iter()
+            .map(|op| match op {
+                StatOp::IncrementValue(_) | StatOp::SetValue(_) | StatOp::AddValue(_) => 1,
+                StatOp::AddValueAggregated { nsamples, .. }
+                | StatOp::AddRepeatedValue { nsamples, .. } => u64::from(nsamples),
+            })
+            .sum()
+    }
+
+    /// Individual samples added to the histogram (or quantile stat) with the
+    /// given key, in order. Repeated values are expanded.
+    pub fn histogram_samples(&self, key: &str) -> Vec<i64> {
+        let mut samples = Vec::new();
+        for op in self.ops(key) {
+            match op {
+                StatOp::AddValue(value) => samples.push(value),
+                StatOp::AddRepeatedValue { value, nsamples } => {
+                    samples.extend(std::iter::repeat_n(value, nsamples as usize))
+                }
+                _ => {}
+            }
+        }
+        samples
+    }
+}
+
+fn record(key: &str, op: StatOp) {
+    if !RECORDING_USED.load(Ordering::Relaxed) {
+        return;
+    }
+    // The thread local might already be destroyed if a stat is used while
+    // the thread is exiting, nothing is being recorded at that point.
+    let _ = RECORDINGS.try_with(|recordings| {
+        for recording in recordings.borrow().iter() {
+            recording.record(key, op);
+        }
+    });
+}
+
+/// Stops the recording it was returned for when dropped, so that a panic
+/// doesn't leave the recording in place on the thread.
+struct RecordingGuard;
+
+impl Drop for RecordingGuard {
+    fn drop(&mut self) {
+        let _ = RECORDINGS.try_with(|recordings| recordings.borrow_mut().pop());
+    }
+}
+
+fn start_recording(recording: RecordedStats) -> RecordingGuard {
+    RECORDING_USED.store(true, Ordering::Relaxed);
+    RECORDINGS.with(|recordings| recordings.borrow_mut().push(recording));
+    RecordingGuard
+}
+
+/// Record all stat operations made on the current thread during the
+/// closure's execution. Recordings can be nested, in which case the
+/// operations are recorded by all of them.
+pub fn with_recorded_stats<T>(f: impl FnOnce() -> T) -> (T, RecordedStats) {
+    let recording = RecordedStats::default();
+    let res = {
+        let _guard = start_recording(recording.clone());
+        f()
+    };
+    (res, recording)
+}
+
+/// Record all stat operations made while polling the future. Recordings can
+/// be nested, in which case the operations are recorded by all of them.
+pub fn with_recorded_stats_async<Out, Fut: Future<Output = Out> + Unpin>(
+    mut fut: Fut,
+) -> impl Future<Output = (Out, RecordedStats)> {
+    let recording = RecordedStats::default();
+    poll_fn(move |cx| {
+        let res = {
+            let _guard = start_recording(recording.clone());
+            fut.poll_unpin(cx)
+        };
+        res.map(|out| (out, recording.clone()))
+    })
+}
+
+/// [StatsManager] that wraps every stat it creates with [Recorded].
+pub(crate) struct RecordingStatsManager(BoxStatsManager);
+
+impl RecordingStatsManager {
+    pub(crate) fn new(manager: BoxStatsManager) -> Self {
+        Self(manager)
+    }
+}
+
+impl StatsManager for RecordingStatsManager {
+    fn aggregate(&self) {
+        self.0.aggregate()
+    }
+
+    fn create_counter(&self, name: &str) -> BoxLocalCounter {
+        Box::new(Recorded::new(name, self.0.create_counter(name)))
+    }
+
+    fn create_timeseries(
+        &self,
+        name: &str,
+        aggregation_types: &[AggregationType],
+        intervals: &[Duration],
+    ) -> BoxLocalTimeseries {
+        let timeseries = self.0.create_timeseries(name, aggregation_types, intervals);
+        Box::new(Recorded::new(name, timeseries))
+    }
+
+    fn create_histogram(
+        &self,
+        name: &str,
+        aggregation_types: &[AggregationType],
+        conf: BucketConfig,
+        percentiles: &[u8],
+    ) -> BoxLocalHistogram {
+        let histogram = self
+            .0
+            .create_histogram(name, aggregation_types, conf, percentiles);
+        Box::new(Recorded::new(name, histogram))
+    }
+
+    fn create_quantile_stat(
+        &self,
+        name: &str,
+        aggregation_types: &[AggregationType],
+        percentiles: &[f32],
+        intervals: &[Duration],
+    ) -> BoxHistogram {
+        let quantile_stat =
+            self.0
+                .create_quantile_stat(name, aggregation_types, percentiles, intervals);
+        Box::new(Recorded::new(name, quantile_stat))
+    }
+}
+
+/// Stat that records the operations made on it before forwarding them.
+pub(crate) struct Recorded<T> {
+    key: String,
+    inner: T,
+}
+
+impl<T> Recorded<T> {
+    pub(crate) fn new(key: &str, inner: T) -> Self {
+        Self {
+            key: key.to_owned(),
+            inner,
+        }
+    }
+}
+
+impl<T: Counter> Counter for Recorded<T> {
+    fn increment_value(&self, value: i64) {
+        record(&self.key, StatOp::IncrementValue(value));
+        self.inner.increment_value(value);
+    }
+}
+
+impl<T: Timeseries> Timeseries for Recorded<T> {
+    fn add_value(&self, value: i64) {
+        record(&self.key, StatOp::AddValue(value));
+        self.inner.add_value(value);
+    }
+
+    fn add_value_aggregated(&self, value: i64, nsamples: u32) {
+        record(&self.key, StatOp::AddValueAggregated { value, nsamples });
+        self.inner.add_value_aggregated(value, nsamples);
+    }
+}
+
+impl<T: Histogram> Histogram for Recorded<T> {
+    fn add_value(&self, value: i64) {
+        record(&self.key, StatOp::AddValue(value));
+        self.inner.add_value(value);
+    }
+
+    fn add_repeated_value(&self, value: i64, nsamples: u32) {
+        record(&self.key, StatOp::AddRepeatedValue { value, nsamples });
+        self.inner.add_repeated_value(value, nsamples);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+impl<T: SingletonCounter> SingletonCounter for Recorded<T> {
+    fn set_value(&self, fb: FacebookInit, value: i64) {
+        record(&self.key, StatOp::SetValue(value));
+        self.inner.set_value(fb, value);
+    }
+
+    fn increment_value(&self, fb: FacebookInit, value: i64) {
+        record(&self.key, StatOp::IncrementValue(value));
+        self.inner.increment_value(fb, value);
+    }
+
+    fn get_value(&self, fb: FacebookInit) -> Option<i64> {
+        self.inner.get_value(fb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::define_stats;
+    use crate::prelude::*;
+
+    define_stats! {
+        prefix = "recording.test";
+        counter: counter(),
+        gauge: singleton_counter(),
+        requests: timeseries(Sum),
+        latency: histogram(10, 0, 100, Average; P 50),
+        quantiles: quantile_stat(Average; P 50; Duration::from_secs(60)),
+        per_region: dynamic_timeseries("requests.{}", (region: &'static str); Sum),
+    }
+
+    #[fbinit::test]
+    fn test_with_recorded_stats(fb: FacebookInit) {
+        // Stats that were already used outside of a recording are recorded too.
+        STATS::counter.increment_value(100);
+
+        let ((), recorded) = with_recorded_stats(|| {
+            STATS::counter.increment_value(1);
+            STATS::counter.increment_value(2);
+            STATS::gauge.set_value(fb, 10);
+            STATS::gauge.increment_value(fb, -1);
+            STATS::requests.add_value(5);
+            STATS::requests.add_value_aggregated(20, 4);
+            STATS::latency.add_value(7);
+            STATS::latency.add_repeated_value(3, 2);
+            STATS::quantiles.add_value(42);
+            STATS::per_region.add_value(1, ("east",));
+        });
+
+        assert_eq!(
+            recorded.keys(),
+            vec![
+                "recording.test.counter",
+                "recording.test.gauge",
+                "recording.test.latency",
+                "recording.test.quantiles",
+                "recording.test.requests",
+                "recording.test.requests.east",
+            ]
+        );
+        assert_eq!(recorded.total("recording.test.counter"), 3);
+        assert_eq!(recorded.count("recording.test.counter"), 2);
+        assert_eq!(recorded.total("recording.test.gauge"), 9);
+        assert_eq!(recorded.total("recording.test.requests"), 25);
+        assert_eq!(recorded.count("recording.test.requests"), 5);
+        assert_eq!(
+            recorded.ops("recording.test.requests"),
+            vec![
+                StatOp::AddValue(5),
+                StatOp::AddValueAggregated {
+                    value: 20,
+                    nsamples: 4
+                }
+            ]
+        );
+        assert_eq!(
+            recorded.histogram_samples("recording.test.latency"),
+            vec![7, 3, 3]
+        );
+        assert_eq!(
+            recorded.histogram_samples("recording.test.quantiles"),
+            vec![42]
+        );
+        assert_eq!(recorded.total("recording.test.requests.east"), 1);
+        assert_eq!(recorded.ops("missing"), vec![]);
+    }
+
+    #[test]
+    fn test_nested_recordings() {
+        let ((), outer) = with_recorded_stats(|| {
+            STATS::counter.increment_value(1);
+            let ((), inner) = with_recorded_stats(|| STATS::counter.increment_value(2));
+            assert_eq!(inner.total("recording.test.counter"), 2);
+        });
+        assert_eq!(outer.total("recording.test.counter"), 3);
+    }
+
+    #[test]
+    fn test_recording_stops_on_panic() {
+        let recording = RecordedStats::default();
+        let res = std::panic::catch_unwind(|| {
+            let _guard = start_recording(recording.clone());
+            panic!("stat update failed");
+        });
+        assert!(res.is_err());
+
+        // Stats used after the panic are not recorded into the recording.
+        STATS::counter.increment_value(1);
+        assert_eq!(recording.keys(), Vec::<String>::new());
+        RECORDINGS.with(|recordings| assert!(recordings.borrow().is_empty()));
+    }
+
+    #[test]
+    fn test_other_threads_are_not_recorded() {
+        let ((), recorded) = with_recorded_stats(|| {
+            std::thread::spawn(|| STATS::counter.increment_value(1))
+                .join()
+                .unwrap();
+        });
+        assert_eq!(recorded.keys(), Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn test_with_recorded_stats_async() {
+        let (res, recorded) = with_recorded_stats_async(
+            async {
+                STATS::requests.add_value(1);
+                tokio::task::yield_now().await;
+                STATS::requests.add_value(2);
+                3
+            }
+            .boxed(),
+        )
+        .await;
+        assert_eq!(res, 3);
+        assert_eq!(recorded.total("recording.test.requests"), 3);
+
+        // Polling outside of the future doesn't record anything.
+        STATS::requests.add_value(5);
+        assert_eq!(recorded.count("recording.test.requests"), 2);
+    }
+}
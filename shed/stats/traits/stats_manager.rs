@@ -23,7 +23,7 @@ pub trait StatsManagerFactory {
 
 pub type BoxStatsManager = Box<dyn StatsManager + Send + Sync>;
 
-#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub enum AggregationType {
     Sum,
     Count,
@@ -32,6 +32,7 @@ pub enum AggregationType {
     Percent,
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct BucketConfig {
     pub width: u32,
     pub min: u32,